@@ -0,0 +1,140 @@
+// Runs a ROM without the UI and as fast as possible, to measure how quick the emulator is.
+// Started with --benchmark, the results get printed to stdout.
+
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::gameboy::{EmulatorMode, Gameboy, JoypadHandler, DMG_CLOCK_HZ};
+use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::memory::cart;
+
+const DEFAULT_SECONDS: u64 = 10;
+
+pub struct BenchmarkResult {
+    elapsed: Duration,
+    cycles: usize,
+    // Real time it took to emulate each frame.
+    frame_times: Vec<Duration>,
+    // Set if the emulator stopped before the time was up, like on an unknown opcode.
+    stopped_early: Option<String>
+}
+
+impl BenchmarkResult {
+    /// Emulated cycles per real second, in MHz.
+    pub fn mhz(&self) -> f64 {
+        self.cycles as f64 / self.elapsed.as_secs_f64() / 1_000_000.0
+    }
+
+    /// How many times faster than a real DMG it ran.
+    pub fn speed(&self) -> f64 {
+        self.cycles as f64 / self.elapsed.as_secs_f64() / DMG_CLOCK_HZ as f64
+    }
+
+    pub fn print(&self) {
+        println!("Ran {} cycles in {:.2} seconds.", self.cycles, self.elapsed.as_secs_f64());
+        println!("Emulated clock: {:.2} MHz ({:.2}x a DMG).", self.mhz(), self.speed());
+
+        if let (Some(min), Some(max)) = (self.frame_times.iter().min(), self.frame_times.iter().max()) {
+            let total: Duration = self.frame_times.iter().sum();
+            let avg = total / self.frame_times.len() as u32;
+
+            println!(
+                "Frames: {}, frame time min {:.3} ms, max {:.3} ms, avg {:.3} ms.",
+                self.frame_times.len(),
+                min.as_secs_f64() * 1000.0,
+                max.as_secs_f64() * 1000.0,
+                avg.as_secs_f64() * 1000.0
+            );
+        }
+        else {
+            println!("No frames were finished, is the LCD turned off?");
+        }
+
+        if let Some(reason) = self.stopped_early.as_ref() {
+            println!("Stopped early: {}.", reason);
+        }
+    }
+}
+
+/// Runs the ROM from power-on for the given amount of real time.
+/// There's no bootrom and no frame limiter, and the screen is still drawn every frame.
+pub fn run(rom: Vec<u8>, duration: Duration) -> Result<BenchmarkResult, String> {
+    cart::check_rom(&rom)?;
+
+    let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+    let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+    let mut gb = Gameboy::init(gb_mem);
+
+    gb.dbg_mode = EmulatorMode::Running;
+
+    let start = Instant::now();
+    let start_cycles = gb.gb_get_cycles();
+
+    let mut frame_times = Vec::new();
+    let mut frame_start = start;
+    let mut stopped_early = None;
+
+    while start.elapsed() < duration {
+        // Checking the time every instruction costs more than the instruction itself.
+        for _ in 0..1000 {
+            gb.gb_cpu_cycle();
+
+            if gb.gb_ppu_cycle() {
+                let now = Instant::now();
+
+                frame_times.push(now - frame_start);
+                frame_start = now;
+            }
+
+            if gb.dbg_mode != EmulatorMode::Running {
+                break;
+            }
+        }
+
+        if gb.dbg_mode != EmulatorMode::Running {
+            stopped_early = Some(gb.dbg_mode.to_string());
+            break;
+        }
+    }
+
+    Ok(BenchmarkResult {
+        elapsed: start.elapsed(),
+        cycles: gb.gb_get_cycles() - start_cycles,
+        frame_times,
+        stopped_early
+    })
+}
+
+pub fn run_from_args(args: &[String]) {
+    let path = match args.get(0) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: rusty-boy --benchmark <ROM> [seconds]");
+            return;
+        }
+    };
+
+    let seconds = match args.get(1).map(|s| s.parse::<u64>()) {
+        Some(Ok(seconds)) => seconds,
+        Some(Err(_)) => {
+            eprintln!("The duration has to be a whole number of seconds.");
+            return;
+        }
+        None => DEFAULT_SECONDS
+    };
+
+    let rom = match std::fs::read(path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Couldn't read {} ({}).", path, error);
+            return;
+        }
+    };
+
+    println!("Benchmarking {} for {} seconds...", path, seconds);
+
+    match run(rom, Duration::from_secs(seconds)) {
+        Ok(result) => result.print(),
+        Err(error) => eprintln!("{} can't be loaded as a Game Boy ROM: {}.", path, error)
+    }
+}
@@ -0,0 +1,138 @@
+// The calls the CPU is currently inside of, for the debugger.
+// Games don't always return the way they called: some pop the return address
+// and jump, some push an address and RET to it, some reset SP. Each frame keeps
+// where its return address was pushed, so returns that don't match can be spotted.
+
+use std::collections::VecDeque;
+
+use crate::log;
+
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+pub struct CallFrame {
+    description: String,
+    return_address: u16,
+    // SP right after pushing the return address.
+    sp: u16
+}
+
+impl CallFrame {
+    /// Get what made the call, like `$0150: CALL 2000`.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Get the address the call should return to.
+    pub fn return_address(&self) -> u16 {
+        self.return_address
+    }
+
+    /// Get where the return address was pushed.
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+}
+
+pub struct Callstack {
+    frames: VecDeque<CallFrame>,
+    // Set when a return didn't match the frame on top, until the stack empties out again.
+    mismatch: Option<String>,
+
+    // Once there are this many frames, the oldest ones get dropped and only counted.
+    // Real games rarely go past a few dozen, so hitting it usually means runaway recursion.
+    max_depth: usize,
+    dropped: usize
+}
+
+impl Default for Callstack {
+    fn default() -> Callstack {
+        Callstack {
+            frames: VecDeque::new(),
+            mismatch: None,
+
+            max_depth: DEFAULT_MAX_DEPTH,
+            dropped: 0
+        }
+    }
+}
+
+impl Callstack {
+    /// Oldest call first.
+    pub fn frames(&self) -> &VecDeque<CallFrame> {
+        &self.frames
+    }
+
+    /// Get how many of the oldest frames were dropped to stay under the maximum depth.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.max(1);
+
+        while self.frames.len() > self.max_depth {
+            self.frames.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Describes the last return that didn't match the calls, if the frames might be wrong because of it.
+    pub fn mismatch(&self) -> Option<&str> {
+        self.mismatch.as_deref()
+    }
+
+    pub fn push(&mut self, description: String, return_address: u16, sp: u16) {
+        if self.frames.len() >= self.max_depth {
+            if self.dropped == 0 {
+                log::warn("CPU", &format!("The callstack went over {} calls at {}, is something recursing forever?", self.max_depth, description));
+            }
+
+            self.frames.pop_front();
+            self.dropped += 1;
+        }
+
+        self.frames.push_back(CallFrame { description, return_address, sp });
+    }
+
+    /// Called on every return, with the address it went to and where it was read from.
+    pub fn pop(&mut self, pc: u16, address: u16, sp: u16) {
+        let matching = self.frames.iter().rposition(|frame| frame.sp == sp && frame.return_address == address);
+
+        match matching {
+            Some(idx) if idx + 1 == self.frames.len() => {
+                self.frames.pop_back();
+
+                // Back to where things started, so nothing left can be wrong.
+                if self.frames.is_empty() && self.dropped == 0 {
+                    self.mismatch = None;
+                }
+            }
+            Some(idx) => {
+                let skipped = self.frames.len() - idx - 1;
+
+                self.frames.truncate(idx);
+                self.mismatch = Some(format!("${:04X}: returned to ${:04X}, skipping {} calls", pc, address, skipped));
+            }
+            // Returning into one of the dropped frames, which can't be checked anymore.
+            None if self.frames.is_empty() && self.dropped > 0 => {
+                self.dropped -= 1;
+            }
+            None => {
+                // The return address was read from where the last call left it, but was changed since.
+                if self.frames.back().map(|frame| frame.sp == sp).unwrap_or(false) {
+                    self.frames.pop_back();
+                    self.mismatch = Some(format!("${:04X}: return address changed to ${:04X}", pc, address));
+                }
+                else {
+                    self.mismatch = Some(format!("${:04X}: returned to ${:04X} without a matching call, stack manipulated", pc, address));
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.mismatch = None;
+        self.dropped = 0;
+    }
+}
@@ -1,6 +1,7 @@
 use std::sync::{Arc, RwLock};
 
 use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::state::{StateReader, StateWriter};
 
 const VBLANK_BIT: u8 = 0x01;
 const STAT_BIT: u8 = 0x02;
@@ -8,6 +9,48 @@ const TIMER_BIT: u8 = 0x04;
 const SERIAL_BIT: u8 = 0x08;
 const JOYPAD_BIT: u8 = 0x10;
 
+// IE is a full 8-bit register and IF's top bits read as 1,
+// but only the five bits above can ever trigger an interrupt.
+const INTERRUPT_MASK: u8 = VBLANK_BIT | STAT_BIT | TIMER_BIT | SERIAL_BIT | JOYPAD_BIT;
+
+// Bit and vector of each interrupt, from the highest priority to the lowest.
+const INTERRUPT_PRIORITY: [(u8, u16); 5] = [
+    (VBLANK_BIT, 0x40),
+    (STAT_BIT, 0x48),
+    (TIMER_BIT, 0x50),
+    (SERIAL_BIT, 0x58),
+    (JOYPAD_BIT, 0x60)
+];
+
+/// Name of the interrupt that jumps to the vector, for the debugger.
+pub fn interrupt_name(vector: u16) -> &'static str {
+    match vector {
+        0x40 => "VBlank",
+        0x48 => "STAT",
+        0x50 => "Timer",
+        0x58 => "Serial",
+        0x60 => "Joypad",
+        _ => "Unknown"
+    }
+}
+
+// The handler's part of a save state, as written by InterruptHandler::save_state().
+pub struct SavedInterrupts {
+    pub ime: bool,
+    pub ei_executed: bool,
+    pub instructions_since_ei: u8
+}
+
+impl SavedInterrupts {
+    pub fn read(state: &mut StateReader) -> Option<SavedInterrupts> {
+        Some(SavedInterrupts {
+            ime: state.read_bool()?,
+            ei_executed: state.read_bool()?,
+            instructions_since_ei: state.read_u8()?
+        })
+    }
+}
+
 pub struct InterruptHandler {
     ime: bool,
 
@@ -61,67 +104,19 @@ impl InterruptHandler {
         }
 
         if self.ime {
-            let if_value = self.read(0xFF0F);
-            let ie_value = self.read(0xFFFF);
+            let if_value = self.read(0xFF0F) & INTERRUPT_MASK;
+            let ie_value = self.read(0xFFFF) & INTERRUPT_MASK;
 
-            if if_value & VBLANK_BIT != 0 {
-                requested = true;
-
-                if ie_value & VBLANK_BIT != 0 {
-                    let new_if = if_value & !VBLANK_BIT;
+            requested = if_value & ie_value != 0;
 
+            // The highest priority interrupt that's both requested and enabled gets serviced,
+            // and only its bit is cleared, so the rest stay pending until the handler returns.
+            for (bit, vector) in INTERRUPT_PRIORITY.iter() {
+                if if_value & ie_value & bit != 0 {
                     self.ime = false;
-                    self.write(0xFF0F, new_if);
+                    self.write(0xFF0F, if_value & !bit);
 
-                    return (requested, Some(0x40));
-                }
-            }
-            else if if_value & STAT_BIT != 0 {
-                requested = true;
-
-                if ie_value & STAT_BIT != 0 {
-                    let new_if = if_value & !STAT_BIT;
-
-                    self.ime = false;
-                    self.write(0xFF0F, new_if);
-    
-                    return (requested, Some(0x48))
-                }
-            }
-            else if if_value & TIMER_BIT != 0 {
-                requested = true;
-
-                if ie_value & TIMER_BIT != 0 {
-                    let new_if = if_value & !TIMER_BIT;
-
-                    self.ime = false;
-                    self.write(0xFF0F, new_if);
-    
-                    return (requested, Some(0x50))
-                }
-            }
-            else if if_value & SERIAL_BIT != 0 {
-                requested = true;
-
-                if ie_value & SERIAL_BIT != 0 {
-                    let new_if = if_value & !SERIAL_BIT;
-
-                    self.ime = false;
-                    self.write(0xFF0F, new_if);
-    
-                    return (requested, Some(0x58));
-                }
-            }
-            else if if_value & JOYPAD_BIT != 0 {
-                requested = true;
-                
-                if ie_value & JOYPAD_BIT != 0 {
-                    let new_if = if_value & !JOYPAD_BIT;
-
-                    self.ime = false;
-                    self.write(0xFF0F, new_if);
-    
-                    return (requested, Some(0x60));
+                    return (requested, Some(*vector));
                 }
             }
         }
@@ -129,6 +124,16 @@ impl InterruptHandler {
         (requested, None)
     }
 
+    /// Whether IME is set, or will be after a pending EI.
+    pub fn ime_enabled(&self) -> bool {
+        self.ime || self.ei_executed
+    }
+
+    /// Whether an enabled interrupt is requested, regardless of IME.
+    pub fn pending(&self) -> bool {
+        self.read(0xFF0F) & self.read(0xFFFF) & INTERRUPT_MASK != 0
+    }
+
     pub fn enable_interrupts(&mut self, ei: bool) {
         if ei {
             self.ei_executed = true;
@@ -144,4 +149,86 @@ impl InterruptHandler {
         self.ei_executed = false;
         self.instructions_since_ei = 0;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ime);
+        state.write_bool(self.ei_executed);
+        state.write_u8(self.instructions_since_ei);
+    }
+
+    pub fn load_state(&mut self, saved: &SavedInterrupts) {
+        self.ime = saved.ime;
+        self.ei_executed = saved.ei_executed;
+        self.instructions_since_ei = saved.instructions_since_ei;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::JoypadHandler;
+
+    fn handler_with_ime() -> InterruptHandler {
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), vec![0; 0x8000], gb_joy)));
+
+        let mut handler = InterruptHandler::init(gb_mem);
+        handler.enable_interrupts(false);
+        handler
+    }
+
+    #[test]
+    fn unused_bits_never_trigger() {
+        let mut handler = handler_with_ime();
+
+        handler.write(0xFFFF, 0xFF);
+        handler.write(0xFF0F, 0xE0);
+        assert!(!handler.pending());
+        assert_eq!(handler.check_interrupts(), (false, None));
+
+        handler.write(0xFFFF, 0xE0);
+        handler.write(0xFF0F, 0xFF);
+        assert!(!handler.pending());
+        assert_eq!(handler.check_interrupts(), (false, None));
+
+        // With both at $FF, only VBlank's bit gets cleared.
+        handler.write(0xFFFF, 0xFF);
+        assert_eq!(handler.check_interrupts(), (true, Some(0x40)));
+        assert_eq!(handler.read(0xFF0F), 0xFE);
+    }
+
+    #[test]
+    fn highest_priority_is_serviced_first() {
+        let mut handler = handler_with_ime();
+
+        // Timer, serial and joypad requested, but serial isn't enabled.
+        handler.write(0xFFFF, 0x14);
+        handler.write(0xFF0F, 0x1C);
+
+        assert_eq!(handler.check_interrupts(), (true, Some(0x50)));
+        assert_eq!(handler.read(0xFF0F) & INTERRUPT_MASK, 0x18);
+
+        // IME is off until the handler returns, so nothing else gets serviced.
+        assert_eq!(handler.check_interrupts(), (false, None));
+
+        handler.enable_interrupts(false);
+        assert_eq!(handler.check_interrupts(), (true, Some(0x60)));
+        assert_eq!(handler.read(0xFF0F) & INTERRUPT_MASK, 0x08);
+    }
+
+    #[test]
+    fn vblank_wins_over_everything_else() {
+        let mut handler = handler_with_ime();
+
+        handler.write(0xFFFF, 0x1F);
+        handler.write(0xFF0F, 0x1F);
+
+        // Each one gets serviced in order, the rest stay pending until then.
+        for (vector, pending) in [(0x40, 0x1E), (0x48, 0x1C), (0x50, 0x18), (0x58, 0x10), (0x60, 0x00)].iter() {
+            assert_eq!(handler.check_interrupts(), (true, Some(*vector)));
+            assert_eq!(handler.read(0xFF0F) & INTERRUPT_MASK, *pending);
+
+            handler.enable_interrupts(false);
+        }
+    }
 }
@@ -1,12 +1,17 @@
 mod interrupts;
 
 use std::fmt;
+use std::collections::HashSet;
 use std::sync::{Arc, RwLock};
 
-use interrupts::InterruptHandler;
+use interrupts::{interrupt_name, InterruptHandler, SavedInterrupts};
 
 use super::*;
-use crate::gameboy::memory::dma::DmaTransfer;
+use crate::gameboy::callstack::Callstack;
+use crate::gameboy::memory::dma::{DmaLog, DmaTransfer};
+use crate::gameboy::memory::regions::{HRAM, IO};
+use crate::gameboy::state::{StateReader, StateWriter};
+use crate::log;
 
 #[derive(Clone, Copy)]
 enum Condition {
@@ -52,6 +57,44 @@ enum Flag {
     Carry(bool)
 }
 
+// The CPU's part of a save state, as written by GameboyCPU::save_state().
+// StateSnapshot reads it too, to look into states without loading them.
+pub struct SavedCpu {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+
+    pub halted: bool,
+    pub halt_bug: bool,
+    pub stopped: bool,
+    pub div_cycles: usize,
+
+    pub interrupts: SavedInterrupts
+}
+
+impl SavedCpu {
+    pub fn read(state: &mut StateReader) -> Option<SavedCpu> {
+        Some(SavedCpu {
+            af: state.read_u16()?,
+            bc: state.read_u16()?,
+            de: state.read_u16()?,
+            hl: state.read_u16()?,
+            sp: state.read_u16()?,
+            pc: state.read_u16()?,
+
+            halted: state.read_bool()?,
+            halt_bug: state.read_bool()?,
+            stopped: state.read_bool()?,
+            div_cycles: state.read_usize()?,
+
+            interrupts: SavedInterrupts::read(state)?
+        })
+    }
+}
+
 pub struct GameboyCPU {
     af: u16,
     bc: u16,
@@ -62,13 +105,23 @@ pub struct GameboyCPU {
     pc: u16,
 
     halted: bool,
+    // Set by a HALT that didn't halt, the next opcode gets fetched without moving PC past it.
+    halt_bug: bool,
     stopped: bool,
+    model: Model,
+    // Where the opcode run by the last cpu_cycle() was, None if it didn't get to run one.
+    executed_pc: Option<u16>,
+
+    // Runs unknown opcodes as NOPs instead of stopping, reporting each address once.
+    skip_unknown_opcodes: bool,
+    skipped_opcodes: HashSet<u16>,
 
     gb_cyc: Arc<RwLock<usize>>,
     div_cycles: usize,
-    callstack: Arc<RwLock<Vec<String>>>,
+    callstack: Arc<RwLock<Callstack>>,
 
     dma_transfer: Option<DmaTransfer>,
+    dma_log: Arc<RwLock<DmaLog>>,
 
     gb_mem: Arc<RwLock<GameboyMemory>>,
     interrupt_handler: InterruptHandler
@@ -88,13 +141,20 @@ impl GameboyCPU {
             pc: 0,
 
             halted: false,
+            halt_bug: false,
             stopped: false,
+            model: Model::Dmg,
+            executed_pc: None,
+
+            skip_unknown_opcodes: false,
+            skipped_opcodes: HashSet::new(),
 
             gb_cyc,
             div_cycles: 0,
-            callstack: Arc::new(RwLock::new(Vec::new())),
+            callstack: Arc::new(RwLock::new(Callstack::default())),
 
             dma_transfer: None,
+            dma_log: Arc::new(RwLock::new(DmaLog::default())),
 
             gb_mem,
             interrupt_handler
@@ -250,10 +310,23 @@ impl GameboyCPU {
         }
     }
 
-    pub fn get_callstack(&self) -> Arc<RwLock<Vec<String>>> {
+    pub fn get_callstack(&self) -> Arc<RwLock<Callstack>> {
         self.callstack.clone()
     }
 
+    pub fn get_dma_log(&self) -> Arc<RwLock<DmaLog>> {
+        self.dma_log.clone()
+    }
+
+    // Only HRAM can be reached while OAM DMA runs, anything else gets counted on the DMA log.
+    fn check_dma_access(&self, address: u16) {
+        if self.dma_transfer.is_some() && !HRAM.contains(&address) {
+            if let Ok(mut lock) = self.dma_log.write() {
+                lock.record_blocked_access();
+            }
+        }
+    }
+
     pub fn get_all_registers(&self) -> (u16, u16, u16, u16, u16, u16) {
         (self.af, self.bc, self.de, self.hl, self.sp, self.pc)
     }
@@ -271,8 +344,11 @@ impl GameboyCPU {
             }
         }
 
+        self.check_dma_access(address);
+
         let value = {
             if let Ok(lock) = self.gb_mem.read() {
+                lock.access_stats().record_read(address);
                 lock.read(address)
             }
             else {
@@ -295,8 +371,12 @@ impl GameboyCPU {
             }
         }
 
+        self.check_dma_access(address);
+
         let result = {
             if let Ok(lock) = self.gb_mem.read() {
+                lock.access_stats().record_read(address);
+                lock.access_stats().record_read(address + 1);
                 u16::from_le_bytes([lock.read(address), lock.read(address + 1)])
             }
             else {
@@ -317,12 +397,27 @@ impl GameboyCPU {
             }
         }
 
+        self.check_dma_access(address);
+
         if address == 0xFF46 {
-            let transfer = DmaTransfer::new(value, *self.gb_cyc.read().unwrap(), self.gb_mem.clone());
+            let cycles = *self.gb_cyc.read().unwrap();
+            let transfer = DmaTransfer::new(value, cycles, self.gb_mem.clone());
             self.dma_transfer = Some(transfer);
+
+            if let Ok(mut lock) = self.dma_log.write() {
+                lock.start(self.pc, value, cycles);
+            }
         }
 
         if let Ok(mut lock) = self.gb_mem.write() {
+            lock.set_cpu_pc(self.pc);
+
+            // Only the CPU's writes are logged, the IF updates done by the PPU and timer would drown them out.
+            if IO.contains(&address) {
+                lock.io_write_log_mut().record(self.pc, address, value);
+            }
+
+            lock.access_stats().record_write(address);
             lock.write(address, value);
         }
         
@@ -341,8 +436,12 @@ impl GameboyCPU {
             }
         }
 
+        self.check_dma_access(self.sp);
+
         let result = {
             if let Ok(lock) = self.gb_mem.read() {
+                lock.access_stats().record_read(self.sp);
+                lock.access_stats().record_read(self.sp + 1);
                 u16::from_le_bytes([lock.read(self.sp), lock.read(self.sp + 1)])
             }
             else {
@@ -379,13 +478,109 @@ impl GameboyCPU {
         self.hl = 0;
         self.sp = 0;
         self.pc = 0;
+
+        self.halted = false;
+        self.halt_bug = false;
+        self.stopped = false;
+        self.div_cycles = 0;
+        self.dma_transfer = None;
+        self.interrupt_handler.disable_interrupts();
         
         if let Ok(mut lock) = self.callstack.write() {
             lock.clear();
         }
     }
 
+    pub fn set_skip_unknown_opcodes(&mut self, skip: bool) {
+        self.skip_unknown_opcodes = skip;
+    }
+
+    fn skip_unknown_opcode(&mut self, opcode: u8) {
+        if self.skipped_opcodes.insert(self.pc) {
+            log::warn("CPU", &format!("Skipped unknown opcode ${:02X} at ${:04X}.", opcode, self.pc));
+        }
+
+        // The disassembler knows how long most of them are, even if they can't run.
+        let (len, _) = disassembler::get_instruction_data(self.pc, &self.gb_mem);
+
+        self.pc = self.pc.wrapping_add(len.max(1));
+        *self.gb_cyc.write().unwrap() += 4;
+    }
+
+    /// Address of the instruction the last cycle ran. After an interrupt dispatch it's the
+    /// handler's, and it's None for cycles spent halted or stopped by a breakpoint.
+    pub fn executed_pc(&self) -> Option<u16> {
+        self.executed_pc
+    }
+
+    /// Whether the CPU is waiting for an interrupt after a HALT or STOP.
+    pub fn is_halted(&self) -> bool {
+        self.halted || self.stopped
+    }
+
+    /// Takes effect on the next reset.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    // Sets the registers to the values the bootrom leaves behind.
+    // Games tell the models apart by the value of A.
+    pub fn skip_bootrom(&mut self) {
+        let (af, bc, de, hl) = match self.model {
+            Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D)
+        };
+
+        self.af = af;
+        self.bc = bc;
+        self.de = de;
+        self.hl = hl;
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+
+    // An OAM DMA in progress isn't saved, it's short enough to not matter.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for reg in [self.af, self.bc, self.de, self.hl, self.sp, self.pc].iter() {
+            state.write_u16(*reg);
+        }
+
+        state.write_bool(self.halted);
+        state.write_bool(self.halt_bug);
+        state.write_bool(self.stopped);
+        state.write_usize(self.div_cycles);
+
+        self.interrupt_handler.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        let saved = SavedCpu::read(state)?;
+
+        self.af = saved.af;
+        self.bc = saved.bc;
+        self.de = saved.de;
+        self.hl = saved.hl;
+        self.sp = saved.sp;
+        self.pc = saved.pc;
+
+        self.halted = saved.halted;
+        self.halt_bug = saved.halt_bug;
+        self.stopped = saved.stopped;
+        self.div_cycles = saved.div_cycles;
+        self.dma_transfer = None;
+
+        if let Ok(mut lock) = self.callstack.write() {
+            lock.clear();
+        }
+
+        self.interrupt_handler.load_state(&saved.interrupts);
+        Some(())
+    }
+
     pub fn cpu_cycle(&mut self, breakpoints: &[Breakpoint], dbg_mode: &mut EmulatorMode) {
+        self.executed_pc = None;
+
         for bp in breakpoints {
             if self.pc == *bp.address() && *bp.execute() && *dbg_mode != EmulatorMode::Stepping {
                 *dbg_mode = EmulatorMode::BreakpointHit;
@@ -399,7 +594,7 @@ impl GameboyCPU {
 
     fn increase_div(&mut self) {
         if let Ok(cycles) = self.gb_cyc.read() {
-            if *cycles > self.div_cycles {
+            if *cycles >= self.div_cycles {
                 let elapsed = *cycles - self.div_cycles;
     
                 if elapsed >= 256 {
@@ -408,16 +603,28 @@ impl GameboyCPU {
                         let div_value = div.get().wrapping_add(1);
     
                         div.set(div_value);
-                        self.div_cycles = *cycles;
+                        self.div_cycles += 256;
                     }
                 }
             }
             else {
-                self.div_cycles = 0;
+                // The clock went back to 0 after a reset.
+                self.div_cycles = *cycles;
             }
         }
     }
 
+    /// Clears DIV and restarts the count to its next increment, for writes to DIV and STOP.
+    pub fn reset_divider(&mut self) {
+        if let Ok(lock) = self.gb_mem.read() {
+            lock.get_io_reg(0xFF04).set(0);
+        }
+
+        if let Ok(cycles) = self.gb_cyc.read() {
+            self.div_cycles = *cycles;
+        }
+    }
+
     fn execute_instruction(&mut self, breakpoints: &[Breakpoint], dbg_mode: &mut EmulatorMode) {
         let (int_requested, int_address) = self.interrupt_handler.check_interrupts();
 
@@ -428,14 +635,34 @@ impl GameboyCPU {
                     *dbg_mode = EmulatorMode::BreakpointHit;
                     return;
                 }
-    
+
+                // Shows that the code is running inside a handler, RETI pops it like any other return.
+                if let Ok(mut lock) = self.callstack.write() {
+                    lock.push(format!("${:04X}: INT ${:02X} ({})", self.pc, int, interrupt_name(int)), self.pc, self.sp);
+                }
+
+                // Two wait cycles, pushing PC takes two more, and jumping to the vector the last one.
                 self.pc = int;
+                *self.gb_cyc.write().unwrap() += 20;
             }
 
             self.halted = false;
             self.stopped = false;
         }
-        
+
+        // Same for HALT with any enabled interrupt.
+        if self.halted && self.interrupt_handler.pending() {
+            self.halted = false;
+        }
+
+        // A button press ends STOP even with interrupts disabled, it just doesn't jump to the handler then.
+        if self.stopped {
+            let joypad_requested = self.gb_mem.read().map(|lock| lock.read(0xFF0F) & 0x10 != 0).unwrap_or(false);
+
+            if joypad_requested {
+                self.stopped = false;
+            }
+        }
 
         if self.halted || self.stopped {
             // HACK: Since the CPU is stopped, the cycle counter doesn't increase.
@@ -450,8 +677,14 @@ impl GameboyCPU {
         }
 
         if let Some(transfer) = self.dma_transfer.as_mut() {
-            if transfer.step(*self.gb_cyc.read().unwrap()) {
+            let cycles = *self.gb_cyc.read().unwrap();
+
+            if transfer.step(cycles) {
                 self.dma_transfer = None;
+
+                if let Ok(mut lock) = self.dma_log.write() {
+                    lock.finish(cycles);
+                }
             }
         }
 
@@ -462,6 +695,15 @@ impl GameboyCPU {
             return;
         }
 
+        self.executed_pc = Some(self.pc);
+
+        // With the HALT bug the opcode byte gets read again as the first operand, or as the next
+        // opcode for single byte instructions. Moving PC back one makes every instruction do that.
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
         match opcode {
             0x00 => self.nop(),
             0x01 => self.load_u16_to_rp(breakpoints, dbg_mode, Register::BC(false)),
@@ -480,7 +722,7 @@ impl GameboyCPU {
             0x0E => self.load_u8_to_r8(breakpoints, dbg_mode, Register::BC(false)),
             0x0F => self.rrca(),
 
-            // 0x10 => stop(),
+            0x10 => self.stop(),
             0x11 => self.load_u16_to_rp(breakpoints, dbg_mode, Register::DE(false)),
             0x12 => self.store_a_to_rp(breakpoints, dbg_mode, Register::DE(false)),
             0x13 => self.inc_rp(Register::DE(false)),
@@ -735,7 +977,16 @@ impl GameboyCPU {
             0xFE => self.cp_u8(breakpoints, dbg_mode),
             0xFF => self.rst(0x38, breakpoints, dbg_mode),
 
-            _ => *dbg_mode = EmulatorMode::UnknownInstruction(false, opcode)
+            // Not emulator gaps, these hang the CPU for good on real hardware.
+            // Some ROMs only run into them by accident though, so they can be skipped instead.
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                if self.skip_unknown_opcodes {
+                    self.skip_unknown_opcode(opcode);
+                }
+                else {
+                    *dbg_mode = EmulatorMode::Locked(opcode);
+                }
+            }
         }
     }
 
@@ -1192,16 +1443,7 @@ impl GameboyCPU {
             return;
         }
 
-        let sp = self.sp;
-        let value = (value as i8) as u16;
-        let result = sp.wrapping_add(value);
-
-        self.hl = result;
-
-        self.set_flag(Flag::Zero(false));
-        self.set_flag(Flag::Negative(false));
-        self.set_flag(Flag::HalfCarry((sp & 0x0F) + (value & 0x0F) > 0x0F));
-        self.set_flag(Flag::Carry((sp & 0x00FF) + (value & 0x00FF) > 0xFF));
+        self.hl = self.add_sp_offset(value);
 
         self.pc += 2;
         *self.gb_cyc.write().unwrap() += 12;
@@ -1356,28 +1598,50 @@ impl GameboyCPU {
             return;
         }
 
-        let sp = self.sp;
-        let value = (value as i8) as u16;
-        let result = sp.wrapping_add(value);
+        self.sp = self.add_sp_offset(value);
 
-        self.sp = result;
+        self.pc += 2;
+        *self.gb_cyc.write().unwrap() += 16;
+    }
+
+    // Shared by LD HL, SP+i8 and ADD SP, i8. The offset is signed, but the flags come from
+    // adding its raw byte to the low byte of SP, as if it was unsigned. So SP=$FFF8 plus -8 ($F8)
+    // sets both carries, even though the 16-bit result ($FFF0) is smaller than SP.
+    fn add_sp_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.sp;
+        let low = sp as u8;
 
         self.set_flag(Flag::Zero(false));
         self.set_flag(Flag::Negative(false));
-        self.set_flag(Flag::HalfCarry((sp & 0x0F) + (value & 0x0F) > 0x0F));
-        self.set_flag(Flag::Carry((sp & 0x00FF) + (value & 0x00FF) > 0xFF));
+        self.set_flag(Flag::HalfCarry((low & 0x0F) + (offset & 0x0F) > 0x0F));
+        self.set_flag(Flag::Carry(low as u16 + offset as u16 > 0xFF));
 
-        self.pc += 2;
-        *self.gb_cyc.write().unwrap() += 16;
+        sp.wrapping_add(offset as i8 as u16)
     }
 
+    // With IME clear and an interrupt already pending HALT doesn't halt,
+    // and the CPU fails to move PC past the next opcode instead.
     fn halt(&mut self) {
-        self.halted = true;
+        if !self.interrupt_handler.ime_enabled() && self.interrupt_handler.pending() {
+            self.halt_bug = true;
+        }
+        else {
+            self.halted = true;
+        }
 
         self.pc += 1;
         *self.gb_cyc.write().unwrap() += 4;
     }
 
+    // STOP is followed by a padding byte, and resets DIV when it's entered.
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.reset_divider();
+
+        self.pc += 2;
+        *self.gb_cyc.write().unwrap() += 4;
+    }
+
     fn daa(&mut self) {
         let a = self.get_r8(&Register::AF);
         let flag_c = self.get_flag(Flag::Carry(false));
@@ -1406,6 +1670,7 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 4;
     }
 
+    // SCF and CCF clear N and H on every model.
     fn scf(&mut self) {
         self.set_flag(Flag::Negative(false));
         self.set_flag(Flag::HalfCarry(false));
@@ -1482,6 +1747,8 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 8;
     }
 
+    // Shared by INC r8 and INC (HL), so both compute the flags the same way.
+    // Half-carry is set when the low nibble overflows ($xF -> $x0), carry is left untouched.
     fn inc(&mut self, value: u8) -> u8 {
         let result = value.wrapping_add(1);
 
@@ -1519,6 +1786,8 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 12;
     }
 
+    // Shared by DEC r8 and DEC (HL). Half-carry is set when the low nibble
+    // borrows ($x0 -> $(x-1)F), carry is left untouched.
     fn dec(&mut self, value: u8) -> u8 {
         let result = value.wrapping_sub(1);
 
@@ -1668,16 +1937,25 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 8;
     }
 
-    fn sub(&mut self, value: u8) {
+    // Shared by SUB, SBC and CP. Computes A - value - carry and sets the flags,
+    // but leaves A untouched so CP can discard the result.
+    fn subtract(&mut self, value: u8, carry: u8) -> u8 {
         let a = self.get_r8(&Register::AF);
-        let result = a.wrapping_sub(value);
-
-        self.set_r8(Register::AF, result);
+        let result = a.wrapping_sub(value).wrapping_sub(carry);
 
         self.set_flag(Flag::Zero(result == 0));
         self.set_flag(Flag::Negative(true));
-        self.set_flag(Flag::HalfCarry((a & 0x0F) < (value & 0x0F)));
-        self.set_flag(Flag::Carry(value > a));
+        // The carry-in is part of the borrow chain, so the low nibble can borrow
+        // even when both nibbles are equal (a & 0x0F == value & 0x0F, carry = 1).
+        self.set_flag(Flag::HalfCarry((a & 0x0F) < (value & 0x0F) + carry));
+        self.set_flag(Flag::Carry((a as u16) < value as u16 + carry as u16));
+
+        result
+    }
+
+    fn sub(&mut self, value: u8) {
+        let result = self.subtract(value, 0);
+        self.set_r8(Register::AF, result);
     }
 
     fn sub_r8(&mut self, reg: Register) {
@@ -1717,16 +1995,10 @@ impl GameboyCPU {
     }
 
     fn sbc(&mut self, value: u8) {
-        let a = self.get_r8(&Register::AF);
         let carry = if self.get_flag(Flag::Carry(false)) {1} else {0};
-        let result = a as i16 - value as i16 - carry as i16;
+        let result = self.subtract(value, carry);
 
-        self.set_r8(Register::AF, result as u8);
-
-        self.set_flag(Flag::Zero(result as u8 == 0));
-        self.set_flag(Flag::Negative(true));
-        self.set_flag(Flag::HalfCarry(((a & 0x0F) as i16 - (value & 0x0F) as i16 - carry as i16) < 0));
-        self.set_flag(Flag::Carry(result < 0));
+        self.set_r8(Register::AF, result);
     }
 
     fn sbc_r8(&mut self, reg: Register) {
@@ -1910,12 +2182,7 @@ impl GameboyCPU {
     }
 
     fn cp(&mut self, value: u8) {
-        let a = self.get_r8(&Register::AF);
-
-        self.set_flag(Flag::Zero(a == value));
-        self.set_flag(Flag::Negative(true));
-        self.set_flag(Flag::HalfCarry((a & 0x0F) < (value & 0x0F)));
-        self.set_flag(Flag::Carry(a < value));
+        self.subtract(value, 0);
     }
 
     fn cp_r8(&mut self, reg: Register) {
@@ -1968,7 +2235,7 @@ impl GameboyCPU {
         }
 
         if let Ok(mut lock) = self.callstack.write() {
-            lock.push(format!("${:04X}: CALL {:04X}", self.pc, address));
+            lock.push(format!("${:04X}: CALL {:04X}", self.pc, address), self.pc + 3, self.sp);
         }
 
         self.pc = address;
@@ -1990,7 +2257,7 @@ impl GameboyCPU {
             }
 
             if let Ok(mut lock) = self.callstack.write() {
-                lock.push(format!("${:04X}: CALL {}, {:04X}", self.pc, condition, address));
+                lock.push(format!("${:04X}: CALL {}, {:04X}", self.pc, condition, address), self.pc + 3, self.sp);
             }
 
             self.pc = address;
@@ -2010,8 +2277,9 @@ impl GameboyCPU {
             return;
         }
 
+        // The address was read from right below the current SP.
         if let Ok(mut lock) = self.callstack.write() {
-            lock.pop();
+            lock.pop(self.pc, address, self.sp.wrapping_sub(2));
         }
 
         self.pc = address;
@@ -2028,7 +2296,7 @@ impl GameboyCPU {
             }
 
             if let Ok(mut lock) = self.callstack.write() {
-                lock.pop();
+                lock.pop(self.pc, address, self.sp.wrapping_sub(2));
             }
 
             self.pc = address;
@@ -2048,10 +2316,13 @@ impl GameboyCPU {
             return;
         }
 
+        // The address was read from right below the current SP.
         if let Ok(mut lock) = self.callstack.write() {
-            lock.pop();
+            lock.pop(self.pc, address, self.sp.wrapping_sub(2));
         }
 
+        // Unlike EI, RETI enables interrupts right away. An interrupt that's
+        // already pending gets serviced before the next instruction at the return address.
         self.interrupt_handler.enable_interrupts(false);
 
         self.pc = address;
@@ -2136,7 +2407,7 @@ impl GameboyCPU {
         }
 
         if let Ok(mut lock) = self.callstack.write() {
-            lock.push(format!("${:04X}: RST {:04X}", self.pc, address));
+            lock.push(format!("${:04X}: RST {:04X}", self.pc, address), self.pc + 1, self.sp);
         }
 
         self.pc = address;
@@ -2512,6 +2783,7 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 16;
     }
 
+    // Carry is left alone, games rely on it surviving a BIT.
     fn bit(&mut self, value: u8, bit: u8) {
         self.set_flag(Flag::Zero((value >> bit) & 1 == 0));
         self.set_flag(Flag::Negative(false));
@@ -2597,3 +2869,372 @@ impl GameboyCPU {
         *self.gb_cyc.write().unwrap() += 16;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Starts at $0100 with the post-boot registers, like a cartridge running without a bootrom.
+    fn cpu_with_program(program: &[u8]) -> GameboyCPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+        let gb_cyc = Arc::new(RwLock::new(0));
+
+        gb_mem.write().unwrap().skip_bootrom();
+
+        let mut cpu = GameboyCPU::init(gb_cyc, gb_mem);
+        cpu.skip_bootrom();
+        cpu
+    }
+
+    fn step(cpu: &mut GameboyCPU) {
+        let mut mode = EmulatorMode::Running;
+        cpu.cpu_cycle(&[], &mut mode);
+    }
+
+    fn flags(cpu: &GameboyCPU) -> u8 {
+        cpu.af as u8 & 0xF0
+    }
+
+    // Runs `opcode` with A and B set, and the carry flag as given. Returns A and the flags.
+    fn run_alu(cpu: &mut GameboyCPU, opcode: u8, a: u8, b: u8, carry: bool) -> (u8, u8) {
+        cpu.gb_mem.write().unwrap().dbg_write(0x0100, opcode);
+        cpu.pc = 0x0100;
+        cpu.af = (a as u16) << 8 | if carry {0x10} else {0x00};
+        cpu.bc = (b as u16) << 8;
+
+        step(cpu);
+        ((cpu.af >> 8) as u8, flags(cpu))
+    }
+
+    // Runs `opcode` with HL pointing to `value` in WRAM. Returns the value after it ran and the flags.
+    fn run_on_hl(cpu: &mut GameboyCPU, opcode: &[u8], value: u8, carry: bool) -> (u8, u8) {
+        for (idx, byte) in opcode.iter().enumerate() {
+            cpu.gb_mem.write().unwrap().dbg_write(0x0100 + idx as u16, *byte);
+        }
+
+        cpu.gb_mem.write().unwrap().write(0xC000, value);
+        cpu.pc = 0x0100;
+        cpu.af = if carry {0x10} else {0x00};
+        cpu.hl = 0xC000;
+
+        step(cpu);
+        (cpu.gb_mem.read().unwrap().read(0xC000), flags(cpu))
+    }
+
+    #[test]
+    fn sub_sbc_cp_flags() {
+        let mut cpu = cpu_with_program(&[]);
+
+        // SUB B, borrowing from bit 4.
+        assert_eq!(run_alu(&mut cpu, 0x90, 0x10, 0x01, false), (0x0F, 0x60));
+        // SBC A, B with the carry-in taking the result to 0.
+        assert_eq!(run_alu(&mut cpu, 0x98, 0x10, 0x0F, true), (0x00, 0xE0));
+        // SBC A, B borrowing from both nibbles and the whole byte.
+        assert_eq!(run_alu(&mut cpu, 0x98, 0x00, 0xFF, true), (0x00, 0xF0));
+        // Equal low nibbles, only the carry-in borrows.
+        assert_eq!(run_alu(&mut cpu, 0x98, 0x25, 0x05, true), (0x1F, 0x60));
+        // CP B sets the same flags as SUB, but leaves A alone.
+        assert_eq!(run_alu(&mut cpu, 0xB8, 0x10, 0x0F, false), (0x10, 0x60));
+        assert_eq!(run_alu(&mut cpu, 0xB8, 0x42, 0x42, false), (0x42, 0xC0));
+        assert_eq!(run_alu(&mut cpu, 0xB8, 0x00, 0x01, false), (0x00, 0x70));
+    }
+
+    #[test]
+    fn sbc_matches_reference() {
+        let mut cpu = cpu_with_program(&[]);
+
+        for a in 0..=0xFF {
+            for b in 0..=0xFF {
+                for carry in [false, true].iter() {
+                    let c = *carry as i16;
+                    let result = a as i16 - b as i16 - c;
+                    let mut expected_flags = 0x40;
+
+                    if result as u8 == 0 {
+                        expected_flags |= 0x80;
+                    }
+
+                    if (a & 0x0F) as i16 - (b & 0x0F) as i16 - c < 0 {
+                        expected_flags |= 0x20;
+                    }
+
+                    if result < 0 {
+                        expected_flags |= 0x10;
+                    }
+
+                    assert_eq!(run_alu(&mut cpu, 0x98, a, b, *carry), (result as u8, expected_flags), "{:02X} - {:02X} - {}", a, b, c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interrupt_dispatch_pushes_a_call_frame() {
+        let mut cpu = cpu_with_program(&[0x00]);
+
+        // NOP, RETI as the VBlank handler.
+        cpu.gb_mem.write().unwrap().dbg_write(0x0041, 0xD9);
+        cpu.gb_mem.write().unwrap().write(0xFFFF, 0x01);
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x01);
+        cpu.interrupt_handler.enable_interrupts(false);
+
+        // Dispatching also runs the handler's first instruction.
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0041);
+
+        {
+            let callstack = cpu.callstack.read().unwrap();
+            let frame = callstack.frames().back().unwrap();
+
+            assert_eq!(callstack.frames().len(), 1);
+            assert_eq!(frame.description(), "$0100: INT $40 (VBlank)");
+            assert_eq!(frame.return_address(), 0x0100);
+        }
+
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0100);
+        assert!(cpu.callstack.read().unwrap().frames().is_empty());
+    }
+
+    #[test]
+    fn reti_services_pending_interrupts_right_away() {
+        let mut cpu = cpu_with_program(&[0x00]);
+
+        // NOP, RETI as the VBlank handler.
+        cpu.gb_mem.write().unwrap().dbg_write(0x0041, 0xD9);
+        cpu.gb_mem.write().unwrap().write(0xFFFF, 0x01);
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x01);
+        cpu.interrupt_handler.enable_interrupts(false);
+
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0041);
+        assert_eq!(cpu.callstack.read().unwrap().frames().len(), 1);
+
+        // Requested again while the handler runs, IME is still off so it has to wait for RETI.
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x01);
+
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(cpu.callstack.read().unwrap().frames().len(), 0);
+
+        // Serviced before anything runs at the return address, the step ends after the NOP at the vector.
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0041);
+
+        let callstack = cpu.callstack.read().unwrap();
+
+        assert_eq!(callstack.frames().len(), 1);
+        assert_eq!(callstack.frames().back().unwrap().return_address(), 0x0100);
+        assert!(callstack.mismatch().is_none());
+    }
+
+    #[test]
+    fn interrupt_dispatch_takes_20_cycles() {
+        let mut cpu = cpu_with_program(&[0x00]);
+
+        cpu.gb_mem.write().unwrap().write(0xFFFF, 0x04);
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x04);
+        cpu.interrupt_handler.enable_interrupts(false);
+
+        // The dispatch, then the NOP at the timer vector.
+        step(&mut cpu);
+
+        assert_eq!(cpu.pc, 0x0051);
+        assert_eq!(*cpu.gb_cyc.read().unwrap(), 20 + 4);
+        assert_eq!(cpu.sp, 0xFFFC);
+    }
+
+    #[test]
+    fn inc_dec_hl_flags() {
+        let mut cpu = cpu_with_program(&[]);
+
+        // INC (HL): half-carry out of the low nibble, carry untouched.
+        assert_eq!(run_on_hl(&mut cpu, &[0x34], 0x0F, false), (0x10, 0x20));
+        assert_eq!(run_on_hl(&mut cpu, &[0x34], 0x0F, true), (0x10, 0x30));
+        assert_eq!(run_on_hl(&mut cpu, &[0x34], 0xFF, false), (0x00, 0xA0));
+        assert_eq!(run_on_hl(&mut cpu, &[0x34], 0x41, true), (0x42, 0x10));
+
+        // DEC (HL): half-carry when the low nibble borrows.
+        assert_eq!(run_on_hl(&mut cpu, &[0x35], 0x10, false), (0x0F, 0x60));
+        assert_eq!(run_on_hl(&mut cpu, &[0x35], 0x00, true), (0xFF, 0x70));
+        assert_eq!(run_on_hl(&mut cpu, &[0x35], 0x01, false), (0x00, 0xC0));
+        assert_eq!(run_on_hl(&mut cpu, &[0x35], 0x42, true), (0x41, 0x50));
+
+        assert_eq!(cpu.pc, 0x0101);
+    }
+
+    // Runs `opcode` with a signed `offset` operand, and Z and N set beforehand. Returns SP, HL and the flags.
+    fn run_sp_offset(cpu: &mut GameboyCPU, opcode: u8, sp: u16, offset: i8) -> (u16, u16, u8) {
+        cpu.gb_mem.write().unwrap().dbg_write(0x0100, opcode);
+        cpu.gb_mem.write().unwrap().dbg_write(0x0101, offset as u8);
+        cpu.pc = 0x0100;
+        cpu.af = 0x00C0;
+        cpu.sp = sp;
+        cpu.hl = 0;
+
+        step(cpu);
+        (cpu.sp, cpu.hl, flags(cpu))
+    }
+
+    #[test]
+    fn sp_offset_flags() {
+        let mut cpu = cpu_with_program(&[]);
+
+        // ADD SP, e: H and C come from adding the raw byte to SP's low byte.
+        assert_eq!(run_sp_offset(&mut cpu, 0xE8, 0xFFF8, -8), (0xFFF0, 0, 0x30));
+        assert_eq!(run_sp_offset(&mut cpu, 0xE8, 0x0000, -1), (0xFFFF, 0, 0x00));
+        assert_eq!(run_sp_offset(&mut cpu, 0xE8, 0x0001, -1), (0x0000, 0, 0x30));
+        assert_eq!(run_sp_offset(&mut cpu, 0xE8, 0x00F0, -16), (0x00E0, 0, 0x10));
+        assert_eq!(*cpu.gb_cyc.read().unwrap(), 4 * 16);
+
+        // LD HL, SP+e: same flags, SP stays as it was.
+        assert_eq!(run_sp_offset(&mut cpu, 0xF8, 0xD00F, -2), (0xD00F, 0xD00D, 0x30));
+        assert_eq!(run_sp_offset(&mut cpu, 0xF8, 0xFFFF, 1), (0xFFFF, 0x0000, 0x30));
+        assert_eq!(run_sp_offset(&mut cpu, 0xF8, 0x1000, 127), (0x1000, 0x107F, 0x00));
+        assert_eq!(cpu.pc, 0x0102);
+    }
+
+    #[test]
+    fn bit_flags() {
+        let mut cpu = cpu_with_program(&[]);
+
+        for bit in 0..8 {
+            for value in [1 << bit, !(1 << bit)].iter() {
+                for carry in [false, true].iter() {
+                    let zero = if value & (1 << bit) == 0 {0x80} else {0x00};
+                    let expected = zero | 0x20 | if *carry {0x10} else {0x00};
+
+                    // BIT b, B.
+                    cpu.gb_mem.write().unwrap().dbg_write(0x0100, 0xCB);
+                    cpu.gb_mem.write().unwrap().dbg_write(0x0101, 0x40 + bit * 8);
+                    cpu.pc = 0x0100;
+                    cpu.af = 0x0040 | if *carry {0x10} else {0x00};
+                    cpu.bc = (*value as u16) << 8;
+
+                    let cycles = *cpu.gb_cyc.read().unwrap();
+                    step(&mut cpu);
+
+                    assert_eq!(flags(&cpu), expected, "BIT {}, B with B = ${:02X}", bit, value);
+                    assert_eq!(*cpu.gb_cyc.read().unwrap() - cycles, 8);
+
+                    // BIT b, (HL).
+                    let cycles = *cpu.gb_cyc.read().unwrap();
+
+                    assert_eq!(run_on_hl(&mut cpu, &[0xCB, 0x46 + bit * 8], *value, *carry), (*value, expected), "BIT {}, (HL) with (HL) = ${:02X}", bit, value);
+                    assert_eq!(*cpu.gb_cyc.read().unwrap() - cycles, 12);
+                    assert_eq!(cpu.pc, 0x0102);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_opcodes_lock_or_skip() {
+        let mut cpu = cpu_with_program(&[0xD3, 0x00]);
+        let mut mode = EmulatorMode::Running;
+
+        cpu.cpu_cycle(&[], &mut mode);
+        assert!(mode == EmulatorMode::Locked(0xD3));
+
+        cpu.pc = 0x0100;
+        cpu.set_skip_unknown_opcodes(true);
+
+        let mut mode = EmulatorMode::Running;
+
+        cpu.cpu_cycle(&[], &mut mode);
+        assert!(mode == EmulatorMode::Running);
+        assert_eq!(cpu.pc, 0x0101);
+    }
+
+    #[test]
+    fn stop_resets_div_and_waits_for_joypad() {
+        let mut cpu = cpu_with_program(&[0x10, 0x00, 0x00]);
+
+        cpu.gb_mem.read().unwrap().get_io_reg(0xFF04).set(0x55);
+        *cpu.gb_cyc.write().unwrap() = 200;
+
+        // The byte after STOP gets skipped, and the divider starts a new period.
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.gb_mem.read().unwrap().read(0xFF04), 0x00);
+        assert_eq!(cpu.div_cycles, 200);
+
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0102);
+
+        // A joypad request ends it even with IME and IE clear.
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x10);
+
+        step(&mut cpu);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0103);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT, INC A, NOP.
+        let mut cpu = cpu_with_program(&[0x76, 0x3C, 0x00]);
+
+        cpu.af = 0x0000;
+        cpu.gb_mem.write().unwrap().write(0xFFFF, 0x04);
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x04);
+
+        // IME is clear with an interrupt pending, so HALT doesn't halt.
+        step(&mut cpu);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0101);
+
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0101);
+
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.af >> 8, 0x02);
+    }
+
+    #[test]
+    fn halt_wakes_on_enabled_interrupts() {
+        let mut cpu = cpu_with_program(&[0x76, 0x00]);
+
+        cpu.gb_mem.write().unwrap().write(0xFFFF, 0x01);
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x00);
+
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+
+        // Timer isn't enabled in IE, so it doesn't count.
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x04);
+        step(&mut cpu);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0101);
+
+        // With IME clear it resumes after HALT instead of jumping to the handler.
+        cpu.gb_mem.write().unwrap().write(0xFF0F, 0x05);
+        step(&mut cpu);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0102);
+    }
+
+    #[test]
+    fn post_boot_registers_match_the_model() {
+        let mut cpu = cpu_with_program(&[]);
+
+        for (model, registers) in [
+            (Model::Dmg, [0x01B0, 0x0013, 0x00D8, 0x014D]),
+            (Model::Mgb, [0xFFB0, 0x0013, 0x00D8, 0x014D]),
+            (Model::Cgb, [0x1180, 0x0000, 0xFF56, 0x000D])
+        ].iter() {
+            cpu.set_model(*model);
+            cpu.skip_bootrom();
+
+            assert_eq!([cpu.af, cpu.bc, cpu.de, cpu.hl], *registers);
+            assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+        }
+    }
+}
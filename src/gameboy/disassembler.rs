@@ -1,12 +1,31 @@
 use std::sync::{Arc, RwLock};
 
+use serde::{Deserialize, Serialize};
+
 use super::memory::GameboyMemory;
 
+// How numbers get written by get_instruction_data_styled().
+// The default matches the plain get_instruction_data() output.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DisassemblyStyle {
+    // 0x instead of $ in front of hex numbers.
+    pub c_prefix: bool,
+    pub lowercase_hex: bool,
+    // Shows JR, ADD SP and LD HL, SP+ offsets as the signed operand, instead of the computed value.
+    pub signed_offsets: bool
+}
+
 pub fn get_instruction_data(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -> (u16, String) {
+    get_instruction_data_banked(address, gb_mem, None)
+}
+
+/// Same as get_instruction_data(), decoding $4000-$7FFF from rom_bank instead of the mapped bank if there's one.
+pub fn get_instruction_data_banked(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>, rom_bank: Option<usize>) -> (u16, String) {
     let (opcode_value, imm_1, imm_2) = {
         if let Ok(lock) = gb_mem.read() {
             // FIXME: This will overflow when getting close to $FFFF.
-            (lock.read(address), lock.read(address + 1), lock.read(address + 2))
+            (lock.dbg_read_banked(address, rom_bank), lock.dbg_read_banked(address + 1, rom_bank), lock.dbg_read_banked(address + 2, rom_bank))
         }
         else {
             (0, 0, 0)
@@ -49,7 +68,7 @@ pub fn get_instruction_data(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -
             (2, dis)
         }
 
-        0x10 => (2, format!("??? (${:02X})", opcode_value)),
+        0x10 => (2, String::from("STOP")),
         0x11 => {
             let args = [imm_1, imm_2];
             let dis = format!("LD DE, ${:04X}", u16::from_le_bytes(args));
@@ -341,7 +360,7 @@ pub fn get_instruction_data(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -
 
             (3, dis)
         }
-        0xCB => get_instruction_data_prefixed(address, gb_mem),
+        0xCB => get_instruction_data_prefixed(address, gb_mem, rom_bank),
         0xCC => {
             let args = [imm_1, imm_2];
             let dis = format!("CALL Z, ${:04X}", u16::from_le_bytes(args));
@@ -486,10 +505,96 @@ pub fn get_instruction_data(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -
     }
 }
 
-pub fn get_instruction_data_prefixed(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -> (u16, String) {
+/// Same as get_instruction_data(), with the numbers written the way the style asks for.
+pub fn get_instruction_data_styled(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>, style: &DisassemblyStyle) -> (u16, String) {
+    get_instruction_data_styled_banked(address, gb_mem, style, None)
+}
+
+pub fn get_instruction_data_styled_banked(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>, style: &DisassemblyStyle, rom_bank: Option<usize>) -> (u16, String) {
+    let (len, mut dis) = get_instruction_data_banked(address, gb_mem, rom_bank);
+
+    if style.signed_offsets {
+        let (opcode_value, offset) = {
+            if let Ok(lock) = gb_mem.read() {
+                (lock.dbg_read_banked(address, rom_bank), lock.dbg_read_banked(address.wrapping_add(1), rom_bank) as i8)
+            }
+            else {
+                (0, 0)
+            }
+        };
+
+        let sign = if offset < 0 {"-"} else {"+"};
+
+        match opcode_value {
+            // JR, the offset is relative to the next instruction.
+            0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+                if let Some(idx) = dis.rfind('$') {
+                    dis.truncate(idx);
+                    dis.push_str(&format!("{}${:02X}", sign, offset.unsigned_abs()));
+                }
+            }
+            0xE8 => dis = format!("ADD SP, {}${:02X}", sign, offset.unsigned_abs()),
+            0xF8 => dis = format!("LD HL, SP{}${:02X}", sign, offset.unsigned_abs()),
+            _ => {}
+        }
+    }
+
+    let mut result = String::with_capacity(dis.len() + 4);
+    let mut in_number = false;
+
+    for c in dis.chars() {
+        if c == '$' {
+            result.push_str(if style.c_prefix {"0x"} else {"$"});
+            in_number = true;
+        }
+        else if in_number && c.is_ascii_hexdigit() {
+            result.push(if style.lowercase_hex {c.to_ascii_lowercase()} else {c});
+        }
+        else {
+            result.push(c);
+            in_number = false;
+        }
+    }
+
+    (len, result)
+}
+
+/// Returns the address an instruction jumps to or accesses, if it has one as an operand.
+/// 16-bit loads into registers are included, since they usually hold pointers.
+pub fn get_instruction_target(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>) -> Option<u16> {
+    get_instruction_target_banked(address, gb_mem, None)
+}
+
+pub fn get_instruction_target_banked(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>, rom_bank: Option<usize>) -> Option<u16> {
+    let (opcode_value, imm_1, imm_2) = {
+        if let Ok(lock) = gb_mem.read() {
+            (lock.dbg_read_banked(address, rom_bank), lock.dbg_read_banked(address.wrapping_add(1), rom_bank), lock.dbg_read_banked(address.wrapping_add(2), rom_bank))
+        }
+        else {
+            (0, 0, 0)
+        }
+    };
+
+    match opcode_value {
+        // LD rr, nn, LD (nn), SP, JP, CALL, LD (nn), A and LD A, (nn).
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 |
+        0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD |
+        0xD2 | 0xD4 | 0xDA | 0xDC |
+        0xEA | 0xFA => Some(u16::from_le_bytes([imm_1, imm_2])),
+        // JR.
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Some(address.wrapping_add(imm_1 as i8 as u16).wrapping_add(2)),
+        // LDH.
+        0xE0 | 0xF0 => Some(0xFF00 + imm_1 as u16),
+        // RST.
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some((opcode_value & 0x38) as u16),
+        _ => None
+    }
+}
+
+pub fn get_instruction_data_prefixed(address: u16, gb_mem: &Arc<RwLock<GameboyMemory>>, rom_bank: Option<usize>) -> (u16, String) {
     let opcode_value = {
         if let Ok(lock) = gb_mem.read() {
-            lock.read(address)
+            lock.dbg_read_banked(address, rom_bank)
         }
         else {
             0
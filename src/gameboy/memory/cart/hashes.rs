@@ -0,0 +1,210 @@
+// Hashes of the ROM file, to tell exactly which dump is loaded.
+// No-Intro and friends identify dumps by these, so they're handy for bug reports.
+
+use super::crc32;
+
+const MD5_SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21
+];
+
+// floor(abs(sin(i + 1)) * 2^32).
+const MD5_CONSTANTS: [u32; 64] = [
+    0xD76AA478, 0xE8C7B756, 0x242070DB, 0xC1BDCEEE,
+    0xF57C0FAF, 0x4787C62A, 0xA8304613, 0xFD469501,
+    0x698098D8, 0x8B44F7AF, 0xFFFF5BB1, 0x895CD7BE,
+    0x6B901122, 0xFD987193, 0xA679438E, 0x49B40821,
+    0xF61E2562, 0xC040B340, 0x265E5A51, 0xE9B6C7AA,
+    0xD62F105D, 0x02441453, 0xD8A1E681, 0xE7D3FBC8,
+    0x21E1CDE6, 0xC33707D6, 0xF4D50D87, 0x455A14ED,
+    0xA9E3E905, 0xFCEFA3F8, 0x676F02D9, 0x8D2A4C8A,
+    0xFFFA3942, 0x8771F681, 0x6D9D6122, 0xFDE5380C,
+    0xA4BEEA44, 0x4BDECFA9, 0xF6BB4B60, 0xBEBFBC70,
+    0x289B7EC6, 0xEAA127FA, 0xD4EF3085, 0x04881D05,
+    0xD9D4D039, 0xE6DB99E5, 0x1FA27CF8, 0xC4AC5665,
+    0xF4292244, 0x432AFF97, 0xAB9423A7, 0xFC93A039,
+    0x655B59C3, 0x8F0CCC92, 0xFFEFF47D, 0x85845DD1,
+    0x6FA87E4F, 0xFE2CE6E0, 0xA3014314, 0x4E0811A1,
+    0xF7537E82, 0xBD3AF235, 0x2AD7D2BB, 0xEB86D391
+];
+
+#[derive(Clone)]
+pub struct RomHashes {
+    size: usize,
+
+    crc32: u32,
+    md5: [u8; 16],
+    sha1: [u8; 20]
+}
+
+impl RomHashes {
+    pub fn new(data: &[u8]) -> RomHashes {
+        RomHashes {
+            size: data.len(),
+
+            crc32: crc32(data),
+            md5: md5(data),
+            sha1: sha1(data)
+        }
+    }
+
+    /// Get the size of the ROM file in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn crc32(&self) -> String {
+        format!("{:08X}", self.crc32)
+    }
+
+    pub fn md5(&self) -> String {
+        to_hex(&self.md5)
+    }
+
+    pub fn sha1(&self) -> String {
+        to_hex(&self.sha1)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+// Both hashes work on 64 byte blocks, and finish with a 1 bit, zeroes,
+// and the length in bits on the last 8 bytes of the last block.
+fn padded_tail(data: &[u8], big_endian: bool) -> Vec<u8> {
+    let mut tail = data[data.len() / 64 * 64..].to_vec();
+    let bits = (data.len() as u64).wrapping_mul(8);
+
+    tail.push(0x80);
+
+    while tail.len() % 64 != 56 {
+        tail.push(0);
+    }
+
+    tail.extend_from_slice(&if big_endian {bits.to_be_bytes()} else {bits.to_le_bytes()});
+    tail
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+    let tail = padded_tail(data, false);
+
+    for block in data.chunks_exact(64).chain(tail.chunks_exact(64)) {
+        let mut words = [0u32; 16];
+
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for (i, (constant, shift)) in MD5_CONSTANTS.iter().zip(MD5_SHIFTS.iter()).enumerate() {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16)
+            };
+
+            let f = f.wrapping_add(a).wrapping_add(*constant).wrapping_add(words[g]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(*shift));
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut result = [0; 16];
+
+    for (bytes, word) in result.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+
+    result
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let tail = padded_tail(data, true);
+
+    for block in data.chunks_exact(64).chain(tail.chunks_exact(64)) {
+        let mut words = [0u32; 80];
+
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5A827999),
+                1 => (b ^ c ^ d, 0x6ED9EBA1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6)
+            };
+
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+        state[4] = state[4].wrapping_add(e);
+    }
+
+    let mut result = [0; 20];
+
+    for (bytes, word) in result.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_test_vectors() {
+        // Empty, a single block, one that needs a second block for the padding, and several blocks.
+        let vectors: [(&[u8], &str, &str, &str); 4] = [
+            (b"", "00000000", "D41D8CD98F00B204E9800998ECF8427E", "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709"),
+            (b"abc", "352441C2", "900150983CD24FB0D6963F7D28E17F72", "A9993E364706816ABA3E25717850C26C9CD0D89D"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "171A3F5F", "8215EF0796A20BCAAAE116D3876C664A", "84983E441C3BD26EBAAE4AA1F95129E5E54670F1"
+            ),
+            (&[b'a'; 1000], "9A38DA03", "CABE45DCC9AE5B66BA86600CCA6B8BA8", "291E9A6C66994949B57BA5E650361E98FC36B1BA")
+        ];
+
+        for (data, crc32, md5, sha1) in vectors.iter() {
+            let hashes = RomHashes::new(data);
+
+            assert_eq!(hashes.crc32(), *crc32);
+            assert_eq!(hashes.md5(), *md5);
+            assert_eq!(hashes.sha1(), *sha1);
+        }
+    }
+}
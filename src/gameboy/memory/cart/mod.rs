@@ -1,12 +1,32 @@
+pub mod hashes;
 mod types;
 
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::collections::VecDeque;
 
 use types::*;
 
+use crate::gameboy::state::{StateReader, StateWriter};
+use crate::log;
+
+const BANK_HISTORY_SIZE: usize = 64;
+
+// Checked by the bootrom, every licensed cartridge has it at $0104-$0133.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
+];
+
 pub struct CartHeader {
     title: String,
     cart_type: CartridgeType,
+    has_battery: bool,
+    sgb_support: bool,
+    logo: [u8; 48],
+    compat_title_key: Option<(u8, u8)>,
 
     rom_size: String,
     rom_banks_count: usize,
@@ -34,6 +54,23 @@ impl CartHeader {
             _ => unimplemented!("Unknown or invalid cart type")
         };
 
+        let has_battery = matches!(data[0x0147], 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF);
+        let sgb_support = data[0x0146] == 0x03;
+
+        // The CGB bootrom only looks up palettes for games licensed by Nintendo.
+        let nintendo_licensee = data[0x014B] == 0x01 || (data[0x014B] == 0x33 && &data[0x0144..0x0146] == b"01");
+        let compat_title_key = {
+            if nintendo_licensee {
+                Some((data[0x0134..0x0144].iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), data[0x0137]))
+            }
+            else {
+                None
+            }
+        };
+
+        let mut logo = [0; 48];
+        logo.copy_from_slice(&data[0x0104..0x0134]);
+
         let (rom_size, rom_banks_count) = match data[0x0148] {
             0x00 => (String::from("32 KByte"), 2),
             0x01 => (String::from("64 KByte"), 4),
@@ -60,6 +97,10 @@ impl CartHeader {
         CartHeader {
             title,
             cart_type,
+            has_battery,
+            sgb_support,
+            logo,
+            compat_title_key,
 
             rom_size,
             rom_banks_count,
@@ -79,6 +120,33 @@ impl CartHeader {
         &self.cart_type
     }
 
+    /// Whether the cartridge has a battery to keep its RAM contents.
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    /// Whether the cartridge declares support for Super Game Boy functions.
+    pub fn sgb_support(&self) -> bool {
+        self.sgb_support
+    }
+
+    /// The sum of the title bytes and its 4th letter, used by the CGB bootrom to pick
+    /// a palette for DMG games. None if the game isn't licensed by Nintendo.
+    pub fn compat_title_key(&self) -> Option<(u8, u8)> {
+        self.compat_title_key
+    }
+
+    /// Get the logo bitmap stored in the cart header.
+    pub fn logo(&self) -> &[u8; 48] {
+        &self.logo
+    }
+
+    /// Whether the header's logo matches the one the bootrom checks for.
+    /// Real hardware locks up during boot if it doesn't.
+    pub fn has_valid_logo(&self) -> bool {
+        self.logo == NINTENDO_LOGO
+    }
+
     /// Get a reference to the cart header's rom size.
     pub fn rom_size(&self) -> &String {
         &self.rom_size
@@ -100,6 +168,110 @@ impl CartHeader {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum BankKind {
+    Rom,
+    Ram
+}
+
+impl fmt::Display for BankKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BankKind::Rom => write!(f, "ROM"),
+            BankKind::Ram => write!(f, "RAM")
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BankSwitch {
+    pc: u16,
+    register: u16,
+
+    kind: BankKind,
+    old_bank: usize,
+    new_bank: usize
+}
+
+impl BankSwitch {
+    /// Get the PC of the instruction that switched banks.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Get the mapper register address that was written to.
+    pub fn register(&self) -> u16 {
+        self.register
+    }
+
+    /// Get whether the switch affected ROM or RAM.
+    pub fn kind(&self) -> BankKind {
+        self.kind
+    }
+
+    /// Get the bank mapped before the switch.
+    pub fn old_bank(&self) -> usize {
+        self.old_bank
+    }
+
+    /// Get the bank mapped after the switch.
+    pub fn new_bank(&self) -> usize {
+        self.new_bank
+    }
+}
+
+// Ring buffer with the last bank switches done by a mapper.
+// Disabled by default, so normal play doesn't pay for it.
+#[derive(Default)]
+pub struct BankHistory {
+    enabled: bool,
+
+    pc: u16,
+    entries: VecDeque<BankSwitch>
+}
+
+impl BankHistory {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // Set by GameboyMemory before a write reaches the mapper, so entries know who caused them.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn record(&mut self, register: u16, kind: BankKind, old_bank: usize, new_bank: usize) {
+        if !self.enabled || old_bank == new_bank {
+            return;
+        }
+
+        if self.entries.len() >= BANK_HISTORY_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(BankSwitch {
+            pc: self.pc,
+            register,
+
+            kind,
+            old_bank,
+            new_bank
+        });
+    }
+
+    pub fn entries(&self) -> &VecDeque<BankSwitch> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
 pub trait GameboyCart {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
@@ -111,9 +283,167 @@ pub trait GameboyCart {
     fn is_ram_enabled(&self) -> bool;
     fn get_selected_rom_bank(&self) -> usize;
     fn get_selected_ram_bank(&self) -> usize;
+
+    /// Reads from any ROM bank, mapped in or not. Offsets go from $0000 to $3FFF,
+    /// and banks past the end of the ROM read as $FF.
+    fn read_rom_bank(&self, bank: usize, offset: u16) -> u8;
+
+    /// Every RAM bank on the cart, not only the one that's mapped in.
+    fn ram_banks(&self) -> &[Vec<u8>];
+    /// Writes to a RAM bank directly, ignoring the banking and enable registers.
+    fn dbg_write_ram(&mut self, bank: usize, offset: usize, value: u8);
+
+    fn bank_history(&self) -> &BankHistory;
+    fn bank_history_mut(&mut self) -> &mut BankHistory;
+
+    /// Writes the cart RAM to disk, if it was written to since the last save.
+    fn save_battery(&mut self);
+
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Option<()>;
+}
+
+pub fn save_ram_banks(ram_banks: &[Vec<u8>], state: &mut StateWriter) {
+    state.write_usize(ram_banks.len());
+
+    for bank in ram_banks {
+        state.write_bytes(bank);
+    }
+}
+
+// The bank count has to match, a state from a different cart can't be loaded.
+pub fn load_ram_banks(ram_banks: &mut [Vec<u8>], state: &mut StateReader) -> Option<()> {
+    if state.read_usize()? != ram_banks.len() {
+        return None;
+    }
+
+    for bank in ram_banks.iter_mut() {
+        state.read_bytes_into(bank)?;
+    }
+
+    Some(())
+}
+
+/// Where the battery save of the cart goes, ram/<title>.bin.
+pub fn ram_path(header: &CartHeader) -> PathBuf {
+    PathBuf::from("ram").join(format!("{}.bin", header.title()))
+}
+
+// Loads the battery save at `path`, or blank RAM if there's none.
+pub fn load_saved_ram(path: &Path, banks: usize) -> Vec<Vec<u8>> {
+    let blank = vec![vec![0; 8192]; banks];
+
+    match std::fs::read(path) {
+        Ok(data) => split_saved_ram(&data, banks).unwrap_or_else(|| {
+            log::warn("Cartridge", &format!("The saved RAM is {} bytes, expected {}. Starting with blank RAM.", data.len(), 8192 * banks));
+            blank
+        }),
+        Err(_) => blank
+    }
+}
+
+// Writes the RAM banks to `path` as one file, returns whether it worked.
+pub fn write_saved_ram(path: &Path, ram_banks: &[Vec<u8>]) -> bool {
+    if let Some(dir) = path.parent() {
+        if let Err(error) = std::fs::create_dir_all(dir) {
+            log::error("Cartridge", &format!("Couldn't create the RAM directory ({}).", error));
+            return false;
+        }
+    }
+
+    if let Err(error) = std::fs::write(path, ram_banks.concat()) {
+        log::error("Cartridge", &format!("Couldn't save the cartridge RAM ({}).", error));
+        return false;
+    }
+
+    true
+}
+
+// A save that doesn't match the header's RAM size gets rejected,
+// instead of leaving the cart with fewer banks than it should have.
+pub fn split_saved_ram(data: &[u8], banks: usize) -> Option<Vec<Vec<u8>>> {
+    if data.len() != 8192 * banks {
+        return None;
+    }
+
+    Some(data.chunks_exact(8192).map(|chunk| chunk.to_vec()).collect())
+}
+
+/// Sanity checks a file before trying to load it as a ROM.
+/// Errors mean the emulator can't handle it at all, while the returned
+/// warnings (bad logo or checksum) still allow loading it.
+pub fn check_rom(data: &[u8]) -> Result<Vec<String>, String> {
+    if data.is_empty() {
+        return Err(String::from("the file is empty"));
+    }
+
+    if data.len() < 0x0150 {
+        return Err(String::from("the file is too small to have a cartridge header"));
+    }
+
+    match data[0x0147] {
+        0x00 | 0x08 | 0x09 | 0x01 | 0x02 | 0x03 | 0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => {}
+        value => return Err(format!("unsupported cartridge type ${:02X}", value))
+    }
+
+    if data[0x0148] > 0x08 {
+        return Err(format!("invalid ROM size ${:02X}", data[0x0148]));
+    }
+
+    if data[0x0149] > 0x05 {
+        return Err(format!("invalid RAM size ${:02X}", data[0x0149]));
+    }
+
+    let mut warnings = Vec::new();
+    let declared_size = declared_rom_size(data[0x0148]);
+
+    if data.len() < declared_size {
+        warnings.push(format!("the file has {} bytes but the header says {}, the rest reads as $FF", data.len(), declared_size));
+    }
+    else if data.len() > declared_size {
+        warnings.push(format!("the file has {} bytes past the {} the header says, they're kept as extra banks", data.len() - declared_size, declared_size));
+    }
+
+    if data[0x0104..0x0134] != NINTENDO_LOGO[..] {
+        warnings.push(String::from("the Nintendo logo doesn't match"));
+    }
+
+    let checksum = data[0x0134..0x014D].iter().fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+
+    if checksum != data[0x014D] {
+        warnings.push(format!("bad header checksum, expected ${:02X} but found ${:02X}", checksum, data[0x014D]));
+    }
+
+    Ok(warnings)
 }
 
-pub fn create_cart(data: Vec<u8>) -> Box<dyn GameboyCart + Send + Sync> {
+/// CRC32 (IEEE) of the whole ROM file, used to tell games apart.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for b in data {
+        crc ^= *b as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+// 32 KiB doubled for each step, the header value was already checked by check_rom().
+fn declared_rom_size(value: u8) -> usize {
+    0x8000 << value.min(8)
+}
+
+/// Expects data that passed check_rom(). Files shorter than the header says, or that don't
+/// end on a bank boundary, get padded with $FF so the mappers always have whole banks to read.
+pub fn create_cart(mut data: Vec<u8>) -> Box<dyn GameboyCart + Send + Sync> {
+    let whole_banks = data.len().div_ceil(0x4000) * 0x4000;
+    data.resize(whole_banks.max(declared_rom_size(data[0x0148])), 0xFF);
+
     let header = Arc::new(CartHeader::new(&data));
 
     match header.cart_type {
@@ -1,8 +1,10 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::gameboy::memory::regions::*;
 use crate::gameboy::memory::GameboyCart;
-use crate::gameboy::memory::cart::CartHeader;
+use crate::gameboy::memory::cart::{self, BankHistory, BankKind, CartHeader};
+use crate::gameboy::state::{StateReader, StateWriter};
 
 pub struct MBC1 {
     header: Arc<CartHeader>,
@@ -15,10 +17,23 @@ pub struct MBC1 {
     bank2: u8,
 
     ramg: bool,
+    // Set by RAM writes, cleared once the RAM is saved to disk.
+    ram_dirty: bool,
+    // Where the RAM is saved, None keeps it in memory only.
+    ram_path: Option<PathBuf>,
+    // MBC1M multicarts only wire 4 bits of BANK1, so BANK2 selects banks in steps of 16.
+    multicart: bool,
+    bank_history: BankHistory
 }
 
 impl MBC1 {
     pub fn new(header: Arc<CartHeader>, data: Vec<u8>) -> MBC1 {
+        let ram_path = Some(cart::ram_path(&header));
+        MBC1::with_ram_path(header, data, ram_path)
+    }
+
+    // Like new(), but the RAM gets loaded from and saved to `ram_path`.
+    pub fn with_ram_path(header: Arc<CartHeader>, data: Vec<u8>, ram_path: Option<PathBuf>) -> MBC1 {
         let rom_banks = {
             let mut result = Vec::new();
             let chunks = data.chunks(16384);
@@ -31,20 +46,16 @@ impl MBC1 {
         };
 
         let ram_banks = {
-            if let Ok(data) = std::fs::read(format!("ram/{}.bin", header.title())) {
-                let mut result = Vec::with_capacity(8192 * header.ram_banks_count());
-
-                for chunk in data.chunks_exact(8192) {
-                    result.push(chunk.to_vec());
-                }
-
-                result
+            if let Some(path) = ram_path.as_ref() {
+                cart::load_saved_ram(path, *header.ram_banks_count())
             }
             else {
-                vec![vec![0; 8192]; header.ram_banks_count]
+                vec![vec![0; 8192]; *header.ram_banks_count()]
             }
         };
 
+        let multicart = is_multicart(&rom_banks);
+
         MBC1 {
             header,
 
@@ -55,40 +66,52 @@ impl MBC1 {
             bank1: 1,
             bank2: 0,
 
-            ramg: false
+            ramg: false,
+            ram_dirty: false,
+            ram_path,
+            multicart,
+            bank_history: BankHistory::default()
         }
     }
 
-    fn save_ram(&self) {
-        let mut data = Vec::with_capacity(8192 * self.ram_banks.len());
-
-        for bank in self.ram_banks.iter() {
-            for byte in bank {
-                data.push(*byte);
-            }
-        }
-
-        if let Err(error) = std::fs::create_dir("ram") {
-            if error.kind() != std::io::ErrorKind::AlreadyExists {
-                println!("Error creating RAM directory: {}", error.to_string());
+    fn save_ram(&mut self) {
+        if let Some(path) = self.ram_path.as_ref() {
+            if cart::write_saved_ram(path, &self.ram_banks) {
+                self.ram_dirty = false;
             }
         }
+    }
 
-        if let Err(error) = std::fs::write(format!("ram/{}.bin", self.header.title()), data) {
-            println!("Error saving ram contents: {}", error.to_string());
-        }
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {4} else {5}
     }
 
     fn get_rom_bank(&self) -> usize {
-        ((self.bank2 << 5) | self.bank1) as usize
+        let bank1 = if self.multicart {self.bank1 & 0x0F} else {self.bank1};
+        ((self.bank2 << self.bank2_shift()) | bank1) as usize
     }
 }
 
+// There's nothing in the header to tell MBC1M carts apart, but they're all 1 MiB
+// collections where each game (every 16 banks) starts with its own copy of the header.
+// The menu is at bank 0, so one more logo in any of the other games' first banks is enough.
+fn is_multicart(rom_banks: &[Vec<u8>]) -> bool {
+    if rom_banks.len() != 64 {
+        return false;
+    }
+
+    [0x10, 0x20, 0x30].iter().any(|game| {
+        rom_banks[*game].get(0x0104..0x0134)
+            .map(|logo| logo == cart::NINTENDO_LOGO)
+            .unwrap_or(false)
+    })
+}
+
 impl GameboyCart for MBC1 {
     fn read(&self, address: u16) -> u8 {
         if CARTRIDGE_ROM_BANK0.contains(&address) {
             if self.mode == 1 {
-                let bank = (self.bank2 << 5) as usize;
+                let bank = (self.bank2 << self.bank2_shift()) as usize;
 
                 if let Some(bank) = self.rom_banks.get(bank) {
                     return bank[address as usize];
@@ -149,10 +172,19 @@ impl GameboyCart for MBC1 {
                 _ => value & 0x1F
             };
 
+            let old_bank = self.get_rom_bank();
+
             self.bank1 = if value == 0 {1} else {value};
+            self.bank_history.record(address, BankKind::Rom, old_bank, self.get_rom_bank());
         }
         else if MBC1_BANK2.contains(&address) {
+            let old_rom_bank = self.get_rom_bank();
+            let old_ram_bank = self.get_selected_ram_bank();
+
             self.bank2 = value & 3;
+
+            self.bank_history.record(address, BankKind::Rom, old_rom_bank, self.get_rom_bank());
+            self.bank_history.record(address, BankKind::Ram, old_ram_bank, self.get_selected_ram_bank());
         }
         else if MBC1_MODE.contains(&address) {
             self.mode = value & 1;
@@ -163,6 +195,7 @@ impl GameboyCart for MBC1 {
             if self.mode == 0 {
                 if let Some(bank) = self.ram_banks.get_mut(0) {
                     bank[address as usize] = value;
+                    self.ram_dirty = true;
                 }
             }
             else {
@@ -172,6 +205,7 @@ impl GameboyCart for MBC1 {
                 
                 if let Some(bank) = self.ram_banks.get_mut(bank) {
                     bank[address as usize] = value;
+                    self.ram_dirty = true;
                 }
             }
         }
@@ -209,4 +243,132 @@ impl GameboyCart for MBC1 {
     fn get_selected_ram_bank(&self) -> usize {
         self.bank2 as usize
     }
-}
\ No newline at end of file
+
+    fn read_rom_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom_banks.get(bank).and_then(|bank| bank.get(offset as usize)).copied().unwrap_or(0xFF)
+    }
+
+    fn ram_banks(&self) -> &[Vec<u8>] {
+        &self.ram_banks
+    }
+
+    fn dbg_write_ram(&mut self, bank: usize, offset: usize, value: u8) {
+        if let Some(byte) = self.ram_banks.get_mut(bank).and_then(|bank| bank.get_mut(offset)) {
+            *byte = value;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn bank_history(&self) -> &BankHistory {
+        &self.bank_history
+    }
+
+    fn bank_history_mut(&mut self) -> &mut BankHistory {
+        &mut self.bank_history
+    }
+
+    fn save_battery(&mut self) {
+        if self.ram_dirty {
+            self.save_ram();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.mode);
+        state.write_u8(self.bank1);
+        state.write_u8(self.bank2);
+        state.write_bool(self.ramg);
+
+        cart::save_ram_banks(&self.ram_banks, state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        self.mode = state.read_u8()?;
+        self.bank1 = state.read_u8()?;
+        self.bank2 = state.read_u8()?;
+        self.ramg = state.read_bool()?;
+
+        cart::load_ram_banks(&mut self.ram_banks, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 MiB MBC1 cart without RAM, with each bank's number at its first byte.
+    // Bank 0 always has the logo, the banks in `logos` get another copy of it.
+    fn cart_1mib(logos: &[usize]) -> MBC1 {
+        let mut rom = vec![0; 64 * 0x4000];
+
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+
+        for bank in [0].iter().chain(logos.iter()) {
+            rom[bank * 0x4000 + 0x0104..bank * 0x4000 + 0x0134].copy_from_slice(&cart::NINTENDO_LOGO);
+        }
+
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x05;
+
+        MBC1::with_ram_path(Arc::new(CartHeader::new(&rom)), rom, None)
+    }
+
+    #[test]
+    fn multicart_detection() {
+        assert!(!cart_1mib(&[]).multicart);
+
+        for game in [0x10, 0x20, 0x30].iter() {
+            assert!(cart_1mib(&[*game]).multicart, "logo at bank ${:02X}", game);
+        }
+
+        assert!(cart_1mib(&[0x10, 0x20, 0x30]).multicart);
+
+        // Only the first bank of each game counts.
+        assert!(!cart_1mib(&[0x11, 0x28]).multicart);
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let mut cart = cart_1mib(&[0x10, 0x20, 0x30]);
+
+        assert!(cart.multicart);
+
+        // BANK2 goes to bits 4-5, and bit 4 of BANK1 isn't wired.
+        cart.write(0x2000, 0x03);
+        cart.write(0x4000, 0x01);
+        assert_eq!(cart.read(0x4000), 0x13);
+
+        cart.write(0x2000, 0x13);
+        assert_eq!(cart.read(0x4000), 0x13);
+
+        // $10 passes the zero check, but maps to the first bank of the game.
+        cart.write(0x2000, 0x10);
+        assert_eq!(cart.read(0x4000), 0x10);
+
+        // Mode 1 maps the first bank of the selected game at $0000.
+        assert_eq!(cart.read(0x0000), 0x00);
+
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0x0000), 0x10);
+
+        cart.write(0x4000, 0x03);
+        assert_eq!(cart.read(0x0000), 0x30);
+        assert_eq!(cart.read(0x4000), 0x30);
+    }
+
+    #[test]
+    fn regular_cart_banking() {
+        let mut cart = cart_1mib(&[]);
+
+        assert!(!cart.multicart);
+
+        cart.write(0x2000, 0x13);
+        cart.write(0x4000, 0x01);
+        assert_eq!(cart.read(0x4000), 0x33);
+
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0x0000), 0x20);
+    }
+}
@@ -1,8 +1,10 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::gameboy::memory::regions::*;
 use crate::gameboy::memory::GameboyCart;
-use crate::gameboy::memory::cart::CartHeader;
+use crate::gameboy::memory::cart::{self, BankHistory, BankKind, CartHeader};
+use crate::gameboy::state::{StateReader, StateWriter};
 
 pub struct MBC5 {
     header: Arc<CartHeader>,
@@ -10,15 +12,29 @@ pub struct MBC5 {
     rom_banks: Vec<Vec<u8>>,
     ram_banks: Vec<Vec<u8>>,
 
+    // Together they make a 9-bit bank number, ROMB1 only has the top bit.
+    // Unlike MBC1, bank 0 can be mapped at $4000-$7FFF too.
     romb0: u8,
     romb1: u8,
     
     ramb: u8,
-    ram_enabled: bool
+    ram_enabled: bool,
+    // Set by RAM writes, cleared once the RAM is saved to disk.
+    ram_dirty: bool,
+    // Where the RAM is saved, None keeps it in memory only.
+    ram_path: Option<PathBuf>,
+
+    bank_history: BankHistory
 }
 
 impl MBC5 {
     pub fn new(header: Arc<CartHeader>, data: Vec<u8>) -> MBC5 {
+        let ram_path = Some(cart::ram_path(&header));
+        MBC5::with_ram_path(header, data, ram_path)
+    }
+
+    // Like new(), but the RAM gets loaded from and saved to `ram_path`.
+    pub fn with_ram_path(header: Arc<CartHeader>, data: Vec<u8>, ram_path: Option<PathBuf>) -> MBC5 {
         let rom_banks = {
             let mut result = Vec::new();
             let chunks = data.chunks(16384);
@@ -31,17 +47,11 @@ impl MBC5 {
         };
 
         let ram_banks = {
-            if let Ok(data) = std::fs::read(format!("ram/{}.bin", header.title())) {
-                let mut result = Vec::with_capacity(8192 * header.ram_banks_count());
-
-                for chunk in data.chunks_exact(8192) {
-                    result.push(chunk.to_vec());
-                }
-
-                result
+            if let Some(path) = ram_path.as_ref() {
+                cart::load_saved_ram(path, *header.ram_banks_count())
             }
             else {
-                vec![vec![0; 8192]; header.ram_banks_count]
+                vec![vec![0; 8192]; *header.ram_banks_count()]
             }
         };
 
@@ -51,36 +61,29 @@ impl MBC5 {
             rom_banks,
             ram_banks,
 
-            romb0: 0,
+            romb0: 1,
             romb1: 0,
 
             ramb: 0,
-            ram_enabled: false
-        }
-    }
+            ram_enabled: false,
+            ram_dirty: false,
+            ram_path,
 
-    fn save_ram(&self) {
-        let mut data = Vec::with_capacity(8192 * self.ram_banks.len());
-
-        for bank in self.ram_banks.iter() {
-            for byte in bank {
-                data.push(*byte);
-            }
+            bank_history: BankHistory::default()
         }
+    }
 
-        if let Err(error) = std::fs::create_dir("ram") {
-            if error.kind() != std::io::ErrorKind::AlreadyExists {
-                println!("Error creating RAM directory: {}", error.to_string());
+    fn save_ram(&mut self) {
+        if let Some(path) = self.ram_path.as_ref() {
+            if cart::write_saved_ram(path, &self.ram_banks) {
+                self.ram_dirty = false;
             }
         }
-
-        if let Err(error) = std::fs::write(format!("ram/{}.bin", self.header.title()), data) {
-            println!("Error saving ram contents: {}", error.to_string());
-        }
     }
 
+    // Carts only wire as many bank lines as they need, so bigger numbers wrap around.
     fn get_rom_bank(&self) -> usize {
-        (((self.romb1 as u16) << 9) | self.romb0 as u16) as usize
+        (((self.romb1 as usize) << 8) | self.romb0 as usize) % self.rom_banks.len().max(1)
     }
 }
 
@@ -93,12 +96,18 @@ impl GameboyCart for MBC5 {
             let address = (address - 0x4000) as usize;
             self.rom_banks[self.get_selected_rom_bank()][address]
         }
-        else if CARTRIDGE_RAM.contains(&address) {
+        else if CARTRIDGE_RAM.contains(&address) && self.ram_enabled {
             let address = (address - 0xA000) as usize;
-            self.ram_banks[self.get_selected_ram_bank()][address]
+
+            if let Some(bank) = self.ram_banks.get(self.get_selected_ram_bank()) {
+                bank[address]
+            }
+            else {
+                0xFF
+            }
         }
         else {
-            unreachable!()
+            0xFF
         }
     }
 
@@ -111,13 +120,29 @@ impl GameboyCart for MBC5 {
             }
         }
         else if MBC5_ROMB0.contains(&address) {
+            let old_bank = self.get_rom_bank();
+
             self.romb0 = value;
+            self.bank_history.record(address, BankKind::Rom, old_bank, self.get_rom_bank());
         }
         else if MBC5_ROMB1.contains(&address) {
+            let old_bank = self.get_rom_bank();
+
             self.romb1 = value & 1;
+            self.bank_history.record(address, BankKind::Rom, old_bank, self.get_rom_bank());
         }
         else if MBC5_RAMB.contains(&address) {
+            let old_bank = self.get_selected_ram_bank();
+
             self.ramb = value & 0b00001111;
+            self.bank_history.record(address, BankKind::Ram, old_bank, self.get_selected_ram_bank());
+        }
+        else if CARTRIDGE_RAM.contains(&address) && self.ram_enabled {
+            // Banks past the ones the header declares aren't mapped, writes to them are dropped.
+            if let Some(bank) = self.ram_banks.get_mut(self.ramb as usize) {
+                bank[(address - 0xA000) as usize] = value;
+                self.ram_dirty = true;
+            }
         }
     }
 
@@ -127,8 +152,9 @@ impl GameboyCart for MBC5 {
     }
 
     fn reset(&mut self) {
-        self.romb0 = 0;
+        self.romb0 = 1;
         self.romb1 = 0;
+        self.ramb = 0;
         self.ram_enabled = false;
     }
 
@@ -147,4 +173,126 @@ impl GameboyCart for MBC5 {
     fn get_selected_ram_bank(&self) -> usize {
         self.ramb as usize
     }
+
+    fn read_rom_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom_banks.get(bank).and_then(|bank| bank.get(offset as usize)).copied().unwrap_or(0xFF)
+    }
+
+    fn ram_banks(&self) -> &[Vec<u8>] {
+        &self.ram_banks
+    }
+
+    fn dbg_write_ram(&mut self, bank: usize, offset: usize, value: u8) {
+        if let Some(byte) = self.ram_banks.get_mut(bank).and_then(|bank| bank.get_mut(offset)) {
+            *byte = value;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn bank_history(&self) -> &BankHistory {
+        &self.bank_history
+    }
+
+    fn bank_history_mut(&mut self) -> &mut BankHistory {
+        &mut self.bank_history
+    }
+
+    fn save_battery(&mut self) {
+        if self.ram_dirty {
+            self.save_ram();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.romb0);
+        state.write_u8(self.romb1);
+        state.write_u8(self.ramb);
+        state.write_bool(self.ram_enabled);
+
+        cart::save_ram_banks(&self.ram_banks, state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        self.romb0 = state.read_u8()?;
+        self.romb1 = state.read_u8()?;
+        self.ramb = state.read_u8()?;
+        self.ram_enabled = state.read_bool()?;
+
+        cart::load_ram_banks(&mut self.ram_banks, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MBC5+RAM cart with 32 KByte of RAM, each bank's number is in its first two bytes.
+    // 257 banks is the least that still has one past bank $FF, so the 9th bit can be told apart.
+    fn cart_257_banks() -> MBC5 {
+        let mut rom = vec![0; 0x101 * 0x4000];
+
+        for bank in 0..0x101 {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+
+        rom[0x0147] = 0x1A;
+        rom[0x0148] = 0x08;
+        rom[0x0149] = 0x03;
+
+        MBC5::with_ram_path(Arc::new(CartHeader::new(&rom)), rom, None)
+    }
+
+    fn romx_bank(cart: &MBC5) -> u16 {
+        u16::from_le_bytes([cart.read(0x4000), cart.read(0x4001)])
+    }
+
+    #[test]
+    fn rom_banking() {
+        let mut cart = cart_257_banks();
+
+        assert_eq!(romx_bank(&cart), 1);
+
+        // Unlike MBC1, bank 0 can be mapped at $4000.
+        cart.write(0x2000, 0x00);
+        assert_eq!(romx_bank(&cart), 0);
+
+        cart.write(0x2000, 0x00);
+        cart.write(0x3000, 0x01);
+        assert_eq!(romx_bank(&cart), 0x100);
+
+        // ROMB1 only has the 9th bit.
+        cart.write(0x3000, 0x03);
+        assert_eq!(romx_bank(&cart), 0x100);
+
+        cart.write(0x3000, 0x00);
+        cart.write(0x2000, 0x34);
+        assert_eq!(romx_bank(&cart), 0x34);
+
+        // Bank numbers past the end of the ROM wrap around.
+        cart.write(0x2000, 0x01);
+        cart.write(0x3000, 0x01);
+        assert_eq!(romx_bank(&cart), 0x00);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut cart = cart_257_banks();
+
+        assert_eq!(cart.read(0xA000), 0xFF);
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x02);
+        cart.write(0xA000, 0x55);
+        assert_eq!(cart.get_selected_ram_bank(), 2);
+        assert_eq!(cart.read(0xA000), 0x55);
+
+        cart.write(0x4000, 0x01);
+        assert_eq!(cart.read(0xA000), 0x00);
+
+        // Only the low 4 bits of RAMB select the bank.
+        cart.write(0x4000, 0x12);
+        assert_eq!(cart.read(0xA000), 0x55);
+        assert_eq!(cart.ram_banks()[2][0], 0x55);
+    }
 }
@@ -1,16 +1,35 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::gameboy::memory::regions::*;
 use crate::gameboy::memory::GameboyCart;
-use crate::gameboy::memory::cart::CartHeader;
+use crate::gameboy::memory::cart::{self, BankHistory, CartHeader};
+use crate::gameboy::state::{StateReader, StateWriter};
 
+// Covers both plain ROM carts ($00) and the ROM+RAM variants ($08, $09).
+// The latter map their RAM directly at $A000-$BFFF, without any enable register.
 pub struct NoMBC {
     header: Arc<CartHeader>,
-    rom_banks: Vec<Vec<u8>>
+
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    // Set by RAM writes, cleared once the RAM is saved to disk.
+    ram_dirty: bool,
+    // Where the RAM is saved, None for carts without a battery.
+    ram_path: Option<PathBuf>,
+
+    // Never gets any entries, since there's no banking to speak of.
+    bank_history: BankHistory
 }
 
 impl NoMBC {
     pub fn new(header: Arc<CartHeader>, data: Vec<u8>) -> NoMBC {
+        let ram_path = if header.has_battery() {Some(cart::ram_path(&header))} else {None};
+        NoMBC::with_ram_path(header, data, ram_path)
+    }
+
+    // Like new(), but the battery save (if any) goes to `ram_path`.
+    pub fn with_ram_path(header: Arc<CartHeader>, data: Vec<u8>, ram_path: Option<PathBuf>) -> NoMBC {
         let rom_banks = {
             let mut result = Vec::new();
             let chunks = data.chunks(16384);
@@ -22,13 +41,43 @@ impl NoMBC {
             result
         };
 
+        let ram_banks = {
+            if let Some(path) = ram_path.as_ref() {
+                cart::load_saved_ram(path, *header.ram_banks_count())
+            }
+            else {
+                vec![vec![0; 8192]; *header.ram_banks_count()]
+            }
+        };
+
         NoMBC {
             header,
-            rom_banks
+
+            rom_banks,
+            ram_banks,
+            ram_dirty: false,
+            ram_path,
+
+            bank_history: BankHistory::default()
+        }
+    }
+
+    fn save_ram(&mut self) {
+        if let Some(path) = self.ram_path.as_ref() {
+            if !self.ram_banks.is_empty() && cart::write_saved_ram(path, &self.ram_banks) {
+                self.ram_dirty = false;
+            }
         }
     }
 }
 
+impl Drop for NoMBC {
+    // There's no RAM enable register to hook the save into, so do it when the cart goes away.
+    fn drop(&mut self) {
+        self.save_ram();
+    }
+}
+
 impl GameboyCart for NoMBC {
     fn read(&self, address: u16) -> u8 {
         if CARTRIDGE_ROM_BANK0.contains(&address) {
@@ -37,13 +86,26 @@ impl GameboyCart for NoMBC {
         else if CARTRIDGE_ROM_BANKX.contains(&address) {
             self.rom_banks[1][address as usize - 0x4000]
         }
+        else if CARTRIDGE_RAM.contains(&address) {
+            if let Some(bank) = self.ram_banks.first() {
+                bank[address as usize - 0xA000]
+            }
+            else {
+                0xFF
+            }
+        }
         else {
             0
         }
     }
 
-    fn write(&mut self, _address: u16, _value: u8) {
-        
+    fn write(&mut self, address: u16, value: u8) {
+        if CARTRIDGE_RAM.contains(&address) {
+            if let Some(bank) = self.ram_banks.get_mut(0) {
+                bank[address as usize - 0xA000] = value;
+                self.ram_dirty = true;
+            }
+        }
     }
 
     fn dbg_write(&mut self, address: u16, value: u8) {
@@ -53,10 +115,13 @@ impl GameboyCart for NoMBC {
         else if CARTRIDGE_ROM_BANKX.contains(&address) {
             self.rom_banks[1][address as usize - 0x4000] = value;
         }
+        else if CARTRIDGE_RAM.contains(&address) {
+            self.write(address, value);
+        }
     }
 
     fn reset(&mut self) {
-        
+        self.save_ram();
     }
 
     fn get_header(&self) -> Arc<CartHeader> {
@@ -64,7 +129,7 @@ impl GameboyCart for NoMBC {
     }
 
     fn is_ram_enabled(&self) -> bool {
-        false
+        !self.ram_banks.is_empty()
     }
 
     fn get_selected_rom_bank(&self) -> usize {
@@ -74,4 +139,98 @@ impl GameboyCart for NoMBC {
     fn get_selected_ram_bank(&self) -> usize {
         0
     }
-}
\ No newline at end of file
+
+    fn read_rom_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom_banks.get(bank).and_then(|bank| bank.get(offset as usize)).copied().unwrap_or(0xFF)
+    }
+
+    fn ram_banks(&self) -> &[Vec<u8>] {
+        &self.ram_banks
+    }
+
+    fn dbg_write_ram(&mut self, bank: usize, offset: usize, value: u8) {
+        if let Some(byte) = self.ram_banks.get_mut(bank).and_then(|bank| bank.get_mut(offset)) {
+            *byte = value;
+            self.ram_dirty = true;
+        }
+    }
+
+    fn bank_history(&self) -> &BankHistory {
+        &self.bank_history
+    }
+
+    fn bank_history_mut(&mut self) -> &mut BankHistory {
+        &mut self.bank_history
+    }
+
+    fn save_battery(&mut self) {
+        if self.ram_dirty {
+            self.save_ram();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        cart::save_ram_banks(&self.ram_banks, state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        cart::load_ram_banks(&mut self.ram_banks, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ROM+RAM+BATTERY with 8 KByte of RAM.
+    fn ram_cart(ram_path: Option<PathBuf>) -> NoMBC {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x09;
+        rom[0x0149] = 0x02;
+
+        NoMBC::with_ram_path(Arc::new(CartHeader::new(&rom)), rom, ram_path)
+    }
+
+    #[test]
+    fn ram_writes_read_back() {
+        let mut cart = ram_cart(None);
+
+        assert!(cart.is_ram_enabled());
+
+        cart.write(0xA000, 0x12);
+        cart.write(0xBFFF, 0x34);
+
+        assert_eq!(cart.read(0xA000), 0x12);
+        assert_eq!(cart.read(0xBFFF), 0x34);
+        assert!(cart.ram_dirty);
+    }
+
+    #[test]
+    fn saves_load_back() {
+        let path = std::env::temp_dir().join(format!("rusty-boy-nombc-{}.bin", std::process::id()));
+
+        let mut cart = ram_cart(Some(path.clone()));
+        cart.write(0xA000, 0x12);
+        drop(cart);
+
+        assert_eq!(ram_cart(Some(path.clone())).read(0xA000), 0x12);
+
+        // A short save is ignored instead of leaving the cart without RAM.
+        std::fs::write(&path, [0x12; 4096]).unwrap();
+
+        let cart = ram_cart(Some(path.clone()));
+
+        assert!(cart.is_ram_enabled());
+        assert_eq!(cart.read(0xA000), 0x00);
+
+        drop(cart);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn short_saves_are_rejected() {
+        assert!(cart::split_saved_ram(&[0; 4096], 1).is_none());
+        assert!(cart::split_saved_ram(&[0; 8192], 4).is_none());
+        assert_eq!(cart::split_saved_ram(&[0; 32768], 4).map(|banks| banks.len()), Some(4));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::sync::{Arc, RwLock};
 
 use crate::gameboy::memory::GameboyMemory;
@@ -5,6 +6,109 @@ use crate::gameboy::memory::GameboyMemory;
 const DMA_COPY_SIZE: u16 = 0x9F;
 const TRANSFER_TARGET: u16 = 0xFE00;
 
+const DMA_LOG_SIZE: usize = 64;
+
+#[derive(Clone)]
+pub struct DmaEvent {
+    pc: u16,
+    source: u16,
+
+    started_at: usize,
+    finished_at: Option<usize>,
+    blocked_accesses: usize
+}
+
+impl DmaEvent {
+    /// Get the PC of the instruction that wrote to $FF46.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Get the address the transfer copies from.
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    /// Get the value of the master clock when the transfer started.
+    pub fn started_at(&self) -> usize {
+        self.started_at
+    }
+
+    /// Get the value of the master clock when the transfer finished, if it did.
+    pub fn finished_at(&self) -> Option<usize> {
+        self.finished_at
+    }
+
+    /// Get how many times the CPU touched memory outside of HRAM during the transfer.
+    pub fn blocked_accesses(&self) -> usize {
+        self.blocked_accesses
+    }
+}
+
+// Ring buffer with the last OAM DMA transfers.
+// Disabled by default, so normal play doesn't pay for it.
+#[derive(Default)]
+pub struct DmaLog {
+    enabled: bool,
+    entries: VecDeque<DmaEvent>
+}
+
+impl DmaLog {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn start(&mut self, pc: u16, source: u8, cycles: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        // Writing to $FF46 again restarts the transfer, so the previous one ends here.
+        self.finish(cycles);
+
+        if self.entries.len() >= DMA_LOG_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(DmaEvent {
+            pc,
+            source: (source as u16) << 8,
+
+            started_at: cycles,
+            finished_at: None,
+            blocked_accesses: 0
+        });
+    }
+
+    pub fn finish(&mut self, cycles: usize) {
+        if let Some(event) = self.entries.back_mut() {
+            if event.finished_at.is_none() {
+                event.finished_at = Some(cycles);
+            }
+        }
+    }
+
+    pub fn record_blocked_access(&mut self) {
+        if let Some(event) = self.entries.back_mut() {
+            if event.finished_at.is_none() {
+                event.blocked_accesses += 1;
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &VecDeque<DmaEvent> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
 pub struct DmaTransfer {
     source: u16,
     current: u16,
@@ -29,10 +133,10 @@ impl DmaTransfer {
     }
 
     pub fn step(&mut self, cycles: usize) -> bool {
-        let elapsed = cycles - self.started_at;
+        let elapsed = cycles.saturating_sub(self.started_at);
         let bytes_to_copy = {
             let missing = DMA_COPY_SIZE as usize - self.copied;
-            let mut amount = (elapsed / 4) - self.copied;
+            let mut amount = (elapsed / 4).saturating_sub(self.copied);
 
             if amount > missing {
                 amount = missing
@@ -43,7 +147,7 @@ impl DmaTransfer {
 
         for _ in 0..bytes_to_copy {
             if let Ok(mut lock) = self.gb_mem.write() {
-                let byte = lock.read(self.source);
+                let byte = lock.watched_read(self.source);
                 lock.write(self.current, byte);
     
                 self.copied += 1;
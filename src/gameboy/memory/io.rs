@@ -1,5 +1,8 @@
+use std::collections::VecDeque;
 use std::sync::{Arc, RwLock};
 
+const IO_WRITE_LOG_SIZE: usize = 2048;
+
 pub struct IoRegister {
     value: RwLock<u8>,
 
@@ -70,6 +73,68 @@ impl IoRegister {
     }
 }
 
+#[derive(Clone)]
+pub struct IoWrite {
+    pc: u16,
+    address: u16,
+    value: u8
+}
+
+impl IoWrite {
+    /// Get the PC of the instruction that did the write.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Get the register that was written to.
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Get the value written, before the register's write mask is applied.
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+}
+
+// Ring buffer with the last writes to $FF00-$FF7F, from any PC.
+// Disabled by default, so normal play doesn't pay for it.
+#[derive(Default)]
+pub struct IoWriteLog {
+    enabled: bool,
+    entries: VecDeque<IoWrite>
+}
+
+impl IoWriteLog {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn record(&mut self, pc: u16, address: u16, value: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.entries.len() >= IO_WRITE_LOG_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(IoWrite { pc, address, value });
+    }
+
+    pub fn entries(&self) -> &VecDeque<IoWrite> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
 pub fn init_io_regs() -> Vec<Arc<IoRegister>> {
     let mut io = {
         let mut res = Vec::with_capacity(128);
@@ -101,8 +166,8 @@ pub fn init_io_regs() -> Vec<Arc<IoRegister>> {
     io[0x0D] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b1111_1111));
     io[0x0E] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b1111_1111));
 
-    // 0xF00F - IF.
-    io[0x0F] = Arc::new(IoRegister::init(0, 0b0111_1111, 0b1000_0000));
+    // 0xFF0F - IF.
+    io[0x0F] = Arc::new(IoRegister::init(0, 0b0001_1111, 0b1110_0000));
 
     // Unused.
     io[0x15] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b1111_1111));
@@ -122,9 +187,9 @@ pub fn init_io_regs() -> Vec<Arc<IoRegister>> {
     io[0x2E] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b1111_1111));
     io[0x2F] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b1111_1111));
 
-    // 0xFF40 - STAT.
+    // 0xFF41 - STAT.
     io[0x41] = Arc::new(IoRegister::init(0, 0b0111_1000, 0b1000_0000));
-    // 0xFF44 - LY.
+    // 0xFF44 - LY. Read-only, writes reset it instead (see GameboyMemory::write()).
     io[0x44] = Arc::new(IoRegister::init(0, 0b0000_0000, 0b0000_0000));
 
     // Unused.
@@ -2,14 +2,86 @@ pub mod io;
 pub mod dma;
 pub mod cart;
 pub mod regions;
+pub mod sgb;
+pub mod serial;
+pub mod stats;
 
 use std::sync::{Arc, RwLock};
 
+use serde::{Deserialize, Serialize};
+
 use regions::*;
-use io::IoRegister;
+use io::{IoRegister, IoWriteLog};
+use sgb::SgbPacketReader;
+use serial::{SerialCapture, SerialLink, SerialLog, SERIAL_TRANSFER_CYCLES};
+use stats::AccessStats;
 use cart::{CartHeader, GameboyCart};
 
-use crate::gameboy::JoypadHandler;
+use crate::gameboy::{Breakpoint, JoypadHandler};
+use crate::gameboy::state::{self, StateReader, StateWriter};
+
+// Debug output for homebrew, only there while enabled (see set_debug_channel()).
+// Every byte written here is added to the current line of the debug console,
+// and a $0A or $00 ends the line. $FF7E is unused on every model, so while the
+// channel is disabled it behaves like on hardware, and it always reads back as $FF.
+pub const DEBUG_CHANNEL_ADDRESS: u16 = 0xFF7E;
+
+// IO registers as the DMG bootrom leaves them, including $FF50 unmapping the bootrom itself.
+const POST_BOOT_IO: [(u16, u8); 34] = [
+    (0xFF04, 0xAB), (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0x00), (0xFF0F, 0x01),
+    (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF14, 0xBF), (0xFF16, 0x3F),
+    (0xFF17, 0x00), (0xFF19, 0xBF), (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F),
+    (0xFF1E, 0xBF), (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+    (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF26, 0xF1), (0xFF40, 0x91), (0xFF41, 0x05),
+    (0xFF42, 0x00), (0xFF43, 0x00), (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF48, 0xFF),
+    (0xFF49, 0xFF), (0xFF4A, 0x00), (0xFF4B, 0x00), (0xFF50, 0x01)
+];
+
+/// What memory holds when the console is turned on. Skipping the bootrom
+/// always sets up the post-boot IO values on top of this, like the bootrom would.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum PowerOnState {
+    // Everything cleared.
+    #[default]
+    Zeroed,
+    // IO registers start with the post-boot values, even if a bootrom runs first.
+    PostBoot,
+    // WRAM and HRAM filled with this byte, real hardware has mostly random values there.
+    Pattern(u8)
+}
+
+// Memory's part of a save state, as written by GameboyMemory::save_state().
+// The cartridge's part comes after it, and its layout depends on the mapper.
+pub struct SavedMemory<'a> {
+    pub vram: &'a [u8],
+    pub wram: &'a [u8],
+    pub oam: &'a [u8],
+    pub io: Vec<u8>,
+    pub hram: &'a [u8],
+    pub ie: u8
+}
+
+impl<'a> SavedMemory<'a> {
+    pub fn read(state: &mut StateReader<'a>) -> Option<SavedMemory<'a>> {
+        let vram = state.read_bytes()?;
+        let wram = state.read_bytes()?;
+        let oam = state.read_bytes()?;
+        let mut io = Vec::with_capacity(128);
+
+        for _ in 0..128 {
+            io.push(state.read_u8()?);
+        }
+
+        Some(SavedMemory {
+            vram,
+            wram,
+            oam,
+            io,
+            hram: state.read_bytes()?,
+            ie: state.read_u8()?
+        })
+    }
+}
 
 pub struct GameboyMemory {
     bootrom: Vec<u8>,
@@ -23,15 +95,43 @@ pub struct GameboyMemory {
     hram: Vec<u8>,
 
     ie: u8,
-
+    // Cached from $FF50, since every ROM read needs it. See update_bootrom_enabled().
+    bootrom_enabled: bool,
+    power_on_state: PowerOnState,
+    // PC of the instruction currently accessing memory, for debugging purposes.
+    cpu_pc: u16,
+
+    // Addresses with a read or write breakpoint, kept in sync with the breakpoint list by Gameboy.
+    // Checked here so accesses done by any component (DMA, interrupts...) get caught, not only the CPU ones.
+    read_breakpoints: Vec<u16>,
+    write_breakpoints: Vec<u16>,
+    breakpoint_hit: bool,
+    io_write_log: IoWriteLog,
+    access_stats: AccessStats,
+    // Set by writes to LY, the PPU owns the line counter so Gameboy passes it along.
+    ly_reset: bool,
+    // Same for DIV, the CPU keeps the divider's internal counter.
+    div_reset: bool,
+    // Value LY reads as instead of the real one, Gameboy Doctor logs expect $90.
+    ly_override: Option<u8>,
+
+    sgb: SgbPacketReader,
     gb_joy: Arc<RwLock<JoypadHandler>>,
-    serial_output: Arc<RwLock<Vec<u8>>>
+    // Only used to timestamp serial output, see set_clock().
+    gb_cyc: Arc<RwLock<usize>>,
+    serial_output: Arc<RwLock<SerialLog>>,
+    debug_channel: bool,
+    debug_output: Arc<RwLock<SerialLog>>,
+    serial_link: Box<dyn SerialLink + Send + Sync>,
+    // When the current transfer was started, see serial_step().
+    serial_started_at: usize
 }
 
 impl GameboyMemory {
     pub fn init(bootrom: Vec<u8>, romfile_data: Vec<u8>, gb_joy: Arc<RwLock<JoypadHandler>>) -> GameboyMemory {
         let io = io::init_io_regs();
         let cartridge = cart::create_cart(romfile_data);
+        let serial_output = Arc::new(RwLock::new(SerialLog::default()));
 
         GameboyMemory {
             bootrom,
@@ -45,9 +145,27 @@ impl GameboyMemory {
             hram: vec![0; 0x007F],
 
             ie: 0,
-
+            bootrom_enabled: true,
+            power_on_state: PowerOnState::Zeroed,
+            cpu_pc: 0,
+
+            read_breakpoints: Vec::new(),
+            write_breakpoints: Vec::new(),
+            breakpoint_hit: false,
+            io_write_log: IoWriteLog::default(),
+            access_stats: AccessStats::default(),
+            ly_reset: false,
+            div_reset: false,
+            ly_override: None,
+
+            sgb: SgbPacketReader::default(),
             gb_joy,
-            serial_output: Arc::new(RwLock::new(Vec::new()))
+            gb_cyc: Arc::new(RwLock::new(0)),
+            serial_link: Box::new(SerialCapture::new(serial_output.clone())),
+            serial_output,
+            debug_channel: false,
+            debug_output: Arc::new(RwLock::new(SerialLog::default())),
+            serial_started_at: 0
         }
     }
 
@@ -60,6 +178,11 @@ impl GameboyMemory {
         &self.cartridge
     }
 
+    #[allow(clippy::borrowed_box)]
+    pub fn cartridge_mut(&mut self) -> &mut Box<dyn GameboyCart + Send + Sync> {
+        &mut self.cartridge
+    }
+
     pub fn header(&self) -> Arc<CartHeader> {
         self.cartridge.get_header()
     }
@@ -68,12 +191,189 @@ impl GameboyMemory {
         self.gb_joy.clone()
     }
 
-    pub fn serial_output(&self) -> Arc<RwLock<Vec<u8>>> {
+    pub fn sgb(&self) -> &SgbPacketReader {
+        &self.sgb
+    }
+
+    pub fn serial_output(&self) -> Arc<RwLock<SerialLog>> {
         self.serial_output.clone()
     }
 
+    /// Plugs something into the link port, None goes back to capturing the output.
+    pub fn set_serial_link(&mut self, link: Option<Box<dyn SerialLink + Send + Sync>>) {
+        self.serial_link = link.unwrap_or_else(|| Box::new(SerialCapture::new(self.serial_output.clone())));
+    }
+
+    pub fn debug_output(&self) -> Arc<RwLock<SerialLog>> {
+        self.debug_output.clone()
+    }
+
+    pub fn set_debug_channel(&mut self, enabled: bool) {
+        self.debug_channel = enabled;
+    }
+
+    pub fn serial_link_status(&self) -> String {
+        self.serial_link.status()
+    }
+
+    // Finishes the transfer in progress, if the other side sent its byte.
+    pub fn serial_step(&mut self) {
+        let sc = self.io[0x02].get();
+
+        if sc & 0x80 == 0 {
+            return;
+        }
+
+        let cycles = self.gb_cyc.read().map(|cycles| *cycles).unwrap_or(0);
+        let internal_clock = sc & 0x01 != 0;
+
+        let received = {
+            if internal_clock {
+                // The clock went back, probably a reset or a loaded state.
+                if cycles < self.serial_started_at {
+                    self.serial_started_at = cycles;
+                }
+
+                if cycles.saturating_sub(self.serial_started_at) < SERIAL_TRANSFER_CYCLES {
+                    return;
+                }
+
+                self.serial_link.receive()
+            }
+            else {
+                let received = self.serial_link.receive();
+
+                // The other side clocked the transfer, so it's waiting for our byte.
+                if received.is_some() {
+                    self.serial_link.send(self.io[0x01].get(), cycles);
+                }
+
+                received
+            }
+        };
+
+        if let Some(value) = received {
+            self.io[0x01].set(value);
+            self.io[0x02].set(sc & !0x80);
+            self.io[0x0F].set(self.io[0x0F].get() | 0x08);
+        }
+    }
+
+    // The memory gets created before the rest of the system, so Gameboy hands the clock over later.
+    pub fn set_clock(&mut self, gb_cyc: Arc<RwLock<usize>>) {
+        self.gb_cyc = gb_cyc;
+    }
+
+    pub fn has_bootrom(&self) -> bool {
+        !self.bootrom.is_empty()
+    }
+
+    /// Whether the bootrom is still running, the cartridge takes over once it writes to $FF50.
+    pub fn bootrom_mapped(&self) -> bool {
+        self.has_bootrom() && self.bootrom_enabled
+    }
+
+    // Has to be called whenever $FF50 gets set directly instead of through write().
+    fn update_bootrom_enabled(&mut self) {
+        self.bootrom_enabled = self.io[0x0050].get() & 1 == 0;
+    }
+
+    // Sets the IO registers to the values the DMG bootrom leaves behind,
+    // and unmaps the (missing) bootrom so execution can start at $0100.
+    pub fn skip_bootrom(&mut self) {
+        for (address, value) in POST_BOOT_IO.iter() {
+            self.io[*address as usize - 0xFF00].set(*value);
+        }
+
+        self.ie = 0;
+        self.update_bootrom_enabled();
+    }
+
+    /// Takes effect on the next reset.
+    pub fn set_power_on_state(&mut self, state: PowerOnState) {
+        self.power_on_state = state;
+    }
+
+    fn apply_power_on_state(&mut self) {
+        match self.power_on_state {
+            PowerOnState::Zeroed => {}
+            PowerOnState::PostBoot => {
+                // Leave $FF50 alone, a bootrom still has to run if there's one.
+                for (address, value) in POST_BOOT_IO.iter().filter(|(address, _)| *address != 0xFF50) {
+                    self.io[*address as usize - 0xFF00].set(*value);
+                }
+            }
+            PowerOnState::Pattern(value) => {
+                for b in self.wram.iter_mut() {
+                    *b = value;
+                }
+
+                for b in self.hram.iter_mut() {
+                    *b = value;
+                }
+            }
+        }
+    }
+
+    pub fn set_cpu_pc(&mut self, pc: u16) {
+        self.cpu_pc = pc;
+    }
+
+    pub fn sync_breakpoints(&mut self, breakpoints: &[Breakpoint]) {
+        let read_breakpoints = breakpoints.iter().filter(|bp| *bp.read()).map(|bp| *bp.address());
+        let write_breakpoints = breakpoints.iter().filter(|bp| *bp.write()).map(|bp| *bp.address());
+
+        if !self.read_breakpoints.iter().copied().eq(read_breakpoints.clone()) {
+            self.read_breakpoints = read_breakpoints.collect();
+        }
+
+        if !self.write_breakpoints.iter().copied().eq(write_breakpoints.clone()) {
+            self.write_breakpoints = write_breakpoints.collect();
+        }
+    }
+
+    /// Returns whether a read or write breakpoint was hit since the last call.
+    pub fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::replace(&mut self.breakpoint_hit, false)
+    }
+
+    pub fn io_write_log(&self) -> &IoWriteLog {
+        &self.io_write_log
+    }
+
+    pub fn io_write_log_mut(&mut self) -> &mut IoWriteLog {
+        &mut self.io_write_log
+    }
+
+    // Accesses get counted by the CPU, since the debugger windows go through read()
+    // all the time, and the PPU's register updates would drown out the game's writes.
+    pub fn access_stats(&self) -> &AccessStats {
+        &self.access_stats
+    }
+
+    pub fn access_stats_mut(&mut self) -> &mut AccessStats {
+        &mut self.access_stats
+    }
+
+    pub fn set_ly_override(&mut self, value: Option<u8>) {
+        self.ly_override = value;
+    }
+
+    /// Returns whether LY was written to since the last call.
+    pub fn take_ly_reset(&mut self) -> bool {
+        std::mem::replace(&mut self.ly_reset, false)
+    }
+
+    /// Returns whether DIV was written to since the last call.
+    pub fn take_div_reset(&mut self) -> bool {
+        std::mem::replace(&mut self.div_reset, false)
+    }
+
     pub fn reset(&mut self) {
         self.cartridge.reset();
+        self.breakpoint_hit = false;
+        self.ly_reset = false;
+        self.div_reset = false;
 
         for b in self.vram.iter_mut() {
             *b = 0;
@@ -96,17 +396,66 @@ impl GameboyMemory {
         }
 
         self.ie = 0;
+        self.apply_power_on_state();
+        self.update_bootrom_enabled();
+        self.sgb.reset();
 
         if let Ok(mut lock) = self.serial_output.write() {
             lock.clear();
         }
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.oam);
+
+        for reg in self.io.iter() {
+            state.write_u8(reg.get());
+        }
+
+        state.write_bytes(&self.hram);
+        state.write_u8(self.ie);
+
+        self.cartridge.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        let saved = SavedMemory::read(state)?;
+
+        state::copy_saved(&mut self.vram, saved.vram)?;
+        state::copy_saved(&mut self.wram, saved.wram)?;
+        state::copy_saved(&mut self.oam, saved.oam)?;
+        state::copy_saved(&mut self.hram, saved.hram)?;
+
+        for (reg, value) in self.io.iter().zip(saved.io.iter()) {
+            reg.set(*value);
+        }
+
+        self.ie = saved.ie;
+        self.update_bootrom_enabled();
+
+        self.breakpoint_hit = false;
+        self.ly_reset = false;
+        self.div_reset = false;
+        self.sgb.reset();
+
+        self.cartridge.load_state(state)
+    }
+
+    // Like read(), but trips read breakpoints. For components reading on the game's behalf, like OAM DMA.
+    // read() itself doesn't check them, the PPU and the debugger windows go through it too.
+    pub fn watched_read(&mut self, address: u16) -> u8 {
+        if self.read_breakpoints.contains(&address) {
+            self.breakpoint_hit = true;
+        }
+
+        self.read(address)
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         if CARTRIDGE_ROM.contains(&address) {
-            let bootrom_enabled = self.io[0x0050].read() & 1 == 0;
-
-            if bootrom_enabled {
+            if self.bootrom_enabled {
                 if address >= self.bootrom.len() as u16 {
                     self.cartridge.read(address)
                 }
@@ -143,6 +492,11 @@ impl GameboyMemory {
                     return lock.get_buttons();
                 }
             }
+            else if address == 0xFF44 {
+                if let Some(value) = self.ly_override {
+                    return value;
+                }
+            }
 
             self.io[address as usize - 0xFF00].read()
         }
@@ -155,7 +509,12 @@ impl GameboyMemory {
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
+        if self.write_breakpoints.contains(&address) {
+            self.breakpoint_hit = true;
+        }
+
         if CARTRIDGE_ROM.contains(&address) {
+            self.cartridge.bank_history_mut().set_pc(self.cpu_pc);
             self.cartridge.write(address, value);
         }
         else if VRAM.contains(&address) {
@@ -179,15 +538,56 @@ impl GameboyMemory {
         }
         else if IO.contains(&address) {
             if address == 0xFF00 {
+                self.sgb.joypad_write(value);
+
                 if let Ok(mut lock) = self.gb_joy.write() {
                     lock.set_value(value);
                     return;
                 }
             }
-            else if address == 0xFF01 {
-                if let Ok(mut lock) = self.serial_output.write() {
-                    lock.push(value);
+            else if address == 0xFF02 {
+                self.io[0x02].write(value);
+
+                if value & 0x80 != 0 {
+                    self.serial_started_at = self.gb_cyc.read().map(|cycles| *cycles).unwrap_or(0);
+
+                    if value & 0x01 != 0 {
+                        self.serial_link.send(self.io[0x01].get(), self.serial_started_at);
+                    }
+                }
+
+                return;
+            }
+            // Any write to DIV resets it, regardless of the value. The CPU restarts
+            // its internal counter too, otherwise the next increment would come early.
+            else if address == 0xFF04 {
+                self.io[0x04].set(0);
+                self.div_reset = true;
+                return;
+            }
+            // Same for LY, which also restarts the PPU's timing for the line.
+            else if address == 0xFF44 {
+                self.ly_reset = true;
+                return;
+            }
+            // Unmapping the bootrom is one-way, only a reset maps it back.
+            else if address == 0xFF50 {
+                if self.bootrom_enabled {
+                    self.io[0x50].write(value);
+                    self.update_bootrom_enabled();
                 }
+
+                return;
+            }
+            else if address == DEBUG_CHANNEL_ADDRESS && self.debug_channel {
+                let cycles = self.gb_cyc.read().map(|cycles| *cycles).unwrap_or(0);
+
+                if let Ok(mut lock) = self.debug_output.write() {
+                    // C strings end with a 0, so take it as the end of the line too.
+                    lock.push(if value == 0 {0x0A} else {value}, cycles);
+                }
+
+                return;
             }
 
             self.io[address as usize - 0xFF00].write(value);
@@ -200,11 +600,57 @@ impl GameboyMemory {
         }
     }
 
+    /// Same as read(), but $4000-$7FFF come from rom_bank instead of the mapped bank, if there's one.
+    pub fn dbg_read_banked(&self, address: u16, rom_bank: Option<usize>) -> u8 {
+        match rom_bank {
+            Some(bank) if CARTRIDGE_ROM_BANKX.contains(&address) => self.cartridge.read_rom_bank(bank, address - 0x4000),
+            _ => self.read(address)
+        }
+    }
+
+    /// Writes `value` to every address from `start` to `end`, both included.
+    pub fn dbg_fill(&mut self, start: u16, end: u16, value: u8) -> Result<(), String> {
+        if end < start {
+            return Err(format!("The end address (${:04X}) is before the start (${:04X}).", end, start));
+        }
+
+        for address in start..=end {
+            self.dbg_write(address, value);
+        }
+
+        Ok(())
+    }
+
+    /// Copies `length` bytes from `source` to `dest`, returns whether the two ranges overlapped.
+    /// The source gets read in full before writing anything, so overlapping copies still work.
+    pub fn dbg_copy(&mut self, source: u16, dest: u16, length: u16) -> Result<bool, String> {
+        if length == 0 {
+            return Err(String::from("Nothing to copy, the length is 0."));
+        }
+
+        let source_end = source as usize + length as usize - 1;
+        let dest_end = dest as usize + length as usize - 1;
+
+        if source_end > 0xFFFF {
+            return Err(format!("The source range goes past $FFFF (${:04X} + ${:04X}).", source, length));
+        }
+
+        if dest_end > 0xFFFF {
+            return Err(format!("The destination range goes past $FFFF (${:04X} + ${:04X}).", dest, length));
+        }
+
+        let data: Vec<u8> = (source..=source_end as u16).map(|address| self.read(address)).collect();
+
+        for (offset, value) in data.into_iter().enumerate() {
+            self.dbg_write(dest + offset as u16, value);
+        }
+
+        Ok(source as usize <= dest_end && dest as usize <= source_end)
+    }
+
     pub fn dbg_write(&mut self, address: u16, value: u8) {
         if CARTRIDGE_ROM.contains(&address) {
-            let bootrom_enabled = self.read(0xFF50) == 0;
-
-            if bootrom_enabled {
+            if self.bootrom_enabled {
                 if address >= self.bootrom.len() as u16 {
                     self.cartridge.dbg_write(address, value);
                 }
@@ -237,6 +683,10 @@ impl GameboyMemory {
         }
         else if IO.contains(&address) {
             self.io[address as usize - 0xFF00].set(value);
+
+            if address == 0xFF50 {
+                self.update_bootrom_enabled();
+            }
         }
         else if HRAM.contains(&address) {
             self.hram[address as usize - 0xFF80] = value;
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::log;
+
+pub const DEFAULT_SERIAL_SCROLLBACK: usize = 1000;
+// Longer lines wrap, so a game that never sends a line break can't grow one without bounds.
+pub const MAX_SERIAL_LINE_LENGTH: usize = 256;
+
+// With the internal clock, 8 bits at 8192Hz.
+pub const SERIAL_TRANSFER_CYCLES: usize = 4096;
+
+pub struct SerialLine {
+    // Value of the master clock when the first byte of the line was sent.
+    cycles: usize,
+    data: Vec<u8>,
+    finished: bool
+}
+
+impl SerialLine {
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// The bytes sent on this line, without the line break.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// Bytes sent over the serial port, split in lines.
+// Only the last `max_lines` lines are kept around, older ones get dropped.
+pub struct SerialLog {
+    lines: VecDeque<SerialLine>,
+    max_lines: usize
+}
+
+impl Default for SerialLog {
+    fn default() -> SerialLog {
+        SerialLog {
+            lines: VecDeque::new(),
+            max_lines: DEFAULT_SERIAL_SCROLLBACK
+        }
+    }
+}
+
+impl SerialLog {
+    pub fn lines(&self) -> &VecDeque<SerialLine> {
+        &self.lines
+    }
+
+    pub fn max_lines(&self) -> usize {
+        self.max_lines
+    }
+
+    pub fn set_max_lines(&mut self, max_lines: usize) {
+        self.max_lines = max_lines.max(1);
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn push(&mut self, value: u8, cycles: usize) {
+        // A line break right after a full line finishes it, instead of adding an empty one.
+        let needs_line = match self.lines.back() {
+            Some(line) => line.finished || (line.data.len() >= MAX_SERIAL_LINE_LENGTH && value != 0x0A),
+            None => true
+        };
+
+        if needs_line {
+            self.lines.push_back(SerialLine { cycles, data: Vec::new(), finished: false });
+            self.trim();
+        }
+
+        if let Some(line) = self.lines.back_mut() {
+            if value == 0x0A {
+                line.finished = true;
+            }
+            else {
+                line.data.push(value);
+            }
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+    }
+}
+
+// What's on the other end of the link cable.
+//
+// The side using its internal clock starts a transfer with send(), and finishes it once
+// receive() hands back the other side's byte. The side using an external clock waits for
+// receive() to give it a byte, and answers it with send().
+pub trait SerialLink {
+    // cycles is the value of the master clock, only used for logging.
+    fn send(&mut self, value: u8, cycles: usize);
+    fn receive(&mut self) -> Option<u8>;
+    fn status(&self) -> String;
+}
+
+// Nothing connected, every sent byte ends up in the serial output log.
+// Like on hardware, transfers clocked by this side read back $FF,
+// and the ones waiting for an external clock never finish.
+pub struct SerialCapture {
+    log: Arc<RwLock<SerialLog>>,
+    reply: Option<u8>
+}
+
+impl SerialCapture {
+    pub fn new(log: Arc<RwLock<SerialLog>>) -> SerialCapture {
+        SerialCapture {
+            log,
+            reply: None
+        }
+    }
+}
+
+impl SerialLink for SerialCapture {
+    fn send(&mut self, value: u8, cycles: usize) {
+        if let Ok(mut lock) = self.log.write() {
+            lock.push(value, cycles);
+        }
+
+        self.reply = Some(0xFF);
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.reply.take()
+    }
+
+    fn status(&self) -> String {
+        String::from("Not connected")
+    }
+}
+
+// A link cable to another instance of the emulator, one byte per transfer.
+// The socket never blocks, bytes that can't go through yet wait in the buffers.
+pub struct TcpLink {
+    // Only used by the side that listens, to take new connections.
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+
+    incoming: VecDeque<u8>,
+    outgoing: Vec<u8>
+}
+
+impl TcpLink {
+    pub fn listen(port: u16) -> Result<TcpLink, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        Ok(TcpLink {
+            listener: Some(listener),
+            stream: None,
+
+            incoming: VecDeque::new(),
+            outgoing: Vec::new()
+        })
+    }
+
+    pub fn connect(host: &str, port: u16) -> Result<TcpLink, String> {
+        let address = (host, port).to_socket_addrs().map_err(|e| e.to_string())?.next();
+        let address = address.ok_or(format!("Couldn't resolve {}.", host))?;
+        let stream = TcpStream::connect_timeout(&address, Duration::from_secs(5)).map_err(|e| e.to_string())?;
+
+        TcpLink::setup_stream(&stream).map_err(|e| e.to_string())?;
+
+        Ok(TcpLink {
+            listener: None,
+            stream: Some(stream),
+
+            incoming: VecDeque::new(),
+            outgoing: Vec::new()
+        })
+    }
+
+    fn setup_stream(stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nonblocking(true)?;
+        // Every transfer is a single byte, waiting to fill packets would only add latency.
+        stream.set_nodelay(true)
+    }
+
+    fn poll(&mut self) {
+        if self.stream.is_none() {
+            if let Some(listener) = self.listener.as_ref() {
+                if let Ok((stream, peer)) = listener.accept() {
+                    if TcpLink::setup_stream(&stream).is_ok() {
+                        log::info("Link cable", &format!("{} connected.", peer));
+                        self.stream = Some(stream);
+                    }
+                }
+            }
+        }
+
+        let mut disconnected = false;
+
+        if let Some(stream) = self.stream.as_mut() {
+            if !self.outgoing.is_empty() {
+                match stream.write(&self.outgoing) {
+                    Ok(written) => {
+                        self.outgoing.drain(..written);
+                    }
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => disconnected = true
+                }
+            }
+
+            let mut buffer = [0; 64];
+
+            loop {
+                match stream.read(&mut buffer) {
+                    Ok(0) => {
+                        disconnected = true;
+                        break;
+                    }
+                    Ok(read) => self.incoming.extend(&buffer[..read]),
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if disconnected {
+            log::warn("Link cable", "The other side disconnected.");
+
+            self.stream = None;
+            self.outgoing.clear();
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn send(&mut self, value: u8, _cycles: usize) {
+        self.outgoing.push(value);
+        self.poll();
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        if self.incoming.is_empty() {
+            self.poll();
+        }
+
+        self.incoming.pop_front()
+    }
+
+    fn status(&self) -> String {
+        match (self.stream.as_ref(), self.listener.as_ref()) {
+            (Some(stream), _) => {
+                match stream.peer_addr() {
+                    Ok(peer) => format!("Connected to {}", peer),
+                    Err(_) => String::from("Connected")
+                }
+            }
+            (None, Some(listener)) => {
+                match listener.local_addr() {
+                    Ok(address) => format!("Waiting for a connection on port {}", address.port()),
+                    Err(_) => String::from("Waiting for a connection")
+                }
+            }
+            (None, None) => String::from("Disconnected")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_wrap() {
+        let mut log = SerialLog::default();
+
+        for _ in 0..MAX_SERIAL_LINE_LENGTH * 2 + 10 {
+            log.push(b'a', 0);
+        }
+
+        let lengths: Vec<usize> = log.lines().iter().map(|line| line.data().len()).collect();
+        assert_eq!(lengths, vec![MAX_SERIAL_LINE_LENGTH, MAX_SERIAL_LINE_LENGTH, 10]);
+
+        // A line break after a full line doesn't leave an empty one behind.
+        let mut log = SerialLog::default();
+
+        for _ in 0..MAX_SERIAL_LINE_LENGTH {
+            log.push(b'a', 0);
+        }
+
+        log.push(0x0A, 0);
+        log.push(b'b', 0);
+
+        let lengths: Vec<usize> = log.lines().iter().map(|line| line.data().len()).collect();
+        assert_eq!(lengths, vec![MAX_SERIAL_LINE_LENGTH, 1]);
+    }
+}
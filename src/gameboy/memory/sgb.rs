@@ -0,0 +1,158 @@
+// Super Game Boy packets are sent by pulsing P14/P15 in the joypad register.
+// A DMG doesn't do anything with them, but SGB-aware games still send them to
+// probe for an SGB, so the transfers are followed here to be able to report them.
+// Borders and palettes aren't implemented, the packets are just consumed.
+
+const PACKET_BITS: usize = 128;
+
+#[derive(PartialEq)]
+enum TransferState {
+    Idle,
+    WaitingPulse,
+    WaitingBit,
+    WaitingStopBit
+}
+
+pub struct SgbPacketReader {
+    state: TransferState,
+
+    bits: usize,
+    packet: [u8; 16],
+    // Packets left to receive for the current command.
+    packets_left: u8,
+
+    packets_received: usize,
+    last_command: Option<u8>
+}
+
+impl Default for SgbPacketReader {
+    fn default() -> SgbPacketReader {
+        SgbPacketReader {
+            state: TransferState::Idle,
+
+            bits: 0,
+            packet: [0; 16],
+            packets_left: 0,
+
+            packets_received: 0,
+            last_command: None
+        }
+    }
+}
+
+impl SgbPacketReader {
+    pub fn reset(&mut self) {
+        *self = SgbPacketReader::default();
+    }
+
+    /// Returns true once at least one complete SGB packet was sent by the game.
+    pub fn detected(&self) -> bool {
+        self.packets_received > 0
+    }
+
+    pub fn packets_received(&self) -> usize {
+        self.packets_received
+    }
+
+    /// Gets the name of the last SGB command sent by the game.
+    pub fn last_command(&self) -> Option<&'static str> {
+        self.last_command.map(command_name)
+    }
+
+    pub fn joypad_write(&mut self, value: u8) {
+        let lines = value & 0x30;
+
+        // Both lines low, start of a packet.
+        if lines == 0x00 {
+            self.state = TransferState::WaitingPulse;
+            self.bits = 0;
+            self.packet = [0; 16];
+
+            return;
+        }
+
+        match self.state {
+            TransferState::Idle => {}
+            TransferState::WaitingPulse | TransferState::WaitingStopBit if lines == 0x30 => {}
+            TransferState::WaitingPulse => {
+                // A bit without the reset pulse being released first, not a packet.
+                self.state = TransferState::Idle;
+            }
+            TransferState::WaitingBit => {
+                if lines == 0x30 {
+                    return;
+                }
+
+                // P15 low sends a 1, P14 low sends a 0.
+                if lines == 0x10 {
+                    self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+                }
+
+                self.bits += 1;
+
+                if self.bits == PACKET_BITS {
+                    self.state = TransferState::WaitingStopBit;
+                }
+                else {
+                    self.state = TransferState::WaitingPulse;
+                }
+
+                return;
+            }
+            TransferState::WaitingStopBit => {
+                // The stop bit is always a 0.
+                if lines == 0x20 {
+                    self.finish_packet();
+                }
+
+                self.state = TransferState::Idle;
+                return;
+            }
+        }
+
+        if self.state == TransferState::WaitingPulse && lines == 0x30 {
+            self.state = TransferState::WaitingBit;
+        }
+    }
+
+    fn finish_packet(&mut self) {
+        if self.packets_left == 0 {
+            self.last_command = Some(self.packet[0] >> 3);
+            self.packets_left = (self.packet[0] & 7).max(1);
+        }
+
+        self.packets_left -= 1;
+        self.packets_received += 1;
+    }
+}
+
+fn command_name(command: u8) -> &'static str {
+    match command {
+        0x00 => "PAL01",
+        0x01 => "PAL23",
+        0x02 => "PAL03",
+        0x03 => "PAL12",
+        0x04 => "ATTR_BLK",
+        0x05 => "ATTR_LIN",
+        0x06 => "ATTR_DIV",
+        0x07 => "ATTR_CHR",
+        0x08 => "SOUND",
+        0x09 => "SOU_TRN",
+        0x0A => "PAL_SET",
+        0x0B => "PAL_TRN",
+        0x0C => "ATRC_EN",
+        0x0D => "TEST_EN",
+        0x0E => "ICON_EN",
+        0x0F => "DATA_SND",
+        0x10 => "DATA_TRN",
+        0x11 => "MLT_REQ",
+        0x12 => "JUMP",
+        0x13 => "CHR_TRN",
+        0x14 => "PCT_TRN",
+        0x15 => "ATTR_TRN",
+        0x16 => "ATTR_SET",
+        0x17 => "MASK_EN",
+        0x18 => "OBJ_TRN",
+        _ => "Unknown"
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::regions::*;
+
+pub const REGION_COUNT: usize = 8;
+pub const REGION_NAMES: [&str; REGION_COUNT] = ["ROM0", "ROMX", "VRAM", "CRAM", "WRAM", "OAM", "IO", "HRAM"];
+
+// Index of the region the address belongs to, in REGION_NAMES.
+// Echo RAM counts as WRAM, the unused area after OAM as OAM, and IE as IO.
+fn region_index(address: u16) -> usize {
+    if CARTRIDGE_ROM_BANK0.contains(&address) {
+        0
+    }
+    else if CARTRIDGE_ROM_BANKX.contains(&address) {
+        1
+    }
+    else if VRAM.contains(&address) {
+        2
+    }
+    else if CARTRIDGE_RAM.contains(&address) {
+        3
+    }
+    else if WRAM.contains(&address) || ECHO.contains(&address) {
+        4
+    }
+    else if address < 0xFF00 {
+        5
+    }
+    else if HRAM.contains(&address) {
+        7
+    }
+    else {
+        6
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct RegionAccesses {
+    pub reads: u32,
+    pub writes: u32
+}
+
+// Reads and writes to each region, counted over a frame.
+// Disabled by default, so normal play doesn't pay for it.
+// Counted with atomics, since the CPU only holds a read lock on the memory for reads.
+#[derive(Default)]
+pub struct AccessStats {
+    enabled: bool,
+    reads: [AtomicU32; REGION_COUNT],
+    writes: [AtomicU32; REGION_COUNT],
+    // Totals of the last finished frame.
+    last_frame: Option<[RegionAccesses; REGION_COUNT]>
+}
+
+impl AccessStats {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    pub fn record_read(&self, address: u16) {
+        if self.enabled {
+            self.reads[region_index(address)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_write(&self, address: u16) {
+        if self.enabled {
+            self.writes[region_index(address)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Called when a frame ends, keeps its totals and starts counting again.
+    pub fn finish_frame(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut totals = [RegionAccesses::default(); REGION_COUNT];
+
+        for (idx, total) in totals.iter_mut().enumerate() {
+            total.reads = self.reads[idx].swap(0, Ordering::Relaxed);
+            total.writes = self.writes[idx].swap(0, Ordering::Relaxed);
+        }
+
+        self.last_frame = Some(totals);
+    }
+
+    /// Get the accesses of the last finished frame, in the same order as REGION_NAMES.
+    pub fn last_frame(&self) -> Option<&[RegionAccesses; REGION_COUNT]> {
+        self.last_frame.as_ref()
+    }
+
+    pub fn clear(&mut self) {
+        for counter in self.reads.iter().chain(self.writes.iter()) {
+            counter.store(0, Ordering::Relaxed);
+        }
+
+        self.last_frame = None;
+    }
+}
@@ -1,28 +1,89 @@
 mod cpu;
+pub mod callstack;
 pub mod ppu;
 pub mod memory;
 pub mod disassembler;
+pub mod state;
 
 use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
+use callstack::Callstack;
 use cpu::GameboyCPU;
-use ppu::GameboyPPU;
+use ppu::{GameboyPPU, Renderer};
+use ppu::recorder::FrameRecorder;
 
-use memory::GameboyMemory;
+use memory::{GameboyMemory, PowerOnState};
+use memory::dma::DmaLog;
 use memory::cart::CartHeader;
+use memory::cart::hashes::RomHashes;
+use memory::serial::{SerialLink, SerialLog};
+use state::{StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+
+use crate::log;
+
+// Instructions run per lock of the emulator while running, roughly a scanline.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+// Master clock of each model, in Hz. The SGB derives it from the SNES clock, so it runs a bit faster.
+// Everything inside the emulator (timer, serial, PPU) counts cycles of this clock, the
+// speed only matters when turning cycles into real time, like for frame pacing.
+pub const DMG_CLOCK_HZ: u32 = 4194304;
+pub const SGB_CLOCK_HZ: u32 = 4295454;
 
 pub struct Gameboy {
+    // The master clock, in T-cycles, shared by every component.
+    // Only the CPU advances it (after each instruction, or while halted), and only
+    // gb_reset() and load_state() set it back. Everything else treats it as read-only:
+    // keep the value you last synced at, work with the elapsed difference,
+    // and resync if the clock ever goes backwards.
     gb_cyc: Arc<RwLock<usize>>,
     gb_cpu: Arc<RwLock<GameboyCPU>>,
     gb_ppu: Arc<RwLock<GameboyPPU>>,
     gb_mem: Arc<RwLock<GameboyMemory>>,
     gb_joy: Arc<RwLock<JoypadHandler>>,
+    // Filled in by a background thread, see start_rom_hashing().
+    rom_hashes: Arc<RwLock<Option<RomHashes>>>,
+
+    // Instructions to run every time the emulation thread grabs the lock.
+    // Bigger batches mean less contention with the UI, but a less responsive debugger.
+    pub batch_size: usize,
+    // Seconds between saves of the cart RAM to disk while running, 0 disables them.
+    // Only RAM that was written to since the last save gets saved.
+    pub ram_autosave_interval: u64,
 
     pub dbg_mode: EmulatorMode,
     pub dbg_do_step: bool,
-    pub dbg_breakpoint_list: Vec<Breakpoint>
+    // Instructions left to run before going back to stepping, see dbg_step_instructions().
+    pub dbg_step_count: usize,
+    // Clock value to go back to stepping at, see dbg_step_cycles().
+    pub dbg_cycle_target: Option<usize>,
+    // Set while running until the next V-Blank, see dbg_step_frame().
+    pub dbg_frame_step: bool,
+    pub dbg_breakpoint_list: Vec<Breakpoint>,
+    // One entry per address, set when an instruction is fetched from it.
+    pub dbg_coverage: Vec<bool>,
+
+    // Addresses written back to their value after every frame, as (address, value).
+    pub freeze_list: Vec<(u16, u8)>,
+
+    // Set while recording, gets a copy of every finished frame.
+    recorder: Option<FrameRecorder>,
+    // Set while tracing, gets a Gameboy Doctor line before every instruction. See start_trace().
+    trace: Option<BufWriter<File>>,
+    trace_lines: usize,
+    // Set by the PPU when a frame ends, the emulation thread sleeps for it after letting go of the lock.
+    frame_sleep: Option<Duration>,
+    // Runs the bootrom without sleeping between frames. Unlike skipping it, the
+    // cartridge still starts with the state and clock the bootrom leaves behind.
+    fast_boot: bool
 }
 
 impl Gameboy {
@@ -32,16 +93,47 @@ impl Gameboy {
         let gb_ppu = Arc::new(RwLock::new(GameboyPPU::init(gb_cyc.clone(), gb_mem.clone())));
         let gb_joy = gb_mem.read().unwrap().gb_joy();
 
-        Gameboy {
+        gb_mem.write().unwrap().set_clock(gb_cyc.clone());
+
+        let mut gameboy = Gameboy {
             gb_cyc,
             gb_cpu,
             gb_ppu,
             gb_mem,
             gb_joy,
+            rom_hashes: Arc::new(RwLock::new(None)),
+
+            batch_size: DEFAULT_BATCH_SIZE,
+            ram_autosave_interval: 0,
 
             dbg_mode: EmulatorMode::Paused,
             dbg_do_step: false,
-            dbg_breakpoint_list: Vec::new()
+            dbg_step_count: 0,
+            dbg_cycle_target: None,
+            dbg_frame_step: false,
+            dbg_breakpoint_list: Vec::new(),
+            dbg_coverage: vec![false; 0x10000],
+
+            freeze_list: Vec::new(),
+
+            recorder: None,
+            trace: None,
+            trace_lines: 0,
+            frame_sleep: None,
+            fast_boot: false
+        };
+
+        gameboy.gb_skip_bootrom();
+        gameboy
+    }
+
+    // Without a bootrom, start straight at $0100 with the state it would've left.
+    fn gb_skip_bootrom(&mut self) {
+        if let Ok(mut mem_lock) = self.gb_mem.write() {
+            if !mem_lock.has_bootrom() {
+                mem_lock.skip_bootrom();
+                self.gb_cpu.write().unwrap().skip_bootrom();
+            }
         }
     }
 
@@ -51,18 +143,63 @@ impl Gameboy {
         std::thread::spawn(move || {
             let exit_rx = rx;
             let gameboy = gameboy;
+            let mut last_ram_save = std::time::Instant::now();
     
             loop {
+                let mut frame_sleep = None;
+
                 if let Ok(mut lock) = gameboy.try_write() {
                     if lock.dbg_mode == EmulatorMode::Running {
-                        lock.gb_cpu_cycle();
-                        lock.gb_ppu_cycle();
+                        for _ in 0..lock.batch_size.max(1) {
+                            lock.gb_cpu_cycle();
+                            let frame_finished = lock.gb_ppu_cycle();
+
+                            if lock.dbg_frame_step {
+                                if lock.dbg_mode != EmulatorMode::Running {
+                                    lock.dbg_frame_step = false;
+                                }
+                                else if frame_finished {
+                                    lock.dbg_frame_step = false;
+                                    lock.dbg_mode = EmulatorMode::Paused;
+                                }
+                            }
+
+                            if lock.dbg_step_count > 0 {
+                                lock.dbg_step_count -= 1;
+
+                                if lock.dbg_mode != EmulatorMode::Running {
+                                    // Something else (like a breakpoint) stopped execution first.
+                                    lock.dbg_step_count = 0;
+                                }
+                                else if lock.dbg_step_count == 0 {
+                                    lock.dbg_mode = EmulatorMode::Stepping;
+                                }
+                            }
+
+                            // Breakpoints and the like end the batch early, so the UI sees them right away.
+                            // So does the end of a frame, to sleep without holding the lock.
+                            if lock.dbg_mode != EmulatorMode::Running || lock.frame_sleep.is_some() {
+                                break;
+                            }
+                        }
                     }
                     else if lock.dbg_mode == EmulatorMode::Stepping && lock.dbg_do_step {
                         lock.gb_cpu_cycle();
                         lock.gb_ppu_cycle();
                         lock.dbg_do_step = false;
                     }
+
+                    if lock.ram_autosave_interval > 0 && last_ram_save.elapsed().as_secs() >= lock.ram_autosave_interval {
+                        lock.gb_save_battery();
+                        last_ram_save = std::time::Instant::now();
+                    }
+
+                    // Once paused there's no frame to wait for, so don't make the UI wait either.
+                    frame_sleep = lock.frame_sleep.take().filter(|_| lock.dbg_mode == EmulatorMode::Running);
+                }
+
+                if let Some(duration) = frame_sleep {
+                    std::thread::sleep(duration);
                 }
 
                 if exit_rx.try_recv().is_ok() {
@@ -74,6 +211,9 @@ impl Gameboy {
         tx
     }
 
+    /// Puts the hardware back to its power-on state and leaves the emulator paused.
+    /// Debugger state is kept, like breakpoints, coverage, frozen addresses, the LY breakpoint
+    /// and the recorded logs. The callstack is the exception, the CPU clears it since it belongs to the previous run.
     pub fn gb_reset(&mut self) {
         self.gb_cpu.write().unwrap().reset();
         self.gb_mem.write().unwrap().reset();
@@ -82,19 +222,454 @@ impl Gameboy {
             *cycles = 0;
         }
 
+        self.gb_skip_bootrom();
+
         self.dbg_mode = EmulatorMode::Paused;
+        self.dbg_step_count = 0;
+        self.dbg_cycle_target = None;
+        self.dbg_frame_step = false;
+    }
+
+    /// Saves the cart RAM to disk if it changed since the last save.
+    pub fn gb_save_battery(&mut self) {
+        if let Ok(mut lock) = self.gb_mem.write() {
+            lock.cartridge_mut().save_battery();
+        }
+    }
+
+    /// Changes what memory holds at power-on, and resets the machine to apply it.
+    pub fn gb_set_power_on_state(&mut self, state: PowerOnState) {
+        self.gb_mem.write().unwrap().set_power_on_state(state);
+        self.gb_reset();
+    }
+
+    /// Whether unknown opcodes should run as NOPs, instead of stopping the emulator.
+    pub fn set_skip_unknown_opcodes(&mut self, skip: bool) {
+        self.gb_cpu.write().unwrap().set_skip_unknown_opcodes(skip);
+    }
+
+    /// Changes the emulated model, and resets the machine to apply it.
+    pub fn gb_set_model(&mut self, model: Model) {
+        self.gb_cpu.write().unwrap().set_model(model);
+        self.gb_reset();
+    }
+
+    /// Same as gb_reset(), but starts running the game again right away.
+    /// Reloading the ROM and bootrom files from disk is handled by the UI instead.
+    pub fn gb_restart(&mut self) {
+        self.gb_reset();
+        self.dbg_mode = EmulatorMode::Running;
+    }
+
+    /// Serializes the state of the machine. Debugger state isn't included.
+    /// StateSnapshot::parse() reads the CPU and memory parts back with the same readers the components use.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+
+        state.write_bytes(STATE_MAGIC);
+        state.write_u8(STATE_VERSION);
+
+        if let Ok(lock) = self.gb_mem.read() {
+            state.write_bytes(lock.header().title().as_bytes());
+        }
+
+        if let Ok(lock) = self.gb_cyc.read() {
+            state.write_usize(*lock);
+        }
+
+        self.gb_cpu.read().unwrap().save_state(&mut state);
+        self.gb_mem.read().unwrap().save_state(&mut state);
+        self.gb_ppu.read().unwrap().save_state(&mut state);
+
+        state.into_data()
+    }
+
+    /// Loads a state made by save_state(). States from other games or
+    /// versions are rejected, and a failed load leaves the machine untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+
+        if state.read_bytes() != Some(&STATE_MAGIC[..]) {
+            return Err(String::from("Not a save state file."));
+        }
+
+        if state.read_u8() != Some(STATE_VERSION) {
+            return Err(String::from("The save state was made by a different version of the emulator."));
+        }
+
+        let title = self.gb_mem.read().unwrap().header().title().to_string();
+
+        if state.read_bytes() != Some(title.as_bytes()) {
+            return Err(String::from("The save state belongs to a different game."));
+        }
+
+        let backup = self.save_state();
+
+        if self.load_components(&mut state).is_none() {
+            // The header of the backup was already checked, so skip straight to the components.
+            let mut backup_state = StateReader::new(&backup);
+
+            backup_state.read_bytes();
+            backup_state.read_u8();
+            backup_state.read_bytes();
+            self.load_components(&mut backup_state);
+
+            return Err(String::from("The save state is truncated or corrupted."));
+        }
+
+        self.dbg_step_count = 0;
+        self.dbg_cycle_target = None;
+        self.dbg_frame_step = false;
+
+        Ok(())
+    }
+
+    fn load_components(&mut self, state: &mut StateReader) -> Option<()> {
+        let cycles = state.read_usize()?;
+
+        if let Ok(mut lock) = self.gb_cyc.write() {
+            *lock = cycles;
+        }
+
+        self.gb_cpu.write().unwrap().load_state(state)?;
+        self.gb_mem.write().unwrap().load_state(state)?;
+        self.gb_ppu.write().unwrap().load_state(state)
+    }
+
+    /// Runs `count` instructions in one go and goes back to stepping afterwards.
+    /// Unlike regular steps, breakpoints are honored and can stop it early.
+    pub fn dbg_step_instructions(&mut self, count: usize) {
+        if count > 0 {
+            self.dbg_step_count = count;
+            self.dbg_mode = EmulatorMode::Running;
+        }
+    }
+
+    /// Runs for at least `count` cycles and goes back to stepping afterwards.
+    /// It stops at the first instruction boundary past the target, breakpoints can stop it early.
+    pub fn dbg_step_cycles(&mut self, count: usize) {
+        if count > 0 {
+            self.dbg_cycle_target = Some(self.gb_cyc.read().map(|cycles| *cycles).unwrap_or(0) + count);
+            self.dbg_mode = EmulatorMode::Running;
+        }
     }
 
     pub fn gb_cpu_cycle(&mut self) {
+        let joypad_interrupt = self.gb_joy.write().map(|mut lock| lock.take_interrupt()).unwrap_or(false);
+
+        // Set on the register itself, it's the hardware writing it and not the CPU.
+        if joypad_interrupt {
+            if let Ok(lock) = self.gb_mem.read() {
+                let if_reg = lock.get_io_reg(0xFF0F);
+                if_reg.set(if_reg.get() | 0x10);
+            }
+        }
+
+        if self.trace.is_some() && !self.dbg_cpu_halted() {
+            self.write_trace_line();
+        }
+
         if let Ok(mut lock) = self.gb_cpu.write() {
             lock.cpu_cycle(&self.dbg_breakpoint_list, &mut self.dbg_mode);
+
+            match (&self.dbg_mode, lock.executed_pc()) {
+                (EmulatorMode::BreakpointHit, _) | (EmulatorMode::Locked(_), _) => {}
+                (_, Some(pc)) => self.dbg_coverage[pc as usize] = true,
+                (_, None) => {}
+            }
+        }
+
+        let mut ly_reset = false;
+        let mut div_reset = false;
+
+        // Catches accesses the CPU didn't check for itself, like the ones from OAM DMA.
+        if let Ok(mut lock) = self.gb_mem.write() {
+            lock.serial_step();
+            ly_reset = lock.take_ly_reset();
+            div_reset = lock.take_div_reset();
+
+            if lock.take_breakpoint_hit() && self.dbg_mode == EmulatorMode::Running {
+                self.dbg_mode = EmulatorMode::BreakpointHit;
+            }
+
+            lock.sync_breakpoints(&self.dbg_breakpoint_list);
+        }
+
+        if ly_reset {
+            if let Ok(mut lock) = self.gb_ppu.write() {
+                lock.reset_ly();
+            }
+        }
+
+        if div_reset {
+            if let Ok(mut lock) = self.gb_cpu.write() {
+                lock.reset_divider();
+            }
+        }
+
+        if let Some(target) = self.dbg_cycle_target {
+            if self.dbg_mode != EmulatorMode::Running {
+                // Something else (like a breakpoint) stopped execution first.
+                self.dbg_cycle_target = None;
+            }
+            else if self.gb_cyc.read().map(|cycles| *cycles >= target).unwrap_or(false) {
+                self.dbg_cycle_target = None;
+                self.dbg_mode = EmulatorMode::Stepping;
+            }
+        }
+    }
+
+    /// Runs until the PPU reaches the next V-Blank, then pauses.
+    /// Like dbg_step_instructions(), breakpoints can stop it early.
+    pub fn dbg_step_frame(&mut self) {
+        self.dbg_frame_step = true;
+        self.dbg_mode = EmulatorMode::Running;
+    }
+
+    pub fn dbg_reset_coverage(&mut self) {
+        for executed in self.dbg_coverage.iter_mut() {
+            *executed = false;
+        }
+    }
+
+    pub fn gb_ppu_cycle(&mut self) -> bool {
+        if let Ok(mut lock) = self.gb_ppu.write() {
+            let frame_finished = lock.ppu_cycle();
+
+            if let Some(duration) = lock.take_frame_sleep() {
+                let booting = self.fast_boot && self.gb_mem.read().map(|mem| mem.bootrom_mapped()).unwrap_or(false);
+
+                if !booting {
+                    self.frame_sleep = Some(duration);
+                }
+            }
+
+            if frame_finished {
+                self.apply_freezes();
+
+                if let Ok(mut mem_lock) = self.gb_mem.write() {
+                    mem_lock.access_stats_mut().finish_frame();
+                }
+
+                if let Some(recorder) = self.recorder.as_mut() {
+                    if let Ok(screen) = lock.get_screen_data().read() {
+                        recorder.push(&screen);
+                    }
+                }
+            }
+
+            if lock.take_ly_breakpoint_hit() && self.dbg_mode == EmulatorMode::Running {
+                self.dbg_mode = EmulatorMode::BreakpointHit;
+            }
+
+            frame_finished
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn set_fast_boot(&mut self, enabled: bool) {
+        self.fast_boot = enabled;
+    }
+
+    /// Keeps only the newest max_depth calls in the callstack.
+    pub fn set_callstack_max_depth(&mut self, max_depth: usize) {
+        if let Ok(mut lock) = self.ui_get_callstack().write() {
+            lock.set_max_depth(max_depth);
+        }
+    }
+
+    /// Changes how many cycles are run per real second, see DMG_CLOCK_HZ.
+    pub fn set_clock_speed(&mut self, hz: u32) {
+        if let Ok(mut lock) = self.gb_ppu.write() {
+            lock.set_clock_speed(hz);
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        if let Ok(mut lock) = self.gb_ppu.write() {
+            lock.set_renderer(renderer);
+        }
+    }
+
+    /// Runs without frame throttling, drawing only one in every frame_skip frames.
+    pub fn set_fast_forward(&mut self, enabled: bool, frame_skip: usize) {
+        if let Ok(mut lock) = self.gb_ppu.write() {
+            lock.set_fast_forward(enabled, frame_skip);
+        }
+    }
+
+    /// Starts keeping the last max_frames frames, dropping anything recorded before.
+    pub fn start_recording(&mut self, max_frames: usize) {
+        self.recorder = Some(FrameRecorder::new(max_frames));
+    }
+
+    /// Returns the recorded frames, oldest first.
+    pub fn stop_recording(&mut self) -> Vec<Vec<u8>> {
+        self.recorder.take().map(|recorder| recorder.into_frames()).unwrap_or_default()
+    }
+
+    /// Returns the amount of recorded frames, or None if not recording.
+    pub fn recorded_frames(&self) -> Option<usize> {
+        self.recorder.as_ref().map(|recorder| recorder.len())
+    }
+
+    /// Writes the CPU state before every instruction to the file at `path`, like dbg_write_cpu_state().
+    /// It's a line per instruction, so expect around 60MB for every second of emulation.
+    pub fn start_trace(&mut self, path: &Path) -> Result<(), String> {
+        self.stop_trace()?;
+
+        let file = File::create(path).map_err(|e| e.to_string())?;
+
+        self.trace = Some(BufWriter::new(file));
+        self.trace_lines = 0;
+
+        Ok(())
+    }
+
+    /// Flushes and closes the trace file, returns how many lines were written to it.
+    pub fn stop_trace(&mut self) -> Result<usize, String> {
+        if let Some(mut trace) = self.trace.take() {
+            trace.flush().map_err(|e| e.to_string())?;
+        }
+
+        Ok(self.trace_lines)
+    }
+
+    /// Returns the amount of traced instructions, or None if not tracing.
+    pub fn traced_lines(&self) -> Option<usize> {
+        self.trace.as_ref().map(|_| self.trace_lines)
+    }
+
+    fn write_trace_line(&mut self) {
+        let line = self.dbg_cpu_state().to_string();
+
+        if let Some(trace) = self.trace.as_mut() {
+            if let Err(error) = writeln!(trace, "{}", line) {
+                log::error("Trace", &format!("Stopped tracing after {} lines, couldn't write to the file ({}).", self.trace_lines, error));
+                self.trace = None;
+            }
+            else {
+                self.trace_lines += 1;
+            }
+        }
+    }
+
+    // Goes through dbg_write(), so freezing an address doesn't trigger mapper writes.
+    fn apply_freezes(&self) {
+        if self.freeze_list.is_empty() {
+            return;
+        }
+
+        if let Ok(mut lock) = self.gb_mem.write() {
+            for (address, value) in self.freeze_list.iter() {
+                lock.dbg_write(*address, *value);
+            }
         }
     }
 
-    pub fn gb_ppu_cycle(&mut self) {
+    /// Pauses the emulator when LY reaches the given scanline, or disables the breakpoint with None.
+    pub fn dbg_set_ly_breakpoint(&mut self, ly: Option<u8>) {
         if let Ok(mut lock) = self.gb_ppu.write() {
-            lock.ppu_cycle();
+            lock.set_ly_breakpoint(ly);
+        }
+    }
+
+    pub fn dbg_cpu_state(&self) -> CpuState {
+        let (af, bc, de, hl, sp, pc) = self.ui_get_cpu_registers();
+        let mut pc_mem = [0; 4];
+
+        if let Ok(lock) = self.gb_mem.read() {
+            for (offset, value) in pc_mem.iter_mut().enumerate() {
+                *value = lock.read(pc.wrapping_add(offset as u16));
+            }
+        }
+
+        CpuState { af, bc, de, hl, sp, pc, pc_mem }
+    }
+
+    /// Disassembles `context` instructions before and after PC, followed by the registers and flags.
+    /// Instructions before PC are found by guessing lengths backwards, so they can be off after data.
+    pub fn dbg_dump_state(&self, context: usize) -> String {
+        let state = self.dbg_cpu_state();
+        let mut start = state.pc;
+
+        for _ in 0..context {
+            let previous = (1..=3).rev()
+                .map(|offset| start.wrapping_sub(offset))
+                .find(|candidate| *candidate < start && candidate.wrapping_add(disassembler::get_instruction_data(*candidate, &self.gb_mem).0) == start)
+            ;
+
+            match previous {
+                Some(address) => start = address,
+                None => break
+            }
+        }
+
+        let mut dump = String::new();
+        let mut address = start;
+        let mut after_pc = 0;
+
+        while after_pc <= context {
+            let (len, dis) = disassembler::get_instruction_data(address, &self.gb_mem);
+            let marker = if address == state.pc {">"} else {" "};
+
+            dump.push_str(&format!("{} {:04X} - {}\n", marker, address, dis));
+
+            if address >= state.pc {
+                after_pc += 1;
+            }
+
+            address = match address.checked_add(len) {
+                Some(next) => next,
+                None => break
+            };
         }
+
+        let flags = state.af & 0xFF;
+        let flag = |mask: u16, name: char| if flags & mask != 0 {name} else {'-'};
+
+        dump.push_str(&format!(
+            "AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X}\nFlags: {}{}{}{}",
+            state.af, state.bc, state.de, state.hl, state.sp, state.pc,
+            flag(0x80, 'Z'), flag(0x40, 'N'), flag(0x20, 'H'), flag(0x10, 'C')
+        ));
+
+        dump
+    }
+
+    /// Whether the CPU is waiting for an interrupt, so the next cycle won't run an instruction.
+    pub fn dbg_cpu_halted(&self) -> bool {
+        self.gb_cpu.read().map(|lock| lock.is_halted()).unwrap_or(false)
+    }
+
+    /// Writes the current CPU state as a single Gameboy Doctor log line.
+    /// Call it before every instruction to get a trace comparable with the reference logs.
+    pub fn dbg_write_cpu_state<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()> {
+        writeln!(output, "{}", self.dbg_cpu_state())
+    }
+
+    /// Cycles run since power-on.
+    pub fn gb_get_cycles(&self) -> usize {
+        self.gb_cyc.read().map(|cycles| *cycles).unwrap_or(0)
+    }
+
+    /// Hashes the ROM file on another thread, big ROMs take a while.
+    pub fn start_rom_hashing(&self, data: Vec<u8>) {
+        let rom_hashes = self.rom_hashes.clone();
+
+        std::thread::spawn(move || {
+            let hashes = RomHashes::new(&data);
+
+            if let Ok(mut lock) = rom_hashes.write() {
+                *lock = Some(hashes);
+            }
+        });
+    }
+
+    pub fn ui_get_rom_hashes(&self) -> Arc<RwLock<Option<RomHashes>>> {
+        self.rom_hashes.clone()
     }
 
     pub fn ui_get_header(&self) -> Arc<CartHeader> {
@@ -105,19 +680,86 @@ impl Gameboy {
         self.gb_mem.clone()
     }
 
+    /// Reads a byte the way the CPU would see it, $FF if memory is unavailable.
+    pub fn read_memory(&self, address: u16) -> u8 {
+        if let Ok(lock) = self.gb_mem.read() {
+            lock.read(address)
+        }
+        else {
+            0xFF
+        }
+    }
+
+    /// Writes a byte with the debugger's semantics: ROM gets patched instead of talking to the MBC,
+    /// and IO registers are set without side effects.
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        if let Ok(mut lock) = self.gb_mem.write() {
+            lock.dbg_write(address, value);
+        }
+    }
+
+    /// Reads `len` bytes starting at `address`, wrapping around after $FFFF.
+    pub fn read_range(&self, address: u16, len: usize) -> Vec<u8> {
+        if let Ok(lock) = self.gb_mem.read() {
+            (0..len).map(|offset| lock.read(address.wrapping_add(offset as u16))).collect()
+        }
+        else {
+            vec![0xFF; len]
+        }
+    }
+
+    /// Writes every byte of `data` starting at `address` like write_memory(), wrapping around after $FFFF.
+    pub fn write_range(&mut self, address: u16, data: &[u8]) {
+        if let Ok(mut lock) = self.gb_mem.write() {
+            for (offset, value) in data.iter().enumerate() {
+                lock.dbg_write(address.wrapping_add(offset as u16), *value);
+            }
+        }
+    }
+
     pub fn ui_get_cpu_registers(&self) -> (u16, u16, u16, u16, u16, u16) {
         let lock = self.gb_cpu.read().unwrap();
         lock.get_all_registers()
     }
 
-    pub fn ui_get_callstack(&self) -> Arc<RwLock<Vec<String>>> {
+    pub fn ui_get_callstack(&self) -> Arc<RwLock<Callstack>> {
         self.gb_cpu.read().unwrap().get_callstack()
     }
 
-    pub fn ui_get_serial_output(&self) -> Arc<RwLock<Vec<u8>>> {
+    pub fn ui_get_dma_log(&self) -> Arc<RwLock<DmaLog>> {
+        self.gb_cpu.read().unwrap().get_dma_log()
+    }
+
+    pub fn ui_get_serial_output(&self) -> Arc<RwLock<SerialLog>> {
         self.gb_mem.read().unwrap().serial_output()
     }
 
+    pub fn ui_get_debug_output(&self) -> Arc<RwLock<SerialLog>> {
+        self.gb_mem.read().unwrap().debug_output()
+    }
+
+    /// Enables the debug output channel, see memory::DEBUG_CHANNEL_ADDRESS.
+    pub fn set_debug_channel(&mut self, enabled: bool) {
+        if let Ok(mut lock) = self.gb_mem.write() {
+            lock.set_debug_channel(enabled);
+        }
+    }
+
+    pub fn set_serial_link(&mut self, link: Option<Box<dyn SerialLink + Send + Sync>>) {
+        if let Ok(mut lock) = self.gb_mem.write() {
+            lock.set_serial_link(link);
+        }
+    }
+
+    pub fn serial_link_status(&self) -> String {
+        if let Ok(lock) = self.gb_mem.read() {
+            lock.serial_link_status()
+        }
+        else {
+            String::new()
+        }
+    }
+
     pub fn ui_get_joypad_handler(&self) -> Arc<RwLock<JoypadHandler>> {
         self.gb_joy.clone()
     }
@@ -126,6 +768,10 @@ impl Gameboy {
         self.gb_ppu.read().unwrap().get_screen_data()
     }
 
+    pub fn ui_get_screen_layers(&self) -> Arc<RwLock<Vec<u8>>> {
+        self.gb_ppu.read().unwrap().get_screen_layers()
+    }
+
     pub fn ui_get_backgrounds_data(&self) -> Arc<RwLock<Vec<Vec<u8>>>> {
         self.gb_ppu.read().unwrap().get_backgrounds_data()
     }
@@ -134,6 +780,8 @@ impl Gameboy {
 #[derive(Default)]
 pub struct JoypadHandler {
     value: u8,
+    // Set when a button gets pressed, until Gameboy requests the joypad interrupt for it.
+    interrupt_requested: bool,
 
     down_pressed: bool,
     up_pressed: bool,
@@ -151,81 +799,119 @@ impl JoypadHandler {
         self.value = value;
     }
 
+    // Both select lines are active low and get checked separately. With both selected
+    // the two groups get AND'd together, and with neither every button reads as released.
+    // The select bits read back as written, the top two bits are always set.
     pub fn get_buttons(&self) -> u8 {
-        let mut result = 0b11001111;
+        let mut result = 0b11001111 | (self.value & 0x30);
 
-        if self.value == 0x20 {
+        if self.value & 0x10 == 0 {
             if self.down_pressed {
-                result &= 0b11000111;
+                result &= !0b1000;
             }
 
             if self.up_pressed {
-                result &= 0b11001011;
+                result &= !0b0100;
             }
 
             if self.left_pressed {
-                result &= 0b11001101;
+                result &= !0b0010;
             }
 
             if self.right_pressed {
-                result &= 0b11001110;
+                result &= !0b0001;
             }
         }
-        else if self.value == 0x10 {
+
+        if self.value & 0x20 == 0 {
             if self.start_pressed {
-                result &= 0b11000111;
+                result &= !0b1000;
             }
 
             if self.select_pressed {
-                result &= 0b11001011;
+                result &= !0b0100;
             }
 
             if self.b_pressed {
-                result &= 0b11001101;
+                result &= !0b0010;
             }
 
             if self.a_pressed {
-                result &= 0b11001110;
+                result &= !0b0001;
             }
         }
 
         result
     }
 
+    fn check_press(&mut self, was_pressed: bool, pressed: bool) {
+        if pressed && !was_pressed {
+            self.interrupt_requested = true;
+        }
+    }
+
+    /// Returns whether a button was pressed since the last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupt_requested, false)
+    }
+
     pub fn set_down_state(&mut self, state: bool) {
+        self.check_press(self.down_pressed, state);
         self.down_pressed = state;
     }
 
     pub fn set_up_state(&mut self, state: bool) {
+        self.check_press(self.up_pressed, state);
         self.up_pressed = state;
     }
 
     pub fn set_left_state(&mut self, state: bool) {
+        self.check_press(self.left_pressed, state);
         self.left_pressed = state;
     }
 
     pub fn set_right_state(&mut self, state: bool) {
+        self.check_press(self.right_pressed, state);
         self.right_pressed = state;
     }
 
     pub fn set_start_state(&mut self, state: bool) {
+        self.check_press(self.start_pressed, state);
         self.start_pressed = state;
     }
 
     pub fn set_select_state(&mut self, state: bool) {
+        self.check_press(self.select_pressed, state);
         self.select_pressed = state;
     }
 
     pub fn set_b_state(&mut self, state: bool) {
+        self.check_press(self.b_pressed, state);
         self.b_pressed = state;
     }
 
     pub fn set_a_state(&mut self, state: bool) {
+        self.check_press(self.a_pressed, state);
         self.a_pressed = state;
     }
+
+    /// Up, down, left, right, A, B, Select and Start, in that order.
+    pub fn get_pressed(&self) -> [bool; 8] {
+        [
+            self.up_pressed,
+            self.down_pressed,
+            self.left_pressed,
+            self.right_pressed,
+
+            self.a_pressed,
+            self.b_pressed,
+            self.select_pressed,
+            self.start_pressed
+        ]
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
 pub struct Breakpoint {
     read: bool,
     write: bool,
@@ -282,13 +968,35 @@ impl Breakpoint {
     }
 }
 
+// The hardware being emulated. Anything that behaves differently
+// between models should check this instead of assuming a DMG.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Model {
+    Dmg,
+    // Game Boy Pocket.
+    Mgb,
+    // Game Boy Color, only running DMG games for now.
+    Cgb
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Model::Dmg => write!(f, "DMG"),
+            Model::Mgb => write!(f, "MGB"),
+            Model::Cgb => write!(f, "CGB")
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub enum EmulatorMode {
     Paused,
     Running,
     Stepping,
     BreakpointHit,
-    UnknownInstruction(bool, u8)
+    // The CPU ran an illegal opcode, which locks up the real hardware.
+    Locked(u8)
 }
 
 impl fmt::Display for EmulatorMode {
@@ -298,14 +1006,193 @@ impl fmt::Display for EmulatorMode {
             EmulatorMode::Running => write!(f, "Emulator running."),
             EmulatorMode::Stepping => write!(f, "Stepping through pain."),
             EmulatorMode::BreakpointHit => write!(f, "Paused on a breakpoint."),
-            EmulatorMode::UnknownInstruction(prefixed, opcode) => {
-                if *prefixed {
-                    write!(f, "Unimplemented instruction $CB ${:02X}", opcode)
-                }
-                else {
-                    write!(f, "Unimplemented instruction ${:02X}", opcode)
-                }
-            },
+            EmulatorMode::Locked(opcode) => write!(f, "CPU locked up by illegal opcode ${:02X}.", opcode),
+        }
+    }
+}
+
+// Snapshot of the CPU registers plus the next bytes at PC.
+// Displays in the format used by Gameboy Doctor, so traces can be diffed against its reference logs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuState {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+
+    pub pc_mem: [u8; 4]
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.af >> 8, self.af & 0xFF, self.bc >> 8, self.bc & 0xFF, self.de >> 8, self.de & 0xFF, self.hl >> 8, self.hl & 0xFF,
+            self.sp, self.pc, self.pc_mem[0], self.pc_mem[1], self.pc_mem[2], self.pc_mem[3]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joypad_select_bits() {
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let mut gb_mem = GameboyMemory::init(Vec::new(), vec![0; 0x8000], gb_joy.clone());
+
+        if let Ok(mut lock) = gb_joy.write() {
+            lock.set_down_state(true);
+            lock.set_a_state(true);
         }
+
+        // Directions only.
+        gb_mem.write(0xFF00, 0x20);
+        assert_eq!(gb_mem.read(0xFF00), 0xE7);
+
+        // Buttons only.
+        gb_mem.write(0xFF00, 0x10);
+        assert_eq!(gb_mem.read(0xFF00), 0xDE);
+
+        // Both groups get AND'd together.
+        gb_mem.write(0xFF00, 0x00);
+        assert_eq!(gb_mem.read(0xFF00), 0xC6);
+
+        // Neither reads as nothing pressed.
+        gb_mem.write(0xFF00, 0x30);
+        assert_eq!(gb_mem.read(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn button_press_wakes_stop() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0103].copy_from_slice(&[0x10, 0x00, 0x00]);
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy.clone())));
+        let mut gameboy = Gameboy::init(gb_mem.clone());
+
+        gameboy.dbg_mode = EmulatorMode::Running;
+        gameboy.dbg_breakpoint_list.push(Breakpoint::new(false, true, false, 0xFF0F));
+        gb_mem.write().unwrap().write(0xFF0F, 0x00);
+
+        gameboy.gb_cpu_cycle();
+        gameboy.gb_cpu_cycle();
+        assert!(gameboy.dbg_cpu_halted());
+
+        gb_joy.write().unwrap().set_start_state(true);
+        gameboy.gb_cpu_cycle();
+
+        // The request doesn't count as a write to IF, so the breakpoint stays untouched.
+        assert_eq!(gb_mem.read().unwrap().read(0xFF0F) & 0x10, 0x10);
+        assert!(!gameboy.dbg_cpu_halted());
+        assert_eq!(gameboy.dbg_cpu_state().pc, 0x0103);
+        assert!(gameboy.dbg_mode == EmulatorMode::Running);
+
+        // Holding the button doesn't request it again.
+        gb_mem.write().unwrap().write(0xFF0F, 0x00);
+        gameboy.gb_cpu_cycle();
+        assert_eq!(gb_mem.read().unwrap().read(0xFF0F) & 0x10, 0x00);
+    }
+
+    #[test]
+    fn div_writes_restart_the_divider() {
+        // 100 NOPs so DIV is partway through a period, then LDH ($04), A.
+        let mut rom = vec![0; 0x8000];
+        rom[0x0164..0x0166].copy_from_slice(&[0xE0, 0x04]);
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+        let mut gameboy = Gameboy::init(gb_mem.clone());
+
+        while gameboy.dbg_cpu_state().pc != 0x0166 {
+            gameboy.gb_cpu_cycle();
+        }
+
+        let written_at = gameboy.gb_get_cycles();
+
+        while gameboy.gb_get_cycles() < written_at + 256 {
+            assert_eq!(gb_mem.read().unwrap().read(0xFF04), 0x00);
+            gameboy.gb_cpu_cycle();
+        }
+
+        gameboy.gb_cpu_cycle();
+        assert_eq!(gb_mem.read().unwrap().read(0xFF04), 0x01);
+    }
+
+    #[test]
+    fn oam_dma_trips_read_breakpoints() {
+        // LD A, $C0 / LDH ($46), A, then NOPs while the transfer runs.
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&[0x3E, 0xC0, 0xE0, 0x46]);
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+        let mut gameboy = Gameboy::init(gb_mem);
+
+        gameboy.dbg_mode = EmulatorMode::Running;
+        gameboy.dbg_breakpoint_list.push(Breakpoint::new(true, false, false, 0xC010));
+
+        for _ in 0..200 {
+            if gameboy.dbg_mode != EmulatorMode::Running {
+                break;
+            }
+
+            gameboy.gb_cpu_cycle();
+        }
+
+        // The CPU never reads $C010 itself, only the transfer does.
+        assert!(gameboy.dbg_mode == EmulatorMode::BreakpointHit);
+    }
+
+    #[test]
+    fn coverage_marks_executed_instructions() {
+        // EI / NOP / NOP / HALT, with a VBlank handler that's a single RETI.
+        let mut rom = vec![0; 0x8000];
+        rom[0x0040] = 0xD9;
+        rom[0x0100..0x0104].copy_from_slice(&[0xFB, 0x00, 0x00, 0x76]);
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+        let mut gameboy = Gameboy::init(gb_mem.clone());
+
+        gameboy.dbg_mode = EmulatorMode::Running;
+        gb_mem.write().unwrap().write(0xFFFF, 0x01);
+        gb_mem.write().unwrap().write(0xFF0F, 0x01);
+
+        for _ in 0..4 {
+            if gameboy.dbg_coverage[0x0040] {
+                break;
+            }
+
+            gameboy.gb_cpu_cycle();
+        }
+
+        // The cycle that dispatches the interrupt runs the handler's RETI, so PC is back
+        // where it got interrupted, and that instruction didn't run yet.
+        let interrupted = gameboy.dbg_cpu_state().pc;
+
+        assert!(gameboy.dbg_coverage[0x0040]);
+        assert!((0x0101..=0x0103).contains(&interrupted));
+        assert!(!gameboy.dbg_coverage[interrupted as usize]);
+
+        for _ in 0..10 {
+            if gameboy.dbg_cpu_halted() {
+                break;
+            }
+
+            gameboy.gb_cpu_cycle();
+        }
+
+        // Cycles spent halted don't run the instruction after HALT.
+        for _ in 0..10 {
+            gameboy.gb_cpu_cycle();
+        }
+
+        assert!(gameboy.dbg_coverage[0x0103]);
+        assert!(!gameboy.dbg_coverage[0x0104]);
     }
 }
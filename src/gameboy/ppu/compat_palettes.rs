@@ -0,0 +1,106 @@
+// Colors the CGB bootrom gives to DMG games, picked from the game's title or with
+// a button combination during the boot animation. Each palette has a set of 4 colors
+// for the background, and one for each of the two object palettes.
+//
+// The DMG palettes (BGP, OBP0, OBP1) still pick a shade for each pixel,
+// these only change which color every shade ends up as.
+
+use serde::{Deserialize, Serialize};
+
+use crate::gameboy::ppu::utils::BASE_PALETTE;
+
+pub struct CompatPalette {
+    pub name: &'static str,
+
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4]
+}
+
+const BROWN: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const RED: [u32; 4] = [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000];
+const DARK_BROWN: [u32; 4] = [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108];
+const BLUE: [u32; 4] = [0xFFFFFF, 0x65A49B, 0x0000FE, 0x000000];
+const DARK_BLUE: [u32; 4] = [0xFFFFFF, 0x8B8CDE, 0x53528C, 0x000000];
+const GRAY: [u32; 4] = [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000];
+const PASTEL: [u32; 4] = [0xFFFFA5, 0xFE9494, 0x9494FE, 0x000000];
+const ORANGE: [u32; 4] = [0xFFFFFF, 0xFFFF00, 0xFE0000, 0x000000];
+const YELLOW: [u32; 4] = [0xFFFFFF, 0xFFFF00, 0x7D4900, 0x000000];
+const GREEN: [u32; 4] = [0xFFFFFF, 0x51FF00, 0xFF4200, 0x000000];
+const DARK_GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000];
+const INVERTED: [u32; 4] = [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF];
+
+const OBJ_RED: [u32; 4] = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const OBJ_GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+const OBJ_BLUE: [u32; 4] = [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000];
+
+// The ones that can be picked with the D-pad and A/B while the logo is shown.
+pub static MANUAL_PALETTES: [CompatPalette; 12] = [
+    CompatPalette { name: "Up (brown)", bg: BROWN, obj0: BROWN, obj1: BROWN },
+    CompatPalette { name: "Up + A (red)", bg: RED, obj0: RED, obj1: RED },
+    CompatPalette { name: "Up + B (dark brown)", bg: DARK_BROWN, obj0: DARK_BROWN, obj1: DARK_BROWN },
+    CompatPalette { name: "Left (blue)", bg: BLUE, obj0: OBJ_RED, obj1: OBJ_GREEN },
+    CompatPalette { name: "Left + A (dark blue)", bg: DARK_BLUE, obj0: OBJ_RED, obj1: BROWN },
+    CompatPalette { name: "Left + B (grayscale)", bg: GRAY, obj0: GRAY, obj1: GRAY },
+    CompatPalette { name: "Down (pastel)", bg: PASTEL, obj0: PASTEL, obj1: PASTEL },
+    CompatPalette { name: "Down + A (orange)", bg: ORANGE, obj0: ORANGE, obj1: ORANGE },
+    CompatPalette { name: "Down + B (yellow)", bg: YELLOW, obj0: OBJ_BLUE, obj1: OBJ_GREEN },
+    CompatPalette { name: "Right (green)", bg: GREEN, obj0: GREEN, obj1: GREEN },
+    CompatPalette { name: "Right + A (dark green)", bg: DARK_GREEN, obj0: OBJ_RED, obj1: OBJ_RED },
+    CompatPalette { name: "Right + B (inverted)", bg: INVERTED, obj0: INVERTED, obj1: INVERTED }
+];
+
+// Games with their own palette, as (title checksum, 4th letter of the title, index in
+// MANUAL_PALETTES). The letter is only checked when set, it tells apart games with the same checksum.
+// Entries get added as they're checked against the bootrom, the rest of the games show in grayscale.
+static TITLE_PALETTES: [(u8, Option<u8>, usize); 0] = [];
+
+/// Which colors to use when running DMG games on a CGB.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+pub enum CompatPaletteChoice {
+    // Picked by title like the bootrom does, grayscale if the game isn't known.
+    #[default]
+    Auto,
+    // Index in MANUAL_PALETTES.
+    Manual(usize)
+}
+
+impl CompatPaletteChoice {
+    /// `title_key` is CartHeader::compat_title_key(), None leaves the screen in grayscale.
+    pub fn resolve(&self, title_key: Option<(u8, u8)>) -> Option<&'static CompatPalette> {
+        match self {
+            CompatPaletteChoice::Auto => {
+                let (checksum, fourth_letter) = title_key?;
+
+                TITLE_PALETTES.iter()
+                    .find(|(sum, letter, _)| *sum == checksum && letter.map(|l| l == fourth_letter).unwrap_or(true))
+                    .and_then(|(_, _, idx)| MANUAL_PALETTES.get(*idx))
+            }
+            CompatPaletteChoice::Manual(idx) => MANUAL_PALETTES.get(*idx)
+        }
+    }
+
+    /// Name to show in the settings, manual indexes that don't exist show as Auto.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompatPaletteChoice::Auto => "Auto",
+            CompatPaletteChoice::Manual(idx) => MANUAL_PALETTES.get(*idx).map(|p| p.name).unwrap_or("Auto")
+        }
+    }
+}
+
+impl CompatPalette {
+    /// Color for a pixel of the screen buffer, given the layer it came from (see GameboyPPU::get_screen_layers()).
+    pub fn color(&self, layer: u8, shade: u8) -> [u8; 3] {
+        let colors = match layer {
+            1 => &self.obj0,
+            2 => &self.obj1,
+            _ => &self.bg
+        };
+
+        let idx = BASE_PALETTE.iter().position(|s| *s == shade).unwrap_or(0);
+        let color = colors[idx];
+
+        [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+    }
+}
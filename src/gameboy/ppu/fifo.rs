@@ -0,0 +1,317 @@
+// Pixel FIFO renderer, draws a line one pixel per dot during mode 3.
+// The fetcher reads a tile row every 6 dots and pushes it once the FIFO is empty,
+// and every dot one pixel gets shifted out to the LCD. Registers and palettes are
+// read while fetching and shifting, so games changing them mid-line get the right picture.
+// Sprite fetches don't stall the fetcher here, and mode 3 still takes 172 cycles,
+// whatever is left of the line gets drawn once it ends.
+
+use std::collections::VecDeque;
+
+use super::{Sprite, SCREEN_WIDTH};
+use super::utils::{Palette, BASE_PALETTE};
+
+use crate::gameboy::memory::GameboyMemory;
+
+// Dots the fetcher takes to have a tile row ready: tile id, low byte, high byte.
+const FETCH_DOTS: u8 = 6;
+
+/// The registers used while drawing, as they were when the PPU last ran.
+pub struct LineRegisters {
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub wy: u8,
+    pub wx: u8
+}
+
+// The row of a sprite on the current line, fetched when the line starts.
+struct SpriteRow {
+    // Screen X of the leftmost pixel, negative when partially off the left edge.
+    x: i16,
+    pixels: [u8; 8],
+
+    bg_priority: bool,
+    palette: bool
+}
+
+pub struct PixelFifo {
+    // Set between the start and the end of a line.
+    active: bool,
+
+    // Color indices waiting to be shifted out.
+    bg_fifo: VecDeque<u8>,
+
+    fetcher_x: u8,
+    fetcher_dots: u8,
+    tile_id: u8,
+    tile_low: u8,
+    tile_high: u8,
+
+    // Pixels shifted out and thrown away, for SCX and a window with WX below 7.
+    discard: u8,
+    // Dots run since mode 3 started.
+    dots: usize,
+    x: usize,
+
+    sprites: Vec<SpriteRow>,
+
+    // Fetching the window instead of the background.
+    window: bool,
+    // Set once LY matched WY this frame, the window can't show up before that.
+    window_y_hit: bool,
+    // The window has its own line counter, which only moves on lines it was drawn on.
+    window_line: u8,
+
+    line: Vec<u8>,
+    // Same values as GameboyPPU::screen_layers.
+    layers: Vec<u8>
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            active: false,
+
+            bg_fifo: VecDeque::with_capacity(16),
+
+            fetcher_x: 0,
+            fetcher_dots: 0,
+            tile_id: 0,
+            tile_low: 0,
+            tile_high: 0,
+
+            discard: 0,
+            dots: 0,
+            x: 0,
+
+            sprites: Vec::with_capacity(10),
+
+            window: false,
+            window_y_hit: false,
+            window_line: 0,
+
+            line: vec![255; SCREEN_WIDTH],
+            layers: vec![0; SCREEN_WIDTH]
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Called when mode 3 starts, with the sprites picked by the OAM scan.
+    pub fn start_line(&mut self, regs: &LineRegisters, sprites: &[Sprite], sprite_height: u8, mem: &GameboyMemory) {
+        if regs.ly == 0 {
+            self.window_y_hit = false;
+            self.window_line = 0;
+        }
+
+        if regs.ly == regs.wy {
+            self.window_y_hit = true;
+        }
+
+        self.active = true;
+        self.bg_fifo.clear();
+
+        self.fetcher_x = 0;
+        self.fetcher_dots = 0;
+
+        self.discard = regs.scx & 7;
+        self.dots = 0;
+        self.x = 0;
+        self.window = false;
+
+        self.sprites.clear();
+
+        for sprite in sprites {
+            let line = (regs.ly - sprite.pos_y) as u16;
+            // flip_x holds the vertical flip bit and flip_y the horizontal one, see draw_sprites.
+            let row = if sprite.flip_x {sprite_height as u16 - 1 - line} else {line};
+            let tile = if sprite_height == 16 {sprite.tile_id & 0xFE} else {sprite.tile_id};
+            let address = 0x8000 + (16 * tile as u16) + (2 * row);
+
+            let mut pixels = tile_row(mem.read(address), mem.read(address + 1));
+
+            if sprite.flip_y {
+                pixels.reverse();
+            }
+
+            self.sprites.push(SpriteRow {
+                x: sprite.oam_x as i16 - 8,
+                pixels,
+
+                bg_priority: sprite.bg_priority,
+                palette: sprite.palette
+            });
+        }
+
+        // The one with the lowest X wins, and OAM order breaks ties.
+        self.sprites.sort_by_key(|sprite| sprite.x);
+    }
+
+    /// Runs dots until `until` dots have passed since mode 3 started, or the line is complete.
+    pub fn run(&mut self, until: usize, regs: &LineRegisters, bg_palette: &Palette, obj_palettes: &[Palette], mem: &GameboyMemory) {
+        if !self.active {
+            return;
+        }
+
+        while self.dots < until && self.x < SCREEN_WIDTH {
+            self.check_window(regs);
+            self.fetch(regs, mem);
+            self.shift_pixel(regs, bg_palette, obj_palettes);
+
+            self.dots += 1;
+        }
+    }
+
+    /// Called when mode 3 ends, the finished line can be read until the next one starts.
+    pub fn end_line(&mut self) {
+        if self.window {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
+
+        self.active = false;
+    }
+
+    pub fn line(&self) -> &[u8] {
+        &self.line
+    }
+
+    pub fn layers(&self) -> &[u8] {
+        &self.layers
+    }
+
+    fn check_window(&mut self, regs: &LineRegisters) {
+        if self.window || !self.window_y_hit || regs.lcdc & 0x20 == 0 || self.discard > 0 {
+            return;
+        }
+
+        if self.x + 7 >= regs.wx as usize {
+            self.window = true;
+            self.bg_fifo.clear();
+
+            self.fetcher_x = 0;
+            self.fetcher_dots = 0;
+
+            // With WX below 7 the window starts off the left edge.
+            if self.x == 0 {
+                self.discard = 7 - regs.wx.min(7);
+            }
+        }
+    }
+
+    fn fetch(&mut self, regs: &LineRegisters, mem: &GameboyMemory) {
+        let (map_start, map_x, map_y) = if self.window {
+            let map_start = if regs.lcdc & 0x40 == 0 {0x9800} else {0x9C00};
+            (map_start, self.fetcher_x, self.window_line)
+        }
+        else {
+            let map_start = if regs.lcdc & 0x08 == 0 {0x9800} else {0x9C00};
+            (map_start, (regs.scx / 8).wrapping_add(self.fetcher_x), regs.ly.wrapping_add(regs.scy))
+        };
+
+        match self.fetcher_dots {
+            0 => {
+                let address = map_start + (32 * (map_y / 8) as u16) + (map_x & 31) as u16;
+                self.tile_id = mem.read(address);
+            }
+            2 => self.tile_low = mem.read(tile_address(self.tile_id, map_y, regs.lcdc)),
+            4 => self.tile_high = mem.read(tile_address(self.tile_id, map_y, regs.lcdc) + 1),
+            _ => {}
+        }
+
+        if self.fetcher_dots < FETCH_DOTS {
+            self.fetcher_dots += 1;
+        }
+        else if self.bg_fifo.is_empty() {
+            self.bg_fifo.extend(tile_row(self.tile_low, self.tile_high).iter());
+
+            self.fetcher_x = self.fetcher_x.wrapping_add(1);
+            self.fetcher_dots = 0;
+        }
+    }
+
+    fn shift_pixel(&mut self, regs: &LineRegisters, bg_palette: &Palette, obj_palettes: &[Palette]) {
+        let bg_idx = match self.bg_fifo.pop_front() {
+            Some(idx) => idx,
+            None => return
+        };
+
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+
+        // With BG and window disabled everything behind the sprites is white.
+        let (bg_idx, mut color) = if regs.lcdc & 1 != 0 {
+            (bg_idx, bg_palette.get_color(bg_idx))
+        }
+        else {
+            (0, BASE_PALETTE[0])
+        };
+
+        let mut layer = 0;
+
+        if regs.lcdc & 2 != 0 {
+            let x = self.x as i16;
+            let sprite_pixel = self.sprites.iter()
+                .filter(|sprite| x >= sprite.x && x < sprite.x + 8)
+                .map(|sprite| (sprite, sprite.pixels[(x - sprite.x) as usize]))
+                .find(|(_, idx)| *idx != 0);
+
+            if let Some((sprite, idx)) = sprite_pixel {
+                if !sprite.bg_priority || bg_idx == 0 {
+                    color = obj_palettes[sprite.palette as usize].get_color(idx);
+                    layer = if !sprite.palette {1} else {2};
+                }
+            }
+        }
+
+        self.line[self.x] = color;
+        self.layers[self.x] = layer;
+        self.x += 1;
+    }
+}
+
+fn tile_address(tile_id: u8, y: u8, lcdc: u8) -> u16 {
+    let row = 2 * (y % 8) as u16;
+
+    if lcdc & 0x10 != 0 {
+        0x8000 + (16 * tile_id as u16) + row
+    }
+    else {
+        (0x9000 + (16 * tile_id as i8 as i32)) as u16 + row
+    }
+}
+
+fn tile_row(low: u8, high: u8) -> [u8; 8] {
+    let mut row = [0; 8];
+
+    for (idx, pixel) in row.iter_mut().enumerate() {
+        let bit = 7 - idx;
+        *pixel = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+    }
+
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_tile_addressing() {
+        // LCDC.4 clear, IDs are signed and relative to $9000.
+        assert_eq!(tile_address(0x80, 0, 0x00), 0x8800);
+        assert_eq!(tile_address(0xFF, 0, 0x00), 0x8FF0);
+        assert_eq!(tile_address(0x00, 0, 0x00), 0x9000);
+        assert_eq!(tile_address(0x7F, 0, 0x00), 0x97F0);
+        assert_eq!(tile_address(0x7F, 7, 0x00), 0x97FE);
+
+        // LCDC.4 set, IDs are unsigned from $8000.
+        assert_eq!(tile_address(0x00, 0, 0x10), 0x8000);
+        assert_eq!(tile_address(0x80, 0, 0x10), 0x8800);
+        assert_eq!(tile_address(0xFF, 0, 0x10), 0x8FF0);
+    }
+}
@@ -1,22 +1,47 @@
 pub mod utils;
+pub mod recorder;
+pub mod compat_palettes;
+
+mod fifo;
 
 use std::time;
 use std::sync::{Arc, RwLock};
 
+use fifo::{LineRegisters, PixelFifo};
 use utils::Palette;
 
+use serde::{Deserialize, Serialize};
+
+use crate::gameboy::DMG_CLOCK_HZ;
 use crate::gameboy::memory::GameboyMemory;
 use crate::gameboy::memory::io::IoRegister;
+use crate::gameboy::state::{StateReader, StateWriter};
 
 const SCREEN_WIDTH: usize = 160;
 const SCREEN_HEIGHT: usize = 144;
 
+// 154 lines of 456 cycles each.
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
 const LYC_BIT: u8 = 0x04;
 const HBLANK_INT_BIT: u8 = 0x08;
 const VBLANK_INT_BIT: u8 = 0x10;
 const OAM_INT_BIT: u8 = 0x20;
 const LYC_INT_BIT: u8 = 0x40;
 
+fn frame_duration(clock_hz: u32) -> time::Duration {
+    time::Duration::from_secs_f64(CYCLES_PER_FRAME as f64 / clock_hz.max(1) as f64)
+}
+
+/// How the screen gets drawn. The pixel FIFO picks up registers and palettes changing
+/// in the middle of a line, the scanline renderer draws each line at once when mode 3 ends.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum Renderer {
+    #[default]
+    PixelFifo,
+    Scanline
+}
+
 enum Mode {
     Vblank,
     Hblank,
@@ -32,6 +57,8 @@ enum Interrupt {
 struct Sprite {
     pos_y: u8,
     pos_x: u8,
+    // X as written in OAM, pos_x loses whatever is past the left edge.
+    oam_x: u8,
     tile_id: u8,
 
     bg_priority: bool,
@@ -50,6 +77,7 @@ impl Sprite {
         Sprite {
             pos_y: data[0].saturating_sub(16),
             pos_x: data[1].saturating_sub(8),
+            oam_x: data[1],
             tile_id: data[2],
 
             bg_priority,
@@ -77,12 +105,44 @@ pub struct GameboyPPU {
     obj_palettes: Vec<Palette>,
 
     gb_cyc: Arc<RwLock<usize>>,
+    // Value of gb_cyc when the current mode (or line, during V-Blank) started.
+    mode_start: usize,
     
     screen: Arc<RwLock<Vec<u8>>>,
+    // Which palette each pixel of the screen came from: 0 for BG and window, 1 for OBP0, 2 for OBP1.
+    // Only needed to color DMG games on a CGB.
+    screen_layers: Arc<RwLock<Vec<u8>>>,
+    // Color indices (0-3) of both background maps, the BG palette gets applied
+    // when drawing the screen so palette changes show up on the next line.
     backgrounds: Arc<RwLock<Vec<Vec<u8>>>>,
+    // Color indices of the background pixels on the current line, for sprites behind the background.
+    line_bg_indices: Vec<u8>,
 
     gb_mem: Arc<RwLock<GameboyMemory>>,
     frame_time: time::Instant,
+    // How long to wait before starting the next frame, to keep it at about 60 FPS.
+    // The emulation thread does the waiting, so the lock isn't held while sleeping.
+    frame_sleep: Option<time::Duration>,
+    // Real time a frame should take, depends on the emulated clock speed.
+    frame_duration: time::Duration,
+
+    // Sprites picked by the OAM scan (mode 2) for the current line, and the height used to pick them.
+    line_sprites: Vec<Sprite>,
+    line_sprites_height: u8,
+
+    renderer: Renderer,
+    fifo: PixelFifo,
+
+    // While fast-forwarding, frames aren't throttled and only one in every
+    // fast_forward_frame_skip gets drawn. Timing and interrupts are unaffected.
+    fast_forward: bool,
+    fast_forward_frame_skip: usize,
+    frame_counter: usize,
+    skip_drawing: bool,
+
+    // Scanline breakpoint, checked whenever LY changes.
+    ly_breakpoint: Option<u8>,
+    ly_breakpoint_hit: bool
 }
 
 impl GameboyPPU {
@@ -113,16 +173,36 @@ impl GameboyPPU {
             obj_palettes: vec![Palette::new(); 2],
 
             gb_cyc,
+            mode_start: 0,
 
             screen: Arc::new(RwLock::new(vec![255; SCREEN_WIDTH * SCREEN_HEIGHT])),
-            backgrounds: Arc::new(RwLock::new(vec![vec![255; 256 * 256]; 2])),
+            screen_layers: Arc::new(RwLock::new(vec![0; SCREEN_WIDTH * SCREEN_HEIGHT])),
+            backgrounds: Arc::new(RwLock::new(vec![vec![0; 256 * 256]; 2])),
+            line_bg_indices: vec![0; SCREEN_WIDTH],
 
             gb_mem,
-            frame_time: time::Instant::now()
+            frame_time: time::Instant::now(),
+            frame_sleep: None,
+            frame_duration: frame_duration(DMG_CLOCK_HZ),
+
+            line_sprites: Vec::with_capacity(10),
+            line_sprites_height: 8,
+
+            renderer: Renderer::default(),
+            fifo: PixelFifo::new(),
+
+            fast_forward: false,
+            fast_forward_frame_skip: 1,
+            frame_counter: 0,
+            skip_drawing: false,
+
+            ly_breakpoint: None,
+            ly_breakpoint_hit: false
         }
     }
 
-    pub fn ppu_cycle(&mut self) {
+    /// Returns true when the PPU just entered V-Blank, which marks the end of a frame.
+    pub fn ppu_cycle(&mut self) -> bool {
         let bg_pal = self.read(0xFF47);
         let obj0_pal = self.read(0xFF48) & 0xFC;
         let obj1_pal = self.read(0xFF49) & 0xFC;
@@ -131,36 +211,70 @@ impl GameboyPPU {
         self.obj_palettes[0].update(obj0_pal);
         self.obj_palettes[1].update(obj1_pal);
 
+        let cycles = *self.gb_cyc.read().unwrap();
+
+        // The clock only goes backwards when the emulator gets reset.
+        if cycles < self.mode_start {
+            self.mode_start = cycles;
+        }
+
         if self.lcdc.get() & 0x80 == 0 {
+            self.mode_start = cycles;
             self.frame_time = time::Instant::now();
-            return;
+            return false;
         }
 
+        let elapsed = cycles - self.mode_start;
+        let mut frame_finished = false;
+
         let current_mode = self.stat.get() & 3;
 
+        // Catch up with the dots that passed, using the registers as they are now.
+        if current_mode == 3 && self.renderer == Renderer::PixelFifo {
+            self.run_fifo(elapsed.min(172));
+        }
+
         // Mode 2 - OAM scan.
-        if *self.gb_cyc.read().unwrap() >= 80 && current_mode == 2 {
-            *self.gb_cyc.write().unwrap() = 0;
+        if elapsed >= 80 && current_mode == 2 {
+            self.mode_start += 80;
+
+            self.scan_oam();
             self.set_mode(Mode::LcdTransfer);
+
+            if self.renderer == Renderer::PixelFifo && !self.skip_drawing {
+                self.start_fifo_line();
+            }
         }
         // Mode 3 - Access OAM and VRAM to generate the picture.
-        else if *self.gb_cyc.read().unwrap() >= 172 && current_mode == 3 {
-            *self.gb_cyc.write().unwrap() = 0;
-            
-            self.draw_screen_line();
-            self.draw_sprites();
+        else if elapsed >= 172 && current_mode == 3 {
+            self.mode_start += 172;
+
+            if self.skip_drawing {
+                self.line_sprites.clear();
+            }
+            // Lines the FIFO didn't start, like right after switching renderers, get drawn at once.
+            else if self.fifo.active() {
+                self.run_fifo(usize::MAX);
+                self.end_fifo_line();
+            }
+            else {
+                self.draw_screen_line();
+                self.draw_sprites();
+            }
 
             self.set_mode(Mode::Hblank);
         }
         // Mode 0 - H-Blank.
-        else if *self.gb_cyc.read().unwrap() >= 204 && current_mode == 0 {
-            self.ly.set(self.ly.get().wrapping_add(1));
+        else if elapsed >= 204 && current_mode == 0 {
+            self.set_ly(self.ly.get().wrapping_add(1));
 
             if self.ly.get() < 144 {
                 self.set_mode(Mode::OamScan);
             }
             else {
+                // The only place V-Blank gets requested, lines 145-153 stay in mode 1 without switching again.
                 self.set_mode(Mode::Vblank);
+                frame_finished = true;
             }
 
             let mut stat = self.stat.get();
@@ -174,22 +288,35 @@ impl GameboyPPU {
             }
 
             self.stat.set(stat);
-            *self.gb_cyc.write().unwrap() = 0;
+            self.mode_start += 204;
         }
-        // Mode 1 - V-Blank.
-        else if *self.gb_cyc.read().unwrap() >= 456 && current_mode == 1 {
-            self.ly.set(self.ly.get().wrapping_add(1));
+        // Mode 1 - V-Blank. Only LY and the LYC check move forward here,
+        // so the V-Blank interrupt isn't requested again until the next frame.
+        else if elapsed >= 456 && current_mode == 1 {
+            self.set_ly(self.ly.get().wrapping_add(1));
 
             if self.ly.get() > 153 {
-                if self.frame_time.elapsed() < time::Duration::from_millis(16) {
-                    let time_to_sleep = time::Duration::from_millis(16).saturating_sub(self.frame_time.elapsed());
-
-                    std::thread::sleep(time_to_sleep);
-                }
+                let now = time::Instant::now();
+                let time_to_sleep = {
+                    if self.fast_forward {
+                        time::Duration::from_millis(0)
+                    }
+                    else {
+                        self.frame_duration.saturating_sub(now.saturating_duration_since(self.frame_time))
+                    }
+                };
 
-                self.ly.set(0);
+                self.set_ly(0);
                 self.set_mode(Mode::OamScan);
-                self.frame_time = time::Instant::now();
+
+                // The next frame starts once the sleep is over.
+                self.frame_sleep = Some(time_to_sleep);
+                self.frame_time = now + time_to_sleep;
+
+                if self.fast_forward {
+                    self.frame_counter = (self.frame_counter + 1) % self.fast_forward_frame_skip;
+                    self.skip_drawing = self.frame_counter != 0;
+                }
             }
 
             let mut stat = self.stat.get();
@@ -203,9 +330,15 @@ impl GameboyPPU {
             }
 
             self.stat.set(stat);
-            self.draw_backgrounds();
-            *self.gb_cyc.write().unwrap() = 0;
+
+            if !self.skip_drawing {
+                self.draw_backgrounds();
+            }
+
+            self.mode_start += 456;
         }
+
+        frame_finished
     }
 
     fn read(&self, address: u16) -> u8 {
@@ -223,10 +356,96 @@ impl GameboyPPU {
         }
     }
 
+    fn set_ly(&mut self, value: u8) {
+        self.ly.set(value);
+
+        if self.ly_breakpoint == Some(value) {
+            self.ly_breakpoint_hit = true;
+        }
+    }
+
+    /// Restarts the frame at line 0, for writes to LY.
+    pub fn reset_ly(&mut self) {
+        self.mode_start = *self.gb_cyc.read().unwrap();
+        self.set_ly(0);
+        self.set_mode(Mode::OamScan);
+
+        let mut stat = self.stat.get();
+
+        if self.lyc.get() == 0 {
+            stat |= LYC_BIT;
+        }
+        else {
+            stat &= !LYC_BIT;
+        }
+
+        self.stat.set(stat);
+    }
+
+    /// Returns how long to sleep for, if a frame ended since the last call.
+    pub fn take_frame_sleep(&mut self) -> Option<time::Duration> {
+        self.frame_sleep.take()
+    }
+
+    /// Frame skip is the amount of frames per drawn frame, 1 draws all of them.
+    pub fn set_clock_speed(&mut self, hz: u32) {
+        self.frame_duration = frame_duration(hz);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_fast_forward(&mut self, enabled: bool, frame_skip: usize) {
+        self.fast_forward = enabled;
+        self.fast_forward_frame_skip = frame_skip.max(1);
+
+        if !enabled {
+            self.frame_counter = 0;
+            self.skip_drawing = false;
+        }
+    }
+
+    pub fn set_ly_breakpoint(&mut self, ly: Option<u8>) {
+        self.ly_breakpoint = ly;
+        self.ly_breakpoint_hit = false;
+    }
+
+    /// Returns whether the scanline breakpoint was hit since the last call.
+    pub fn take_ly_breakpoint_hit(&mut self) -> bool {
+        std::mem::replace(&mut self.ly_breakpoint_hit, false)
+    }
+
+    // The registers live in memory, so only the timing and the last frame are saved here.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.mode_start);
+
+        if let Ok(lock) = self.screen.read() {
+            state.write_bytes(&lock);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Option<()> {
+        self.mode_start = state.read_usize()?;
+        self.ly_breakpoint_hit = false;
+        self.line_sprites.clear();
+        self.fifo = PixelFifo::new();
+
+        if let Ok(mut lock) = self.screen.write() {
+            state.read_bytes_into(&mut lock)?;
+        }
+
+        Some(())
+    }
+
     pub fn get_screen_data(&self) -> Arc<RwLock<Vec<u8>>> {
         self.screen.clone()
     }
 
+    pub fn get_screen_layers(&self) -> Arc<RwLock<Vec<u8>>> {
+        self.screen_layers.clone()
+    }
+
     pub fn get_backgrounds_data(&self) -> Arc<RwLock<Vec<Vec<u8>>>> {
         self.backgrounds.clone()
     }
@@ -277,13 +496,69 @@ impl GameboyPPU {
         self.write(0xFF0F, if_value);
     }
 
+    fn line_registers(&self) -> LineRegisters {
+        LineRegisters {
+            lcdc: self.lcdc.get(),
+            scy: self.scy.get(),
+            scx: self.scx.get(),
+            ly: self.ly.get(),
+            wy: self.wy.get(),
+            wx: self.wx.get()
+        }
+    }
+
+    fn start_fifo_line(&mut self) {
+        let regs = self.line_registers();
+
+        if let Ok(mem) = self.gb_mem.read() {
+            self.fifo.start_line(&regs, &self.line_sprites, self.line_sprites_height, &mem);
+        }
+    }
+
+    fn run_fifo(&mut self, until: usize) {
+        let regs = self.line_registers();
+
+        if let Ok(mem) = self.gb_mem.read() {
+            self.fifo.run(until, &regs, &self.bg_palette, &self.obj_palettes, &mem);
+        }
+    }
+
+    fn end_fifo_line(&mut self) {
+        let start = SCREEN_WIDTH * self.ly.get() as usize;
+
+        self.fifo.end_line();
+        self.line_sprites.clear();
+
+        if let Ok(mut screen) = self.screen.write() {
+            screen[start..start + SCREEN_WIDTH].copy_from_slice(self.fifo.line());
+        }
+
+        if let Ok(mut layers) = self.screen_layers.write() {
+            layers[start..start + SCREEN_WIDTH].copy_from_slice(self.fifo.layers());
+        }
+    }
+
     // Draw a screen line using the data in self.backgrounds.
     fn draw_screen_line(&mut self) {
+        let ly = self.ly.get();
+
+        // Sprites get drawn on top later, and mark their own pixels.
+        if let Ok(mut layers) = self.screen_layers.write() {
+            let start = 160 * ly as usize;
+
+            for layer in layers[start..start + 160].iter_mut() {
+                *layer = 0;
+            }
+        }
+
+        for idx in self.line_bg_indices.iter_mut() {
+            *idx = 0;
+        }
+
         if self.lcdc.get() & 1 == 0 {
             return;
         }
 
-        let ly = self.ly.get();
         let scy = self.scy.get();
         let scx = self.scx.get();
         let lcdc = self.lcdc.get();
@@ -299,11 +574,14 @@ impl GameboyPPU {
             for screen_point in 0..160 {
                 let screen_point: u8 = screen_point;
                 let background_line_idx: u8 = screen_point.wrapping_add(scx);
+                let color_idx = background_line[background_line_idx as usize];
 
                 if let Ok(mut screen) = self.screen.write() {
-                    screen[screen_idx] = background_line[background_line_idx as usize];
+                    screen[screen_idx] = self.bg_palette.get_color(color_idx);
                 }
 
+                self.line_bg_indices[screen_point as usize] = color_idx;
+
                 screen_idx += 1;
             }
 
@@ -329,10 +607,13 @@ impl GameboyPPU {
                     for screen_point in 0..160 {
                         let screen_point: u8 = screen_point;
                         let background_line_idx: u8 = screen_point.wrapping_add(wx - 7);
+                        let color_idx = background_line[background_line_idx as usize];
     
                         if let Ok(mut screen) = self.screen.write() {
-                            screen[screen_idx] = background_line[background_line_idx as usize];
+                            screen[screen_idx] = self.bg_palette.get_color(color_idx);
                         }
+
+                        self.line_bg_indices[screen_point as usize] = color_idx;
     
                         screen_idx += 1;
                     }
@@ -341,39 +622,49 @@ impl GameboyPPU {
         }
     }
 
-    fn draw_sprites(&mut self) {
+    // Picks the (up to 10) sprites that overlap the current line, in OAM order.
+    // Done at the end of mode 2, so later OAM changes don't affect this line.
+    fn scan_oam(&mut self) {
         let ly = self.ly.get();
-        let lcdc = self.lcdc.get();
+        let sprite_heigth = if self.lcdc.get() & 4 != 0 {16} else {8};
+        let mut oam_data = Vec::with_capacity(160);
 
-        // OBJ Enabled flag.
-        if lcdc & 2 != 0 {
-            // Whether to use 8x16 sprites or 8x8.
-            let sprite_heigth = if lcdc & 4 != 0 {16} else {8};
-            let mut oam_data = Vec::with_capacity(160);
-            let mut sprites_to_draw = Vec::with_capacity(10);
+        self.line_sprites.clear();
+        self.line_sprites_height = sprite_heigth;
 
-            for offset in 0..160 {
-                oam_data.push(self.read(0xFE00 + offset));
-            }
-            
-            for chunk in oam_data.chunks_exact(4) {
-                let sprite = Sprite::new(chunk);
-                
-                match ly.cmp(&sprite.pos_y){
-                    std::cmp::Ordering::Equal => sprites_to_draw.push(sprite),
-                    std::cmp::Ordering::Greater => {
-                        if (ly - sprite.pos_y) < sprite_heigth {
-                            sprites_to_draw.push(sprite);
-                        }
+        for offset in 0..160 {
+            oam_data.push(self.read(0xFE00 + offset));
+        }
+
+        for chunk in oam_data.chunks_exact(4) {
+            let sprite = Sprite::new(chunk);
+
+            match ly.cmp(&sprite.pos_y){
+                std::cmp::Ordering::Equal => self.line_sprites.push(sprite),
+                std::cmp::Ordering::Greater => {
+                    if (ly - sprite.pos_y) < sprite_heigth {
+                        self.line_sprites.push(sprite);
                     }
-                    _ => {}
                 }
+                _ => {}
+            }
 
-                // Can only draw 10 sprites per line.
-                if sprites_to_draw.len() >= 10 {
-                    break;
-                }
+            // Can only draw 10 sprites per line.
+            if self.line_sprites.len() >= 10 {
+                break;
             }
+        }
+    }
+
+    fn draw_sprites(&mut self) {
+        let ly = self.ly.get();
+        let lcdc = self.lcdc.get();
+        let sprites_to_draw = std::mem::take(&mut self.line_sprites);
+
+        // OBJ Enabled flag.
+        if lcdc & 2 != 0 {
+            // Whether to use 8x16 sprites or 8x8, as seen by the scan.
+            let sprite_heigth = self.line_sprites_height;
 
             for sprite in sprites_to_draw {
                 // Sprite is off-screen.
@@ -385,6 +676,7 @@ impl GameboyPPU {
                 let mut tile_data = Vec::with_capacity((sprite_heigth * 2) as usize);
 
                 let palette = if !sprite.palette {&self.obj_palettes[0]} else {&self.obj_palettes[1]};
+                let layer = if !sprite.palette {1} else {2};
 
                 if sprite_heigth == 16 {
                     let tiles = [sprite.tile_id & 0xFE, sprite.tile_id | 1];
@@ -417,7 +709,8 @@ impl GameboyPPU {
                 let sprite_line = &tile_data[idx..idx+2];
 
                 let mut result = Vec::new();
-                let mut screen_idx = (160 * ly as usize) + sprite.pos_x as usize;
+                let line_start = 160 * ly as usize;
+                let mut screen_idx = line_start + sprite.pos_x as usize;
 
                 if sprite.flip_y {
                     for bit in 0..8 {
@@ -441,16 +734,22 @@ impl GameboyPPU {
                     let pixel_color = palette.get_color(color_idx);
     
                     if let Ok(mut lock) = self.screen.write() {
-                        if sprite.bg_priority {
-                            let point_color = lock[screen_idx];
-                            let color_0 = self.bg_palette.get_color(0);
-    
-                            if point_color == color_0 {
-                                lock[screen_idx] = pixel_color;
+                        let drawn = {
+                            if sprite.bg_priority {
+                                // Only color 0 of the background goes behind sprites, whatever shade it has.
+                                self.line_bg_indices.get(screen_idx - line_start).copied().unwrap_or(0) == 0
                             }
-                        }
-                        else {
+                            else {
+                                true
+                            }
+                        };
+
+                        if drawn {
                             lock[screen_idx] = pixel_color;
+
+                            if let Ok(mut layers) = self.screen_layers.write() {
+                                layers[screen_idx] = layer;
+                            }
                         }
                     }
     
@@ -494,6 +793,8 @@ impl GameboyPPU {
                     let y_offset = bg_line_idx * 8;
 
                     for tile_idx in bg_line_data {
+                        // In $8800 mode the tiles start at $8800, which is index $80 (-128).
+                        // $80-$FF end up on the first half ($8800-$8FFF), and $00-$7F on the second ($9000-$97FF).
                         let tile_idx = if signed {
                             (*tile_idx as i8 as i16 + 128) as u16
                         }
@@ -501,7 +802,7 @@ impl GameboyPPU {
                             *tile_idx as u16
                         };
 
-                        let tile = utils::create_tile(&tiles[tile_idx as usize], &self.bg_palette);
+                        let tile = utils::tile_indices(&tiles[tile_idx as usize]);
                         let tile_data = tile.chunks_exact(8);
 
                         for (tile_y, line) in tile_data.enumerate() {
@@ -520,3 +821,61 @@ impl GameboyPPU {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::JoypadHandler;
+
+    fn ppu() -> (GameboyPPU, Arc<RwLock<GameboyMemory>>) {
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), vec![0; 0x8000], gb_joy)));
+        let gb_cyc = Arc::new(RwLock::new(0));
+
+        (GameboyPPU::init(gb_cyc, gb_mem.clone()), gb_mem)
+    }
+
+    #[test]
+    fn ly_writes_reset_it() {
+        let (mut ppu, gb_mem) = ppu();
+
+        ppu.set_ly(0x40);
+        gb_mem.write().unwrap().write(0xFF44, 0x99);
+
+        // The value written is dropped, the PPU resets LY once it sees the write.
+        assert_eq!(gb_mem.read().unwrap().read(0xFF44), 0x40);
+        assert!(gb_mem.write().unwrap().take_ly_reset());
+        assert!(!gb_mem.write().unwrap().take_ly_reset());
+
+        ppu.reset_ly();
+        assert_eq!(gb_mem.read().unwrap().read(0xFF44), 0x00);
+    }
+
+    #[test]
+    fn one_vblank_per_frame() {
+        let (mut ppu, gb_mem) = ppu();
+        let mut vblanks = 0;
+        let mut frames = 0;
+
+        gb_mem.write().unwrap().write(0xFF40, 0x91);
+
+        for _ in 0..(3 * 70224 / 4) {
+            *ppu.gb_cyc.write().unwrap() += 4;
+
+            if ppu.ppu_cycle() {
+                frames += 1;
+            }
+
+            // Acknowledge it like a handler would, so each request gets counted once.
+            let mut lock = gb_mem.write().unwrap();
+
+            if lock.read(0xFF0F) & 0x01 != 0 {
+                vblanks += 1;
+                lock.write(0xFF0F, 0x00);
+            }
+        }
+
+        assert_eq!(frames, 3);
+        assert_eq!(vblanks, 3);
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::VecDeque;
+
+// Copies of the screen buffer, one per frame. Once it's full the oldest
+// frames get dropped, so it always holds the last max_frames of gameplay.
+pub struct FrameRecorder {
+    frames: VecDeque<Vec<u8>>,
+    max_frames: usize
+}
+
+impl FrameRecorder {
+    pub fn new(max_frames: usize) -> FrameRecorder {
+        FrameRecorder {
+            frames: VecDeque::new(),
+            max_frames: max_frames.max(1)
+        }
+    }
+
+    pub fn push(&mut self, frame: &[u8]) {
+        // Reuse the buffer of the frame that's getting dropped.
+        let buffer = {
+            if self.frames.len() >= self.max_frames {
+                self.frames.pop_front().map(|mut buffer| {
+                    buffer.clear();
+                    buffer.extend_from_slice(frame);
+                    buffer
+                })
+            }
+            else {
+                None
+            }
+        };
+
+        self.frames.push_back(buffer.unwrap_or_else(|| frame.to_vec()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn into_frames(self) -> Vec<Vec<u8>> {
+        self.frames.into()
+    }
+}
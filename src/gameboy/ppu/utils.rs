@@ -7,7 +7,9 @@ use glium::{Display, Texture2d};
 use glium::texture::{ClientFormat, RawImage2d};
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior};
 
-const BASE_PALETTE: [u8; 4] = [255, 192, 96, 0];
+use crate::log;
+
+pub const BASE_PALETTE: [u8; 4] = [255, 192, 96, 0];
 
 #[derive(Clone)]
 pub struct Palette {
@@ -84,21 +86,49 @@ impl GameboyTexture {
             self.id = Some(textures.insert(texture));
         }
         else {
-            println!("Error updating texture.");
+            log::error("Renderer", "Couldn't update a texture.");
         }
     }
 }
 
 pub fn create_tile(data: &[u8], palette: &Palette) -> Vec<u8> {
+    tile_indices(data).into_iter().map(|color_idx| palette.get_color(color_idx)).collect()
+}
+
+/// Packs 64 color indices back into the 16 bytes of a tile, the opposite of tile_indices().
+pub fn tile_data(indices: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16);
+
+    for tile_line in indices.chunks_exact(8) {
+        let mut low = 0;
+        let mut high = 0;
+
+        for (bit, color_idx) in tile_line.iter().rev().enumerate() {
+            low |= (color_idx & 1) << bit;
+            high |= ((color_idx >> 1) & 1) << bit;
+        }
+
+        data.push(low);
+        data.push(high);
+    }
+
+    data
+}
+
+/// Gets the color index with the closest shade on BASE_PALETTE.
+pub fn closest_color_index(shade: u8) -> u8 {
+    (0..4).min_by_key(|idx| (BASE_PALETTE[*idx as usize] as i16 - shade as i16).abs()).unwrap_or(0)
+}
+
+/// Color indices (0-3) of the 8x8 pixels in a tile, before applying a palette.
+pub fn tile_indices(data: &[u8]) -> Vec<u8> {
     let mut tile = Vec::with_capacity(64);
     let chunks = data.chunks_exact(2);
 
     for tile_line in chunks {
         for bit in (0..8).rev() {
             let color_idx = ((tile_line[0] >> bit) & 1) | (((tile_line[1] >> bit) & 1) << 1);
-            let pixel_color = palette.get_color(color_idx);
-
-            tile.push(pixel_color);
+            tile.push(color_idx);
         }
     }
 
@@ -0,0 +1,250 @@
+// Helpers to save and load the emulator state.
+// The format is just every component's fields written in order, so states
+// are only meant to be loaded back by the same version of the emulator.
+
+pub const STATE_MAGIC: &[u8; 4] = b"RBST";
+pub const STATE_VERSION: u8 = 2;
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.data.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    // Length-prefixed, so it can be checked when loading.
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+            position: 0
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(len)?;
+        let result = self.data.get(self.position..end)?;
+
+        self.position = end;
+        Some(result)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_usize(&mut self) -> Option<usize> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+
+        Some(u64::from_le_bytes(bytes) as usize)
+    }
+
+    pub fn read_bool(&mut self) -> Option<bool> {
+        Some(self.read_u8()? != 0)
+    }
+
+    pub fn read_bytes(&mut self) -> Option<&'a [u8]> {
+        let mut len = [0; 4];
+        len.copy_from_slice(self.take(4)?);
+
+        self.take(u32::from_le_bytes(len) as usize)
+    }
+
+    // Fails if the saved data doesn't have the same size as the target.
+    pub fn read_bytes_into(&mut self, target: &mut [u8]) -> Option<()> {
+        copy_saved(target, self.read_bytes()?)
+    }
+}
+
+// Copies saved bytes over a component's own buffer, failing if the sizes don't match.
+pub fn copy_saved(target: &mut [u8], saved: &[u8]) -> Option<()> {
+    if saved.len() != target.len() {
+        return None;
+    }
+
+    target.copy_from_slice(saved);
+    Some(())
+}
+
+use crate::gameboy::cpu::SavedCpu;
+use crate::gameboy::memory::SavedMemory;
+
+pub struct MemoryRegion {
+    pub name: &'static str,
+    pub start: u16,
+    pub data: Vec<u8>
+}
+
+// The machine state inside a save state file, read without loading it into an emulator.
+// Cartridge and PPU data come after these, and depend on the mapper, so they're left out.
+pub struct StateSnapshot {
+    pub title: String,
+    pub cycles: usize,
+
+    pub registers: [(&'static str, u16); 6],
+    pub flags: [(&'static str, bool); 5],
+    pub regions: Vec<MemoryRegion>
+}
+
+pub struct StateDiff {
+    // Name, then the values in the first and second state.
+    pub fields: Vec<(String, String, String)>,
+    pub bytes: Vec<(u16, u8, u8)>
+}
+
+impl StateSnapshot {
+    // Reads the components with the same readers they load themselves with, so the layout only lives there.
+    pub fn parse(data: &[u8]) -> Result<StateSnapshot, String> {
+        let truncated = || String::from("The save state is truncated or corrupted.");
+        let mut state = StateReader::new(data);
+
+        if state.read_bytes() != Some(&STATE_MAGIC[..]) {
+            return Err(String::from("Not a save state file."));
+        }
+
+        if state.read_u8() != Some(STATE_VERSION) {
+            return Err(String::from("The save state was made by a different version of the emulator."));
+        }
+
+        let title = String::from_utf8_lossy(state.read_bytes().ok_or_else(truncated)?).to_string();
+        let cycles = state.read_usize().ok_or_else(truncated)?;
+
+        let cpu = SavedCpu::read(&mut state).ok_or_else(truncated)?;
+        let memory = SavedMemory::read(&mut state).ok_or_else(truncated)?;
+
+        let regions = vec![
+            MemoryRegion { name: "VRAM", start: 0x8000, data: memory.vram.to_vec() },
+            MemoryRegion { name: "WRAM", start: 0xC000, data: memory.wram.to_vec() },
+            MemoryRegion { name: "OAM", start: 0xFE00, data: memory.oam.to_vec() },
+            MemoryRegion { name: "IO", start: 0xFF00, data: memory.io },
+            MemoryRegion { name: "HRAM", start: 0xFF80, data: memory.hram.to_vec() },
+            MemoryRegion { name: "IE", start: 0xFFFF, data: vec![memory.ie] }
+        ];
+
+        Ok(StateSnapshot {
+            title,
+            cycles,
+
+            registers: [("AF", cpu.af), ("BC", cpu.bc), ("DE", cpu.de), ("HL", cpu.hl), ("SP", cpu.sp), ("PC", cpu.pc)],
+            flags: [
+                ("Halted", cpu.halted), ("HALT bug", cpu.halt_bug), ("Stopped", cpu.stopped),
+                ("IME", cpu.interrupts.ime), ("EI pending", cpu.interrupts.ei_executed)
+            ],
+            regions
+        })
+    }
+
+    pub fn diff(&self, other: &StateSnapshot) -> StateDiff {
+        let mut fields = Vec::new();
+        let mut bytes = Vec::new();
+
+        if self.title != other.title {
+            fields.push((String::from("Game"), self.title.clone(), other.title.clone()));
+        }
+
+        if self.cycles != other.cycles {
+            fields.push((String::from("Cycles"), self.cycles.to_string(), other.cycles.to_string()));
+        }
+
+        for ((name, a), (_, b)) in self.registers.iter().zip(other.registers.iter()) {
+            if a != b {
+                fields.push((name.to_string(), format!("{:04X}", a), format!("{:04X}", b)));
+            }
+        }
+
+        for ((name, a), (_, b)) in self.flags.iter().zip(other.flags.iter()) {
+            if a != b {
+                fields.push((name.to_string(), a.to_string(), b.to_string()));
+            }
+        }
+
+        for (a, b) in self.regions.iter().zip(other.regions.iter()) {
+            for (offset, (value_a, value_b)) in a.data.iter().zip(b.data.iter()).enumerate() {
+                if value_a != value_b {
+                    bytes.push((a.start.wrapping_add(offset as u16), *value_a, *value_b));
+                }
+            }
+        }
+
+        StateDiff {
+            fields,
+            bytes
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, RwLock};
+    use crate::gameboy::{Gameboy, JoypadHandler};
+    use crate::gameboy::memory::GameboyMemory;
+
+    #[test]
+    fn snapshot_parses_saved_states() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+
+        let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+        let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+        let gameboy = Gameboy::init(gb_mem.clone());
+
+        gb_mem.write().unwrap().write(0xC123, 0x42);
+        gb_mem.write().unwrap().write(0xFFFF, 0x1F);
+
+        let data = gameboy.save_state();
+        let snapshot = StateSnapshot::parse(&data).unwrap();
+        let cpu = gameboy.dbg_cpu_state();
+        let wram = snapshot.regions.iter().find(|region| region.name == "WRAM").unwrap();
+
+        assert_eq!(snapshot.title, "TEST");
+        assert_eq!(snapshot.cycles, gameboy.gb_get_cycles());
+        assert_eq!(snapshot.registers.iter().map(|(_, value)| *value).collect::<Vec<u16>>(), vec![cpu.af, cpu.bc, cpu.de, cpu.hl, cpu.sp, cpu.pc]);
+        assert!(snapshot.flags.iter().all(|(_, value)| !value));
+        assert_eq!(wram.data[0x0123], 0x42);
+        assert_eq!(snapshot.regions.last().unwrap().data, vec![0x1F]);
+
+        // The state still loads back into the emulator it came from.
+        let mut gameboy = gameboy;
+        assert!(gameboy.load_state(&data).is_ok());
+
+        // Cut off in the middle of the CPU registers.
+        assert!(StateSnapshot::parse(&data[..30]).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+// Messages meant for the user, like load errors and save confirmations.
+// Anything can add to the log, including the emulation thread. The UI turns
+// new entries into notifications every frame, and keeps them for the log window.
+
+use std::sync::Mutex;
+
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error
+}
+
+#[derive(Clone)]
+pub struct LogEntry {
+    pub level: Level,
+    pub title: String,
+    pub message: String,
+
+    // Whether the UI should show a notification for it.
+    pub notify: bool
+}
+
+struct Log {
+    entries: Vec<LogEntry>,
+    // Index of the first entry the UI hasn't picked up yet.
+    unread: usize
+}
+
+static LOG: Mutex<Log> = Mutex::new(Log { entries: Vec::new(), unread: 0 });
+
+pub fn push(level: Level, title: &str, message: &str, notify: bool) {
+    if let Ok(mut lock) = LOG.lock() {
+        lock.entries.push(LogEntry {
+            level,
+            title: title.to_string(),
+            message: message.to_string(),
+
+            notify
+        });
+
+        if lock.entries.len() > MAX_ENTRIES {
+            let excess = lock.entries.len() - MAX_ENTRIES;
+
+            lock.entries.drain(0..excess);
+            lock.unread = lock.unread.saturating_sub(excess);
+        }
+    }
+}
+
+pub fn info(title: &str, message: &str) {
+    push(Level::Info, title, message, true);
+}
+
+pub fn warn(title: &str, message: &str) {
+    push(Level::Warning, title, message, true);
+}
+
+pub fn error(title: &str, message: &str) {
+    push(Level::Error, title, message, true);
+}
+
+/// Returns the entries added since the last call.
+pub fn take_unread() -> Vec<LogEntry> {
+    if let Ok(mut lock) = LOG.lock() {
+        let unread = lock.entries[lock.unread..].to_vec();
+
+        lock.unread = lock.entries.len();
+        unread
+    }
+    else {
+        Vec::new()
+    }
+}
+
+pub fn entries() -> Vec<LogEntry> {
+    if let Ok(lock) = LOG.lock() {
+        lock.entries.clone()
+    }
+    else {
+        Vec::new()
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut lock) = LOG.lock() {
+        lock.entries.clear();
+        lock.unread = 0;
+    }
+}
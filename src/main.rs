@@ -1,6 +1,17 @@
 mod ui;
 mod gameboy;
+mod log;
+mod benchmark;
+mod trace_compare;
 
 fn main() {
-    ui::run_app();
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        // rusty-boy --benchmark <ROM> [seconds]
+        Some("--benchmark") => benchmark::run_from_args(&args[2..]),
+        // rusty-boy --compare-log <ROM> <reference log>
+        Some("--compare-log") => trace_compare::run_from_args(&args[2..]),
+        _ => ui::run_app()
+    }
 }
@@ -0,0 +1,122 @@
+// Runs a ROM without the UI, checking the CPU state before every instruction against
+// a Gameboy Doctor reference log. Started with --compare-log, stops at the first difference.
+//
+// The reference logs are made with LY stuck at $90, so it's faked the same way here.
+// Interrupts get dispatched together with the first instruction of their handler,
+// so the states only line up until the first interrupt is serviced.
+
+use std::sync::{Arc, RwLock};
+
+use crate::gameboy::{EmulatorMode, Gameboy, JoypadHandler};
+use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::memory::cart;
+
+pub enum Comparison {
+    // Every line of the log matched, holds how many there were.
+    Matched(usize),
+    Mismatch {
+        // Instructions run before the mismatch, also the 0-based line of the log.
+        instruction: usize,
+        pc: u16,
+        expected: String,
+        actual: String
+    },
+    // The emulator stopped on its own before the end of the log.
+    Stopped {
+        instruction: usize,
+        reason: String
+    }
+}
+
+impl Comparison {
+    pub fn print(&self) {
+        match self {
+            Comparison::Matched(lines) => println!("All {} lines match.", lines),
+            Comparison::Mismatch { instruction, pc, expected, actual } => {
+                println!("Mismatch after {} instructions (line {}), at PC ${:04X}.", instruction, instruction + 1, pc);
+                println!("Expected: {}", expected);
+                println!("Actual:   {}", actual);
+            }
+            Comparison::Stopped { instruction, reason } => {
+                println!("The emulator stopped after {} instructions: {}.", instruction, reason);
+            }
+        }
+    }
+}
+
+pub fn run(rom: Vec<u8>, reference: &str) -> Result<Comparison, String> {
+    cart::check_rom(&rom)?;
+
+    let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
+    let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(Vec::new(), rom, gb_joy)));
+
+    if let Ok(mut lock) = gb_mem.write() {
+        lock.set_ly_override(Some(0x90));
+    }
+
+    let mut gb = Gameboy::init(gb_mem);
+    let mut lines = reference.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate();
+
+    gb.dbg_mode = EmulatorMode::Running;
+
+    while let Some((instruction, expected)) = lines.next() {
+        let state = gb.dbg_cpu_state();
+        let actual = state.to_string();
+
+        if actual != expected {
+            return Ok(Comparison::Mismatch {
+                instruction,
+                pc: state.pc,
+                expected: expected.to_string(),
+                actual
+            });
+        }
+
+        // The log only has a line per instruction, not for cycles spent halted.
+        loop {
+            gb.gb_cpu_cycle();
+            gb.gb_ppu_cycle();
+
+            if gb.dbg_mode != EmulatorMode::Running {
+                return Ok(Comparison::Stopped { instruction: instruction + 1, reason: gb.dbg_mode.to_string() });
+            }
+
+            if !gb.dbg_cpu_halted() {
+                break;
+            }
+        }
+    }
+
+    Ok(Comparison::Matched(reference.lines().filter(|line| !line.trim().is_empty()).count()))
+}
+
+pub fn run_from_args(args: &[String]) {
+    let (rom_path, log_path) = match (args.get(0), args.get(1)) {
+        (Some(rom_path), Some(log_path)) => (rom_path, log_path),
+        _ => {
+            eprintln!("Usage: rusty-boy --compare-log <ROM> <reference log>");
+            return;
+        }
+    };
+
+    let rom = match std::fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Couldn't read {} ({}).", rom_path, error);
+            return;
+        }
+    };
+
+    let reference = match std::fs::read_to_string(log_path) {
+        Ok(reference) => reference,
+        Err(error) => {
+            eprintln!("Couldn't read {} ({}).", log_path, error);
+            return;
+        }
+    };
+
+    match run(rom, &reference) {
+        Ok(comparison) => comparison.print(),
+        Err(error) => eprintln!("{} can't be loaded as a Game Boy ROM: {}.", rom_path, error)
+    }
+}
@@ -0,0 +1,23 @@
+use arboard::Clipboard;
+use imgui::ClipboardBackend;
+
+use crate::log;
+
+// Hooks imgui's clipboard up to the system one.
+pub struct ClipboardSupport(Clipboard);
+
+pub fn init() -> Option<ClipboardSupport> {
+    Clipboard::new().ok().map(ClipboardSupport)
+}
+
+impl ClipboardBackend for ClipboardSupport {
+    fn get(&mut self) -> Option<String> {
+        self.0.get_text().ok()
+    }
+
+    fn set(&mut self, text: &str) {
+        if let Err(error) = self.0.set_text(text.to_owned()) {
+            log::warn("Clipboard", &format!("Couldn't copy to the clipboard ({}).", error));
+        }
+    }
+}
@@ -1,4 +1,7 @@
 mod windows;
+mod clipboard;
+mod overrides;
+mod recording;
 
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
@@ -23,38 +26,79 @@ use ron::ser::{PrettyConfig, to_string_pretty};
 
 use windows::*;
 use windows::settings::SettingsWindow;
+use windows::game_settings::GameSettingsWindow;
 use windows::notification::Notification;
 use windows::file_picker::FilePickerWindow;
 
-use crate::gameboy::memory::GameboyMemory;
-use crate::gameboy::{EmulatorMode, Gameboy, JoypadHandler};
+use overrides::GameOverrides;
 
+use crate::gameboy::callstack::DEFAULT_MAX_DEPTH;
+use crate::gameboy::disassembler::DisassemblyStyle;
+use crate::gameboy::memory::{cart, GameboyMemory, PowerOnState};
+use crate::gameboy::ppu::Renderer;
+use crate::gameboy::ppu::utils::GameboyTexture;
+use crate::gameboy::ppu::compat_palettes::CompatPaletteChoice;
+use crate::gameboy::{DEFAULT_BATCH_SIZE, DMG_CLOCK_HZ, EmulatorMode, Gameboy, JoypadHandler, Model};
+use crate::log::{self, Level};
+
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "rom"];
 
 pub struct AppState {
     config: AppConfig,
 
     rom_data: Vec<u8>,
+    // CRC32 of rom_data, identifies the game for its per-game settings.
+    rom_hash: u32,
     bootrom_data: Vec<u8>,
 
     reload: bool,
     picking_rom: bool,
     picking_bootrom: bool,
     settings_opened: bool,
+    game_settings_opened: bool,
 
     gb: Option<Arc<RwLock<Gameboy>>>,
     gb_mem: Option<Arc<RwLock<GameboyMemory>>>,
     gb_exit_tx: Option<Sender<()>>,
 
+    // Filled by the panic hook, so crashes in the emulation thread can be reported.
+    crash_report: Arc<RwLock<Option<String>>>,
+    crash_message: Option<String>,
+
     notifications: Vec<Notification>,
     file_picker_instance: FilePickerWindow,
 
+    // Quick slot requested from the keyboard as (slot, saving), handled on the next frame.
+    pending_quick_slot: Option<(u8, bool)>,
+    // Set by the dump key, handled on the next frame so the clipboard can be used.
+    pending_state_dump: bool,
+    // Set when a ROM gets picked, so its resume state only gets loaded then and not on reloads.
+    resume_pending: bool,
+    // Set while fast-forwarding, see FastForwardMode.
+    fast_forwarding: bool,
+    // Key repeat sends a lot of presses, this tells the first one apart.
+    fast_forward_key_down: bool,
+    // Set when a ROM gets loaded paused, until the emulator runs for the first time.
+    waiting_for_start: bool,
+    // Window positions get reset before the next frame, see default_layout_ini().
+    reset_layout: bool,
+
     window_cart_info: (bool, Option<cart_info::CartWindow>),
+    window_cart_ram: (bool, Option<cart_ram::CartRamWindow>),
     window_cpu_debugger: (bool, Option<cpu_debugger::CPUWindow>),
+    window_debug_console: (bool, Option<debug_console::DebugConsoleWindow>),
     window_disassembler: (bool, Option<disassembler::DisassemblerWindow>),
+    window_freeze: (bool, Option<freeze::FreezeWindow>),
     window_memory_viewer: (bool, Option<memory_viewer::MemoryWindow>),
     window_screen: (bool, Option<screen::ScreenWindow>),
     window_serial: (bool, Option<serial_output::SerialWindow>),
-    window_vram_viewer: (bool, Option<vram_viewer::VramViewerWindow>)
+    window_vram_viewer: (bool, Option<vram_viewer::VramViewerWindow>),
+    window_watch: (bool, Option<watch::WatchWindow>),
+    // These two don't need a ROM, and get created the first time they're opened.
+    window_log: (bool, Option<log_viewer::LogWindow>),
+    window_state_diff: (bool, Option<state_diff::StateDiffWindow>),
+    // Only available while no ROM is loaded.
+    window_test_pattern: (bool, Option<screen::ScreenWindow>)
 }
 
 impl AppState {
@@ -66,34 +110,120 @@ impl AppState {
             config,
 
             rom_data: Vec::new(),
+            rom_hash: 0,
             bootrom_data: Vec::new(),
 
             reload: false,
             picking_rom: false,
             picking_bootrom: false,
             settings_opened: false,
+            game_settings_opened: false,
 
             gb: None,
             gb_mem: None,
             gb_exit_tx: None,
 
+            crash_report: Arc::new(RwLock::new(None)),
+            crash_message: None,
+
             notifications: Vec::new(),
             file_picker_instance: FilePickerWindow::init(current_path),
 
+            pending_quick_slot: None,
+            pending_state_dump: false,
+            resume_pending: false,
+            fast_forwarding: false,
+            fast_forward_key_down: false,
+            waiting_for_start: false,
+            reset_layout: false,
+
             window_cart_info: (false, None),
+            window_cart_ram: (false, None),
             window_cpu_debugger: (false, None),
+            window_debug_console: (false, None),
             window_disassembler: (false, None),
+            window_freeze: (false, None),
             window_memory_viewer: (false, None),
             window_screen: (false, None),
             window_serial: (false, None),
-            window_vram_viewer: (false, None)
+            window_vram_viewer: (false, None),
+            window_watch: (false, None),
+            window_log: (false, None),
+            window_state_diff: (false, None),
+            window_test_pattern: (false, None)
+        }
+    }
+
+    fn emu_stop(&mut self) {
+        self.emu_stop_trace();
+
+        if let Some(tx) = self.gb_exit_tx.as_ref() {
+            // The thread might be gone already if it crashed.
+            tx.send(()).ok();
+        }
+
+        self.gb = None;
+        self.gb_mem = None;
+        self.gb_exit_tx = None;
+    }
+
+    // Exiting ends the process without dropping anything, so everything that
+    // has to reach the disk gets written here first.
+    fn shutdown(&mut self) {
+        self.emu_set_mode(EmulatorMode::Paused);
+        self.emu_save_resume_state();
+
+        if let Some(Ok(mut lock)) = self.gb.as_ref().map(|gb| gb.write()) {
+            lock.gb_save_battery();
+        }
+
+        self.emu_stop();
+        self.config.save();
+    }
+
+    // Checks for a panic in the emulation thread or a poisoned lock left behind by one.
+    fn emu_check_health(&mut self) {
+        if self.crash_message.is_some() || self.gb.is_none() {
+            return;
+        }
+
+        if let Ok(mut lock) = self.crash_report.write() {
+            if let Some(message) = lock.take() {
+                self.crash_message = Some(message);
+                return;
+            }
+        }
+
+        let gb_poisoned = self.gb.as_ref().map(|gb| gb.is_poisoned()).unwrap_or(false);
+        let mem_poisoned = self.gb_mem.as_ref().map(|mem| mem.is_poisoned()).unwrap_or(false);
+
+        if gb_poisoned || mem_poisoned {
+            self.crash_message = Some(String::from("The emulator state was left poisoned by a panic."));
         }
     }
 
+    // There are three ways of starting over:
+    // - Reset: resets the emulated hardware and leaves it paused, handy for debugging from the first instruction.
+    //   Breakpoints survive it unless clear_breakpoints_on_reset is set, see Gameboy::gb_reset() for the rest.
+    // - Restart: same as reset, but keeps running. Breakpoints and open windows are kept around.
+    // - Reload: throws the whole emulator away and builds a new one from the loaded ROM and bootrom.
+    //   Only the breakpoints saved with the game's overrides come back, every other bit of debugger state is lost.
     fn emu_reset(&self) {
         if let Some(gb) = self.gb.as_ref() {
             if let Ok(mut lock) = gb.write() {
                 lock.gb_reset();
+
+                if self.config.clear_breakpoints_on_reset {
+                    lock.dbg_breakpoint_list.clear();
+                }
+            }
+        }
+    }
+
+    fn emu_restart(&self) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.gb_restart();
             }
         }
     }
@@ -106,6 +236,287 @@ impl AppState {
         }
     }
 
+    fn emu_step_frame(&self) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.dbg_step_frame();
+            }
+        }
+    }
+
+    fn emu_set_batch_size(&self, batch_size: u32) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.batch_size = batch_size as usize;
+            }
+        }
+    }
+
+    fn emu_set_fast_boot(&self, enabled: bool) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_fast_boot(enabled);
+            }
+        }
+    }
+
+    fn emu_set_skip_unknown_opcodes(&self, skip: bool) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_skip_unknown_opcodes(skip);
+            }
+        }
+    }
+
+    fn emu_set_callstack_max_depth(&self, max_depth: u32) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_callstack_max_depth(max_depth as usize);
+            }
+        }
+    }
+
+    fn emu_set_renderer(&self, renderer: Renderer) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_renderer(renderer);
+            }
+        }
+    }
+
+    fn emu_set_clock_speed(&self, hz: u32) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_clock_speed(hz);
+            }
+        }
+    }
+
+    fn emu_set_ram_autosave_interval(&self, seconds: u32) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.ram_autosave_interval = seconds as u64;
+            }
+        }
+    }
+
+    // Keyed by the ROM hash like the resume state, so games with the same title don't share slots.
+    fn quick_slot_path(&self, slot: u8) -> Option<PathBuf> {
+        self.gb.as_ref()?;
+
+        Some(self.config.save_state_dir.join(format!("{:08X}.slot{}.state", self.rom_hash, slot)))
+    }
+
+    fn emu_quick_save(&self, slot: u8) -> Result<(), String> {
+        let path = self.quick_slot_path(slot).ok_or_else(|| String::from("no game loaded"))?;
+        let gb = self.gb.as_ref().ok_or_else(|| String::from("no game loaded"))?;
+
+        let data = {
+            if let Ok(lock) = gb.read() {
+                lock.save_state()
+            }
+            else {
+                return Err(String::from("the emulator is unavailable"));
+            }
+        };
+
+        std::fs::create_dir_all(&self.config.save_state_dir).map_err(|e| e.to_string())?;
+        std::fs::write(path, data).map_err(|e| e.to_string())
+    }
+
+    fn emu_quick_load(&self, slot: u8) -> Result<(), String> {
+        let path = self.quick_slot_path(slot).ok_or_else(|| String::from("no game loaded"))?;
+        let gb = self.gb.as_ref().ok_or_else(|| String::from("no game loaded"))?;
+
+        if !path.exists() {
+            return Err(String::from("the slot is empty"));
+        }
+
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+
+        if let Ok(mut lock) = gb.write() {
+            lock.load_state(&data)
+        }
+        else {
+            Err(String::from("the emulator is unavailable"))
+        }
+    }
+
+    // Keyed by the ROM hash instead of the title, so it can't be mixed up with another revision of the game.
+    fn resume_state_path(&self) -> PathBuf {
+        self.config.save_state_dir.join(format!("{:08X}.resume.state", self.rom_hash))
+    }
+
+    /// Saves the state of the running game to load it the next time it starts, if resuming is enabled.
+    fn emu_save_resume_state(&self) {
+        if !self.config.resume_on_startup {
+            return;
+        }
+
+        let data = match self.gb.as_ref().and_then(|gb| gb.read().ok().map(|lock| lock.save_state())) {
+            Some(data) => data,
+            None => return
+        };
+
+        let result = std::fs::create_dir_all(&self.config.save_state_dir)
+            .and_then(|_| std::fs::write(self.resume_state_path(), data))
+        ;
+
+        if let Err(error) = result {
+            log::error("Resume", &format!("Couldn't save the state to resume from ({}).", error));
+        }
+    }
+
+    fn emu_load_resume_state(&self) {
+        let path = self.resume_state_path();
+
+        if !self.config.resume_on_startup || !path.exists() {
+            return;
+        }
+
+        let result = match std::fs::read(&path) {
+            Ok(data) => {
+                if let Some(Ok(mut lock)) = self.gb.as_ref().map(|gb| gb.write()) {
+                    lock.load_state(&data)
+                }
+                else {
+                    Err(String::from("the emulator is unavailable"))
+                }
+            }
+            Err(error) => Err(error.to_string())
+        };
+
+        match result {
+            Ok(_) => log::info("Resume", "Resumed where the game was left."),
+            Err(error) => log::warn("Resume", &format!("Couldn't resume the game, starting it from scratch ({}).", error))
+        }
+    }
+
+    fn emu_set_fast_forward(&mut self, enabled: bool) {
+        if self.fast_forwarding == enabled {
+            return;
+        }
+
+        self.fast_forwarding = enabled;
+
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.set_fast_forward(enabled, self.config.fast_forward_frame_skip as usize);
+            }
+        }
+    }
+
+    fn emu_fast_forward_key(&mut self, pressed: bool) {
+        let first_press = pressed && !self.fast_forward_key_down;
+
+        self.fast_forward_key_down = pressed;
+
+        match self.config.fast_forward_mode {
+            FastForwardMode::Hold => self.emu_set_fast_forward(pressed),
+            FastForwardMode::Toggle => {
+                if first_press {
+                    self.emu_set_fast_forward(!self.fast_forwarding);
+                }
+            }
+        }
+    }
+
+    fn emu_start_recording(&self) {
+        if let Some(gb) = self.gb.as_ref() {
+            if let Ok(mut lock) = gb.write() {
+                lock.start_recording(self.config.recording_max_seconds as usize * 60);
+            }
+        }
+    }
+
+    fn emu_recorded_frames(&self) -> Option<usize> {
+        self.gb.as_ref().and_then(|gb| gb.read().ok()?.recorded_frames())
+    }
+
+    // Encoding can take a while, so it's done on its own thread.
+    fn emu_stop_recording(&self) {
+        let frames = {
+            if let Some(Ok(mut lock)) = self.gb.as_ref().map(|gb| gb.write()) {
+                lock.stop_recording()
+            }
+            else {
+                return;
+            }
+        };
+
+        if frames.is_empty() {
+            log::warn("Recorder", "Nothing was recorded, is the emulator paused?");
+            return;
+        }
+
+        let title = {
+            if let Some(Ok(lock)) = self.gb_mem.as_ref().map(|gb_mem| gb_mem.read()) {
+                lock.header().title().clone()
+            }
+            else {
+                String::from("recording")
+            }
+        };
+
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        let path = self.config.recordings_dir.join(format!("{}-{}.gif", title, timestamp));
+
+        if let Err(error) = std::fs::create_dir_all(&self.config.recordings_dir) {
+            log::error("Recorder", &format!("Couldn't create the recordings directory ({}).", error));
+            return;
+        }
+
+        std::thread::spawn(move || {
+            match recording::save_gif(&path, &frames) {
+                Ok(_) => log::info("Recorder", &format!("Saved {}.", path.display())),
+                Err(error) => log::error("Recorder", &format!("Failed to save {} ({}).", path.display(), error))
+            }
+        });
+    }
+
+    fn emu_start_trace(&self) {
+        let title = {
+            if let Some(Ok(lock)) = self.gb_mem.as_ref().map(|gb_mem| gb_mem.read()) {
+                lock.header().title().clone()
+            }
+            else {
+                String::from("trace")
+            }
+        };
+
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        let path = self.config.traces_dir.join(format!("{}-{}.log", title, timestamp));
+
+        if let Err(error) = std::fs::create_dir_all(&self.config.traces_dir) {
+            log::error("Trace", &format!("Couldn't create the traces directory ({}).", error));
+            return;
+        }
+
+        if let Some(Ok(mut lock)) = self.gb.as_ref().map(|gb| gb.write()) {
+            match lock.start_trace(&path) {
+                Ok(_) => log::info("Trace", &format!("Tracing to {}.", path.display())),
+                Err(error) => log::error("Trace", &format!("Couldn't create {} ({}).", path.display(), error))
+            }
+        }
+    }
+
+    fn emu_traced_lines(&self) -> Option<usize> {
+        self.gb.as_ref().and_then(|gb| gb.read().ok()?.traced_lines())
+    }
+
+    fn emu_stop_trace(&self) {
+        if self.emu_traced_lines().is_none() {
+            return;
+        }
+
+        if let Some(Ok(mut lock)) = self.gb.as_ref().map(|gb| gb.write()) {
+            match lock.stop_trace() {
+                Ok(lines) => log::info("Trace", &format!("Stopped tracing after {} lines.", lines)),
+                Err(error) => log::error("Trace", &format!("Couldn't finish writing the trace ({}).", error))
+            }
+        }
+    }
+
     fn emu_get_mode(&self) -> EmulatorMode {
         if let Some(gb) = self.gb.as_ref() {
             if let Ok(lock) = gb.read() {
@@ -125,42 +536,298 @@ impl AppState {
     }
 }
 
-#[derive(Default, Deserialize, Serialize)]
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+pub enum FastForwardMode {
+    // Only while the key is held down.
+    Hold,
+    // Each press turns it on or off.
+    Toggle
+}
+
+/// Presets for the settings that trade accuracy for speed. Changing one of
+/// those by hand switches to Custom, keeping whatever values they had.
+/// VRAM and OAM access blocking isn't emulated and the 10 sprites per line limit
+/// is always on, so no profile changes those.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AccuracyProfile {
+    // Pixel FIFO renderer, and batches of 16 instructions so pausing and
+    // stepping from the UI stop closer to where they were asked to.
+    Accurate,
+    // Pixel FIFO renderer, and the default batches of 64 instructions.
+    Balanced,
+    // Scanline renderer, and batches of 256 instructions to spend less time on locking.
+    Fast,
+    Custom
+}
+
+impl AccuracyProfile {
+    /// Renderer and instructions per batch the profile uses, None for Custom.
+    pub fn settings(&self) -> Option<(Renderer, u32)> {
+        match self {
+            AccuracyProfile::Accurate => Some((Renderer::PixelFifo, 16)),
+            AccuracyProfile::Balanced => Some((Renderer::PixelFifo, DEFAULT_BATCH_SIZE as u32)),
+            AccuracyProfile::Fast => Some((Renderer::Scanline, 256)),
+            AccuracyProfile::Custom => None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct AppConfig {
     keybinds: Keybinds,
     screen_size: [f32; 2],
+    // Snaps the screen to the largest whole multiple of 160x144 that fits the window.
+    screen_integer_scale: bool,
+    // Draws the pressed buttons on a corner of the screen.
+    screen_input_overlay: bool,
+    screen_gamma: f32,
+    screen_contrast: f32,
+    // How much of the previous frame is blended into each new one, like the slow DMG LCD. 0 disables it.
+    screen_ghosting: f32,
+    // Sets renderer and emulation_batch_size, which can still be changed on their own.
+    accuracy_profile: AccuracyProfile,
+    renderer: Renderer,
+    emulation_batch_size: u32,
+    // While fast-forwarding, only one in every this many frames gets drawn.
+    fast_forward_frame_skip: u32,
+    fast_forward_mode: FastForwardMode,
+    power_on_state: PowerOnState,
+    model: Model,
+    // Emulated cycles per real second, only changes how fast the game runs.
+    cpu_clock_hz: u32,
+    // Colors for DMG games while emulating a CGB.
+    compat_palette: CompatPaletteChoice,
+    // Runs unknown opcodes as NOPs and logs them, instead of stopping.
+    skip_unknown_opcodes: bool,
+    // Runs the bootrom as fast as possible, see Gameboy::set_fast_boot().
+    fast_boot: bool,
+    // Saves a state when closing the emulator or loading another ROM, and loads it the next time the game starts.
+    resume_on_startup: bool,
+    // The dump key also copies the disassembly and registers to the clipboard.
+    copy_state_dump: bool,
+    // Lets games print to the debug console, see memory::DEBUG_CHANNEL_ADDRESS.
+    debug_channel: bool,
+    // Keeps each game's breakpoints with its overrides, so they survive reloads and restarts.
+    persist_breakpoints: bool,
+    // Resetting from the menu or the CPU debugger also removes every breakpoint.
+    clear_breakpoints_on_reset: bool,
+    // Calls kept in the callstack, older ones are only counted.
+    callstack_max_depth: u32,
+    disassembly_style: DisassemblyStyle,
+    default_windows: WindowSet,
+    // Seconds between saves of the cart RAM while running, 0 only saves when the game disables it.
+    ram_autosave_interval: u32,
 
     pause_emulator_on_startup: bool,
+    // Tells how to start the game on the screen while it's paused on startup.
+    show_startup_prompt: bool,
     pause_emulator_on_focus_loss: bool,
 
     last_dir_rom: PathBuf,
-    last_dir_bootrom: PathBuf
+    last_dir_bootrom: PathBuf,
+    // Where quick slots get saved, one file per game and slot.
+    save_state_dir: PathBuf,
+    recordings_dir: PathBuf,
+    traces_dir: PathBuf,
+    // Recordings only keep this many seconds, dropping the oldest frames.
+    recording_max_seconds: u32,
+    // Last used address for the link cable.
+    link_host: String,
+    link_port: u16,
+
+    // Settings of the loaded game, layered on top of the ones above.
+    // Kept out of config.ron, use the getters below to read the values in effect.
+    #[serde(skip)]
+    overrides: GameOverrides
+}
+
+impl Default for AppConfig {
+    fn default() -> AppConfig {
+        AppConfig {
+            keybinds: Keybinds::default(),
+            screen_size: [160.0, 144.0],
+            screen_integer_scale: false,
+            screen_input_overlay: false,
+            screen_gamma: 1.0,
+            screen_contrast: 1.0,
+            screen_ghosting: 0.0,
+            accuracy_profile: AccuracyProfile::Balanced,
+            renderer: Renderer::default(),
+            emulation_batch_size: DEFAULT_BATCH_SIZE as u32,
+            fast_forward_frame_skip: 4,
+            fast_forward_mode: FastForwardMode::Hold,
+            power_on_state: PowerOnState::Zeroed,
+            model: Model::Dmg,
+            cpu_clock_hz: DMG_CLOCK_HZ,
+            compat_palette: CompatPaletteChoice::Auto,
+            skip_unknown_opcodes: false,
+            fast_boot: false,
+            resume_on_startup: false,
+            copy_state_dump: true,
+            debug_channel: false,
+            persist_breakpoints: true,
+            clear_breakpoints_on_reset: false,
+            callstack_max_depth: DEFAULT_MAX_DEPTH as u32,
+            disassembly_style: DisassemblyStyle::default(),
+            default_windows: WindowSet::default(),
+            ram_autosave_interval: 30,
+
+            pause_emulator_on_startup: false,
+            show_startup_prompt: true,
+            pause_emulator_on_focus_loss: false,
+
+            last_dir_rom: PathBuf::new(),
+            last_dir_bootrom: PathBuf::new(),
+            save_state_dir: PathBuf::from("states"),
+            recordings_dir: PathBuf::from("recordings"),
+            traces_dir: PathBuf::from("traces"),
+            recording_max_seconds: 30,
+            link_host: String::from("127.0.0.1"),
+            link_port: 8765,
+
+            overrides: GameOverrides::default()
+        }
+    }
 }
 
 impl AppConfig {
     pub fn load() -> AppConfig {
         if let Ok(file) = std::fs::File::open("config.ron") {
-            if let Ok(config) = from_reader(file) {
+            if let Ok(mut config) = from_reader::<_, AppConfig>(file) {
+                // Configs from before profiles existed, or edited by hand, can name one that doesn't match.
+                if config.accuracy_profile.settings() != Some((config.renderer, config.emulation_batch_size)) {
+                    config.accuracy_profile = AccuracyProfile::Custom;
+                }
+
                 return config;
             }
         }
         
-        AppConfig {
-            screen_size: [160.0, 144.0],
-            ..Default::default()
+        AppConfig::default()
+    }
+
+    /// Switches to a profile, overwriting the settings it covers.
+    pub fn set_accuracy_profile(&mut self, profile: AccuracyProfile) {
+        if let Some((renderer, batch_size)) = profile.settings() {
+            self.renderer = renderer;
+            self.emulation_batch_size = batch_size;
         }
+
+        self.accuracy_profile = profile;
     }
 
     pub fn save(&self) {
         if let Ok(data) = to_string_pretty(self, PrettyConfig::default()) {
             if let Err(error) = std::fs::write("config.ron", data) {
-                println!("Error saving config: {}", error.to_string());
+                log::error("Settings", &format!("Couldn't save the config ({}).", error));
             }
         }
     }
+
+    pub fn keybinds(&self) -> &Keybinds {
+        self.overrides.keybinds.as_ref().unwrap_or(&self.keybinds)
+    }
+
+    pub fn screen_gamma(&self) -> f32 {
+        self.overrides.screen_gamma.unwrap_or(self.screen_gamma)
+    }
+
+    pub fn screen_contrast(&self) -> f32 {
+        self.overrides.screen_contrast.unwrap_or(self.screen_contrast)
+    }
+
+    pub fn emulation_batch_size(&self) -> u32 {
+        self.overrides.emulation_batch_size.unwrap_or(self.emulation_batch_size)
+    }
+
+    pub fn pause_emulator_on_startup(&self) -> bool {
+        self.overrides.pause_emulator_on_startup.unwrap_or(self.pause_emulator_on_startup)
+    }
+
+    pub fn skip_bootrom(&self) -> bool {
+        self.overrides.skip_bootrom.unwrap_or(false)
+    }
+
+    pub fn fast_boot(&self) -> bool {
+        self.overrides.fast_boot.unwrap_or(self.fast_boot)
+    }
+
+    pub fn model(&self) -> Model {
+        self.overrides.model.unwrap_or(self.model)
+    }
+
+    pub fn compat_palette(&self) -> CompatPaletteChoice {
+        self.overrides.compat_palette.unwrap_or(self.compat_palette)
+    }
+}
+
+// Windows that get opened after loading a ROM, or resetting the layout.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WindowSet {
+    cart_info: bool,
+    cart_ram: bool,
+    cpu_debugger: bool,
+    debug_console: bool,
+    disassembler: bool,
+    freeze: bool,
+    memory_viewer: bool,
+    screen: bool,
+    serial: bool,
+    vram_viewer: bool,
+    watch: bool
+}
+
+impl Default for WindowSet {
+    fn default() -> WindowSet {
+        WindowSet {
+            cart_info: true,
+            screen: true,
+
+            ..WindowSet::play()
+        }
+    }
+}
+
+impl WindowSet {
+    /// Only the screen.
+    pub fn play() -> WindowSet {
+        WindowSet {
+            cart_info: false,
+            cart_ram: false,
+            cpu_debugger: false,
+            debug_console: false,
+            disassembler: false,
+            freeze: false,
+            memory_viewer: false,
+            screen: true,
+            serial: false,
+            vram_viewer: false,
+            watch: false
+        }
+    }
+
+    /// Every window that needs a ROM.
+    pub fn debug() -> WindowSet {
+        WindowSet {
+            cart_info: true,
+            cart_ram: true,
+            cpu_debugger: true,
+            debug_console: true,
+            disassembler: true,
+            freeze: true,
+            memory_viewer: true,
+            screen: true,
+            serial: true,
+            vram_viewer: true,
+            watch: true
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Keybinds {
     gb_a: VirtualKeyCode,
     gb_b: VirtualKeyCode,
@@ -173,7 +840,11 @@ pub struct Keybinds {
     gb_right: VirtualKeyCode,
 
     emu_step: VirtualKeyCode,
-    emu_resume: VirtualKeyCode
+    emu_frame_step: VirtualKeyCode,
+    emu_resume: VirtualKeyCode,
+    emu_fast_forward: VirtualKeyCode,
+    // Logs the disassembly around PC and the registers.
+    emu_dump_state: VirtualKeyCode
 }
 
 impl Default for Keybinds {
@@ -190,7 +861,10 @@ impl Default for Keybinds {
             gb_right: VirtualKeyCode::Right,
 
             emu_step: VirtualKeyCode::F3,
-            emu_resume: VirtualKeyCode::F9
+            emu_frame_step: VirtualKeyCode::F4,
+            emu_resume: VirtualKeyCode::F9,
+            emu_fast_forward: VirtualKeyCode::Tab,
+            emu_dump_state: VirtualKeyCode::F8
         }
     }
 }
@@ -218,6 +892,16 @@ pub fn run_app() {
 
     let mut app_state = AppState::init();
     let mut settings_window = SettingsWindow::init();
+    let mut game_settings_window = GameSettingsWindow::init();
+
+    install_panic_hook(app_state.crash_report.clone());
+
+    if let Some(backend) = clipboard::init() {
+        imgui_ctx.set_clipboard_backend(backend);
+    }
+    else {
+        log::warn("rusty-boy", "System clipboard unavailable, copied text stays inside the emulator.");
+    }
 
     imgui_ctx.io_mut().config_flags |= imgui::ConfigFlags::DOCKING_ENABLE;
 
@@ -230,6 +914,12 @@ pub fn run_app() {
                 gl_window.window().request_redraw();
             }
             Event::RedrawRequested(_) => {
+                // Has to happen outside of a frame.
+                if app_state.reset_layout {
+                    app_state.reset_layout = false;
+                    imgui_ctx.load_ini_settings(&default_layout_ini());
+                }
+
                 let ui = imgui_ctx.frame();
                 
                 draw_menu_bar(&mut app_state, ui, control_flow);
@@ -246,14 +936,45 @@ pub fn run_app() {
                     settings_window.draw(ui, &mut app_state);
                 }
 
+                if app_state.game_settings_opened {
+                    game_settings_window.draw(ui, &mut app_state);
+                }
+
+                app_state.emu_check_health();
+
                 if app_state.reload {
                     reload_app(&mut app_state, ui);
                 }
+                else if app_state.crash_message.is_some() {
+                    draw_crash_dialog(&mut app_state, ui);
+                }
                 else if app_state.gb.is_some() {
                     draw_windows(&mut app_state, ui, &display, renderer.textures());
+                    save_breakpoints(&mut app_state);
+                }
+                else if app_state.window_test_pattern.0 {
+                    draw_test_pattern(&mut app_state, ui, &display, renderer.textures());
                 }
 
-                show_notifications(&mut app_state, ui);
+                if app_state.window_log.0 {
+                    let log_win = app_state.window_log.1.get_or_insert_with(log_viewer::LogWindow::init);
+                    log_win.draw(ui, &mut app_state.window_log.0);
+                }
+
+                if app_state.window_state_diff.0 {
+                    draw_state_diff(&mut app_state, ui);
+                }
+
+                if let Some((slot, save)) = app_state.pending_quick_slot.take() {
+                    handle_quick_slot(&mut app_state, ui, slot, save, &display, renderer.textures());
+                }
+
+                if app_state.pending_state_dump {
+                    app_state.pending_state_dump = false;
+                    dump_state(&app_state, ui);
+                }
+
+                show_notifications(&mut app_state, ui, renderer.textures());
 
                 let gl_window = display.gl_window();
                 let mut target = display.draw();
@@ -267,11 +988,26 @@ pub fn run_app() {
                 target.finish().unwrap();
             }
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                app_state.shutdown();
                 *control_flow = ControlFlow::Exit;
             }
             Event::WindowEvent { event: WindowEvent::KeyboardInput { input, ..}, ..} => {
+                if let Some(keycode) = input.virtual_keycode {
+                    if keycode == app_state.config.keybinds().emu_fast_forward && !imgui_ctx.io().want_text_input {
+                        app_state.emu_fast_forward_key(input.state == ElementState::Pressed);
+                    }
+                }
+
                 if input.state == ElementState::Pressed {
                     if let Some(keycode) = input.virtual_keycode {
+                        if keycode == app_state.config.keybinds().emu_dump_state && app_state.gb.is_some() {
+                            app_state.pending_state_dump = true;
+                        }
+
+                        if keycode == app_state.config.keybinds().emu_frame_step && app_state.emu_get_mode() != EmulatorMode::Running {
+                            app_state.emu_step_frame();
+                        }
+
                         match keycode {
                             VirtualKeyCode::F3 => {
                                 if app_state.emu_get_mode() == EmulatorMode::Stepping {
@@ -286,6 +1022,17 @@ pub fn run_app() {
                                     app_state.emu_set_mode(EmulatorMode::Paused)
                                 }
                             }
+                            // Quick slots, Shift + number saves and the number alone loads.
+                            VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3 |
+                            VirtualKeyCode::Key4 | VirtualKeyCode::Key5 | VirtualKeyCode::Key6 |
+                            VirtualKeyCode::Key7 | VirtualKeyCode::Key8 | VirtualKeyCode::Key9 => {
+                                let io = imgui_ctx.io();
+
+                                if app_state.gb.is_some() && !io.want_text_input {
+                                    let slot = keycode as u8 - VirtualKeyCode::Key1 as u8 + 1;
+                                    app_state.pending_quick_slot = Some((slot, io.key_shift));
+                                }
+                            }
                             _ => {}
                         }
                     }
@@ -300,25 +1047,94 @@ pub fn run_app() {
     });
 }
 
+// Keeps the default panic output, but also stores the message so the UI can show it.
+fn install_panic_hook(crash_report: Arc<RwLock<Option<String>>>) {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut lock) = crash_report.write() {
+            *lock = Some(info.to_string());
+        }
+
+        default_hook(info);
+    }));
+}
+
 fn create_windows(app_state: &mut AppState) {
     if let Some(gb) = app_state.gb.as_ref() {
-        app_state.window_cart_info = (true, Some(cart_info::CartWindow::init(gb.clone())));
-        app_state.window_cpu_debugger = (false, Some(cpu_debugger::CPUWindow::init(gb.clone())));
+        app_state.window_cart_info.1 = Some(cart_info::CartWindow::init(gb.clone()));
+        app_state.window_cpu_debugger.1 = Some(cpu_debugger::CPUWindow::init(gb.clone()));
+        app_state.window_debug_console.1 = Some(debug_console::DebugConsoleWindow::init(gb.clone()));
 
         if let Some(gb_mem) = app_state.gb_mem.as_ref() {
-            app_state.window_disassembler = (false, Some(disassembler::DisassemblerWindow::init(gb.clone())));
-            app_state.window_memory_viewer = (false, Some(memory_viewer::MemoryWindow::init(gb_mem.clone())));
+            app_state.window_cart_ram.1 = Some(cart_ram::CartRamWindow::init(gb_mem.clone()));
+            app_state.window_disassembler.1 = Some(disassembler::DisassemblerWindow::init(gb.clone()));
+            app_state.window_memory_viewer.1 = Some(memory_viewer::MemoryWindow::init(gb_mem.clone()));
         }
 
-        app_state.window_screen = (true, Some(screen::ScreenWindow::init(gb.clone())));
-        app_state.window_serial = (false, Some(serial_output::SerialWindow::init(gb.clone())));
-        app_state.window_vram_viewer = (false, Some(vram_viewer::VramViewerWindow::init(gb.clone())));
+        app_state.window_freeze.1 = Some(freeze::FreezeWindow::init(gb.clone()));
+        app_state.window_screen.1 = Some(screen::ScreenWindow::init(gb.clone()));
+        app_state.window_serial.1 = Some(serial_output::SerialWindow::init(gb.clone()));
+        app_state.window_vram_viewer.1 = Some(vram_viewer::VramViewerWindow::init(gb.clone()));
+        app_state.window_watch.1 = Some(watch::WatchWindow::init(gb.clone()));
+
+        open_default_windows(app_state);
     }
 }
 
+fn open_default_windows(app_state: &mut AppState) {
+    let set = &app_state.config.default_windows;
+
+    app_state.window_cart_info.0 = set.cart_info;
+    app_state.window_cart_ram.0 = set.cart_ram;
+    app_state.window_cpu_debugger.0 = set.cpu_debugger;
+    app_state.window_debug_console.0 = set.debug_console;
+    app_state.window_disassembler.0 = set.disassembler;
+    app_state.window_freeze.0 = set.freeze;
+    app_state.window_memory_viewer.0 = set.memory_viewer;
+    app_state.window_screen.0 = set.screen;
+    app_state.window_serial.0 = set.serial;
+    app_state.window_vram_viewer.0 = set.vram_viewer;
+    app_state.window_watch.0 = set.watch;
+}
+
+// Where each window goes when the layout gets reset, as (title, position, size).
+const DEFAULT_LAYOUT: [(&str, [u32; 2], [u32; 2]); 14] = [
+    ("Screen", [10, 30], [160, 144]),
+    ("Cartridge Info", [10, 230], [290, 175]),
+    ("CPU Debugger", [310, 30], [290, 400]),
+    ("Disassembler", [610, 30], [300, 325]),
+    ("Memory Viewer", [610, 365], [350, 170]),
+    ("Cartridge RAM", [970, 365], [350, 250]),
+    ("VRAM Viewer", [310, 440], [256, 256]),
+    ("Watch", [10, 415], [300, 200]),
+    ("Freeze", [920, 30], [250, 200]),
+    ("Serial Output", [610, 545], [475, 220]),
+    ("Debug Console", [610, 545], [475, 220]),
+    ("Log", [610, 545], [475, 220]),
+    ("State Diff", [920, 240], [360, 400]),
+    ("References", [920, 240], [300, 250])
+];
+
+// Settings in imgui's ini format that put every window back in its default place.
+// Loading them also undocks the windows.
+fn default_layout_ini() -> String {
+    let mut ini = String::new();
+
+    for (title, position, size) in DEFAULT_LAYOUT.iter() {
+        ini.push_str(&format!("[Window][{}]\nPos={},{}\nSize={},{}\nCollapsed=0\n\n", title, position[0], position[1], size[0], size[1]));
+    }
+
+    ini
+}
+
 fn reload_app(app_state: &mut AppState, ui: &Ui) {
-    if !app_state.rom_data.is_empty() && !app_state.bootrom_data.is_empty() {
-        let bootrom_data = app_state.bootrom_data.clone();
+    // The bootrom is optional, without one the emulator skips straight to the cartridge.
+    if !app_state.rom_data.is_empty() {
+        app_state.rom_hash = cart::crc32(&app_state.rom_data);
+        app_state.config.overrides = GameOverrides::load(app_state.rom_hash);
+
+        let bootrom_data = if app_state.config.skip_bootrom() {Vec::new()} else {app_state.bootrom_data.clone()};
         let romfile_data = app_state.rom_data.clone();
 
         let gb_joy = Arc::new(RwLock::new(JoypadHandler::default()));
@@ -326,41 +1142,136 @@ fn reload_app(app_state: &mut AppState, ui: &Ui) {
         let gb_mem = Arc::new(RwLock::new(GameboyMemory::init(bootrom_data, romfile_data, gb_joy)));
         let gb = Arc::new(RwLock::new(Gameboy::init(gb_mem.clone())));
 
+        if let Ok(mut lock) = gb.write() {
+            lock.start_rom_hashing(app_state.rom_data.clone());
+            lock.gb_set_model(app_state.config.model());
+            lock.gb_set_power_on_state(app_state.config.power_on_state);
+            lock.set_debug_channel(app_state.config.debug_channel);
+            lock.freeze_list = app_state.config.overrides.enabled_freezes();
+
+            if app_state.config.persist_breakpoints {
+                lock.dbg_breakpoint_list = app_state.config.overrides.breakpoints.clone();
+            }
+        }
+
         let gb_exit_tx = Gameboy::gb_start(gb.clone());
 
         app_state.gb = Some(gb);
         app_state.gb_mem = Some(gb_mem);
         app_state.gb_exit_tx = Some(gb_exit_tx);
+        app_state.fast_forwarding = false;
 
-        app_state.notifications.push(
-            Notification::init(
-                ImString::new("rusty-boy"),
-                ImString::new("Emulator ready!"),
-                ui.time()
-            )
-        );
+        log::info("rusty-boy", "Emulator ready!");
 
         create_windows(app_state);
+        app_state.emu_set_batch_size(app_state.config.emulation_batch_size());
+        app_state.emu_set_ram_autosave_interval(app_state.config.ram_autosave_interval);
+        app_state.emu_set_skip_unknown_opcodes(app_state.config.skip_unknown_opcodes);
+        app_state.emu_set_fast_boot(app_state.config.fast_boot());
+        app_state.emu_set_clock_speed(app_state.config.cpu_clock_hz);
+        app_state.emu_set_renderer(app_state.config.renderer);
+        app_state.emu_set_callstack_max_depth(app_state.config.callstack_max_depth);
+
+        if app_state.resume_pending {
+            app_state.resume_pending = false;
+            app_state.emu_load_resume_state();
+        }
 
-        if !app_state.config.pause_emulator_on_startup {
+        if !app_state.config.pause_emulator_on_startup() {
             app_state.emu_set_mode(EmulatorMode::Running);
         }
+
+        app_state.waiting_for_start = app_state.config.pause_emulator_on_startup() && app_state.config.show_startup_prompt;
     }
 
     app_state.reload = false;
 }
 
-fn show_notifications(app_state: &mut AppState, ui: &Ui) {
+// Breakpoints get edited from a few windows, so they're compared here instead.
+fn save_breakpoints(app_state: &mut AppState) {
+    if !app_state.config.persist_breakpoints {
+        return;
+    }
+
+    if let Some(gb) = app_state.gb.as_ref() {
+        if let Ok(lock) = gb.read() {
+            if lock.dbg_breakpoint_list != app_state.config.overrides.breakpoints {
+                app_state.config.overrides.breakpoints = lock.dbg_breakpoint_list.clone();
+                app_state.config.overrides.save(app_state.rom_hash);
+            }
+        }
+    }
+}
+
+fn handle_quick_slot(app_state: &mut AppState, ui: &Ui, slot: u8, save: bool, display: &Display, textures: &mut Textures<Texture>) {
+    let result = if save {app_state.emu_quick_save(slot)} else {app_state.emu_quick_load(slot)};
+
+    match result {
+        Ok(_) => {
+            let message = if save {format!("Saved to slot {}.", slot)} else {format!("Loaded slot {}.", slot)};
+            let notification = Notification::init(ImString::new("Quick slots"), ImString::new(&message), ui.time());
+
+            // Shown here with its thumbnail instead, so the log doesn't need to notify.
+            log::push(Level::Info, "Quick slots", &message, false);
+
+            // The screen buffer is part of the state, so this is the frame in the slot either way.
+            let thumbnail = app_state.gb.as_ref().and_then(|gb| {
+                let screen_data = gb.read().ok()?.ui_get_screen_data();
+                let data: Vec<u8> = screen_data.read().ok()?.iter().flat_map(|b| std::iter::repeat(*b).take(3)).collect();
+                let mut texture = GameboyTexture::new(160, 144);
+
+                texture.update_texture(data, display, textures);
+                *texture.id()
+            });
+
+            if let Some(thumbnail) = thumbnail {
+                app_state.notifications.push(notification.with_thumbnail(thumbnail));
+            }
+            else {
+                app_state.notifications.push(notification);
+            }
+        }
+        Err(error) => {
+            let action = if save {"save to"} else {"load"};
+            log::error("Quick slots", &format!("Couldn't {} slot {} ({}).", action, slot, error));
+        }
+    }
+}
+
+fn dump_state(app_state: &AppState, ui: &Ui) {
+    let dump = app_state.gb.as_ref().and_then(|gb| gb.read().ok().map(|lock| lock.dbg_dump_state(8)));
+
+    if let Some(dump) = dump {
+        log::info("CPU state", &dump);
+
+        if app_state.config.copy_state_dump {
+            ui.set_clipboard_text(&dump);
+        }
+    }
+}
+
+fn show_notifications(app_state: &mut AppState, ui: &Ui, textures: &mut Textures<Texture>) {
     let mut finished_notifications = 0;
+    let mut offset = 0.0;
+
+    for entry in log::take_unread() {
+        if entry.notify {
+            app_state.notifications.push(Notification::from_entry(&entry, ui.time()));
+        }
+    }
 
     for (i, n) in app_state.notifications.iter_mut().enumerate() {
-        if n.draw(ui, i) {
+        if n.draw(ui, i, offset) {
             finished_notifications += 1;
         }
+
+        offset += n.height() + 5.0;
     }
 
-    for _ in 0..finished_notifications {
-        app_state.notifications.remove(0);
+    for n in app_state.notifications.drain(0..finished_notifications) {
+        if let Some(thumbnail) = n.thumbnail() {
+            textures.remove(thumbnail);
+        }
     }
 }
 
@@ -369,7 +1280,7 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
         ui.menu("File", || {
             if ui.menu_item("Load ROM") {
                 app_state.picking_rom = true;
-                app_state.file_picker_instance = FilePickerWindow::init(app_state.config.last_dir_rom.clone());
+                app_state.file_picker_instance = FilePickerWindow::init(app_state.config.last_dir_rom.clone()).with_extensions(&ROM_EXTENSIONS);
             }
 
             if ui.menu_item("Load Bootrom") {
@@ -380,15 +1291,12 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
             ui.separator();
 
             if ui.menu_item_config("Reload").enabled(app_state.gb.is_some()).build() {
-                if let Some(tx) = app_state.gb_exit_tx.as_ref() {
-                    tx.send(()).unwrap();
-                }
-
+                app_state.emu_stop();
                 app_state.reload = true;
+            }
 
-                app_state.gb = None;
-                app_state.gb_mem = None;
-                app_state.gb_exit_tx = None;
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Rebuilds the emulator from the loaded ROM and bootrom.");
             }
 
             ui.separator();
@@ -397,7 +1305,12 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                 app_state.settings_opened = true;
             }
 
+            if ui.menu_item_config("Per-game settings").enabled(app_state.gb.is_some()).build() {
+                app_state.game_settings_opened = true;
+            }
+
             if ui.menu_item("Exit") {
+                app_state.shutdown();
                 *control_flow = ControlFlow::Exit;
             }
         });
@@ -411,7 +1324,7 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                         app_state.emu_set_mode(EmulatorMode::Paused);
                     }
                 }
-                EmulatorMode::UnknownInstruction(_, _) => {
+                EmulatorMode::Locked(_) => {
                     ui.menu_item_config("Resume").enabled(false).build();
                 }
                 _ => {
@@ -421,12 +1334,114 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                 }
             }
 
+            if ui.menu_item_config("Step frame").shortcut(format!("{:?}", app_state.config.keybinds().emu_frame_step)).build() {
+                app_state.emu_step_frame();
+            }
+
+            ui.separator();
+
+            if let Some(frames) = app_state.emu_recorded_frames() {
+                if ui.menu_item(format!("Stop recording ({} frames)", frames)) {
+                    app_state.emu_stop_recording();
+                }
+            }
+            else if ui.menu_item("Record GIF") {
+                app_state.emu_start_recording();
+            }
+
+            if ui.is_item_hovered() {
+                let seconds = app_state.config.recording_max_seconds;
+                ui.tooltip_text(format!("Keeps the last {} seconds, saved to the recordings directory when stopped.", seconds));
+            }
+
+            if let Some(lines) = app_state.emu_traced_lines() {
+                if ui.menu_item(format!("Stop tracing ({} lines)", lines)) {
+                    app_state.emu_stop_trace();
+                }
+            }
+            else if ui.menu_item("Trace to file") {
+                app_state.emu_start_trace();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Writes the CPU state before every instruction to the traces directory, in the Gameboy Doctor format.\nThat's around 60MB for every second of emulation, so keep it short.");
+            }
+
+            ui.separator();
+
+            if ui.menu_item_config("Remember breakpoints").selected(app_state.config.persist_breakpoints).build() {
+                app_state.config.persist_breakpoints = !app_state.config.persist_breakpoints;
+                app_state.config.save();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Saves this game's breakpoints, and loads them back the next time it's opened.");
+            }
+
+            if ui.menu_item_config("Clear breakpoints on reset").selected(app_state.config.clear_breakpoints_on_reset).build() {
+                app_state.config.clear_breakpoints_on_reset = !app_state.config.clear_breakpoints_on_reset;
+                app_state.config.save();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Otherwise they're kept, only reloading the game starts from the saved ones.");
+            }
+
+            ui.separator();
+
             if ui.menu_item("Restart") {
+                app_state.emu_restart();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Starts the game over, keeping breakpoints and windows.");
+            }
+
+            if ui.menu_item("Reset") {
                 app_state.emu_reset();
             }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Resets the hardware and pauses before the first instruction.");
+            }
         });
 
-        ui.menu_with_enabled("View", app_state.gb.is_some(), || {
+        ui.menu("View", || {
+            if ui.menu_item("Reset window layout") {
+                app_state.reset_layout = true;
+
+                if app_state.gb.is_some() {
+                    open_default_windows(app_state);
+                }
+            }
+
+            ui.separator();
+
+            let test_pattern_label = if app_state.window_test_pattern.0 {"Hide test pattern"} else {"Show test pattern"};
+
+            if ui.menu_item_config(test_pattern_label).enabled(app_state.gb.is_none()).build() {
+                app_state.window_test_pattern.0 = !app_state.window_test_pattern.0;
+            }
+
+            let log_label = if app_state.window_log.0 {"Hide log"} else {"Show log"};
+
+            if ui.menu_item(log_label) {
+                app_state.window_log.0 = !app_state.window_log.0;
+            }
+
+            let state_diff_label = if app_state.window_state_diff.0 {"Hide state diff"} else {"Show state diff"};
+
+            if ui.menu_item(state_diff_label) {
+                app_state.window_state_diff.0 = !app_state.window_state_diff.0;
+            }
+
+            // The rest of the windows need a ROM to show anything.
+            if app_state.gb.is_none() {
+                return;
+            }
+
+            ui.separator();
+
             if app_state.window_cart_info.0 {
                 if ui.menu_item("Hide cartridge info") {
                     app_state.window_cart_info.0 = false;
@@ -436,6 +1451,15 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                 app_state.window_cart_info.0 = true;
             }
 
+            if app_state.window_cart_ram.0 {
+                if ui.menu_item("Hide cartridge RAM") {
+                    app_state.window_cart_ram.0 = false;
+                }
+            }
+            else if ui.menu_item("Show cartridge RAM") {
+                app_state.window_cart_ram.0 = true;
+            }
+
             if app_state.window_cpu_debugger.0 {
                 if ui.menu_item("Hide CPU debugger") {
                     app_state.window_cpu_debugger.0 = false;
@@ -445,6 +1469,15 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                 app_state.window_cpu_debugger.0 = true;
             }
 
+            if app_state.window_debug_console.0 {
+                if ui.menu_item("Hide debug console") {
+                    app_state.window_debug_console.0 = false;
+                }
+            }
+            else if ui.menu_item("Show debug console") {
+                app_state.window_debug_console.0 = true;
+            }
+
             if app_state.window_disassembler.0 {
                 if ui.menu_item("Hide disassembler") {
                     app_state.window_disassembler.0 = false;
@@ -454,6 +1487,15 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
                 app_state.window_disassembler.0 = true;
             }
 
+            if app_state.window_freeze.0 {
+                if ui.menu_item("Hide freeze list") {
+                    app_state.window_freeze.0 = false;
+                }
+            }
+            else if ui.menu_item("Show freeze list") {
+                app_state.window_freeze.0 = true;
+            }
+
             if app_state.window_memory_viewer.0 {
                 if ui.menu_item("Hide memory viewer") {
                     app_state.window_memory_viewer.0 = false;
@@ -480,31 +1522,84 @@ fn draw_menu_bar(app_state: &mut AppState, ui: &Ui, control_flow: &mut ControlFl
             else if ui.menu_item("Show VRAM viewer") {
                 app_state.window_vram_viewer.0 = true;
             }
+
+            if app_state.window_watch.0 {
+                if ui.menu_item("Hide watch") {
+                    app_state.window_watch.0 = false;
+                }
+            }
+            else if ui.menu_item("Show watch") {
+                app_state.window_watch.0 = true;
+            }
         });
     });
 }
 
+// Opens the window asked for by another one, and shows the address on it.
+fn navigate(app_state: &mut AppState, navigation: Option<Navigation>) {
+    match navigation {
+        Some(Navigation::Disassembler(address)) => {
+            if let Some(disas_win) = app_state.window_disassembler.1.as_mut() {
+                app_state.window_disassembler.0 = true;
+                disas_win.navigate_to(address);
+            }
+        }
+        Some(Navigation::Memory(address)) => {
+            if let Some(memory_win) = app_state.window_memory_viewer.1.as_mut() {
+                app_state.window_memory_viewer.0 = true;
+                memory_win.navigate_to(address);
+            }
+        }
+        Some(Navigation::Tile(address)) => {
+            if let Some(vram_win) = app_state.window_vram_viewer.1.as_mut() {
+                if vram_win.select_tile(address) {
+                    app_state.window_vram_viewer.0 = true;
+                }
+            }
+        }
+        None => {}
+    }
+}
+
 fn draw_windows(app_state: &mut AppState, ui: &Ui, display: &Display, textures: &mut Textures<Texture>) {
     let mut adjust = false;
 
-    if let Some(cart_win) = app_state.window_cart_info.1.as_ref() {
-        cart_win.draw(ui, &mut app_state.window_cart_info.0);
+    if let Some(cart_win) = app_state.window_cart_info.1.as_mut() {
+        cart_win.draw(ui, &mut app_state.window_cart_info.0, display, textures);
     }
 
     if let Some(cpu_win) = app_state.window_cpu_debugger.1.as_mut() {
-        adjust = cpu_win.draw(ui, &mut app_state.window_cpu_debugger.0);
+        adjust = cpu_win.draw(ui, &mut app_state.window_cpu_debugger.0, app_state.config.clear_breakpoints_on_reset);
+
+        let navigation = cpu_win.take_navigation();
+        navigate(app_state, navigation);
     }
 
     if let Some(disas_win) = app_state.window_disassembler.1.as_mut() {
-        disas_win.draw(ui, adjust, &mut app_state.window_disassembler.0);
+        disas_win.draw(ui, adjust, &mut app_state.window_disassembler.0, &mut app_state.config);
+
+        let navigation = disas_win.take_navigation();
+        navigate(app_state, navigation);
     }
 
     if let Some(mem_win) = app_state.window_memory_viewer.1.as_mut() {
         mem_win.draw(ui, &mut app_state.window_memory_viewer.0);
+
+        let navigation = mem_win.take_navigation();
+        navigate(app_state, navigation);
+    }
+
+    if let Some(cart_ram_win) = app_state.window_cart_ram.1.as_mut() {
+        cart_ram_win.draw(ui, &mut app_state.window_cart_ram.0);
+    }
+
+    if app_state.waiting_for_start && app_state.emu_get_mode() != EmulatorMode::Paused {
+        app_state.waiting_for_start = false;
     }
 
     if let Some(screen_win) = app_state.window_screen.1.as_mut() {
-        let focused = screen_win.draw(&mut app_state.config, ui, &mut app_state.window_screen.0, display, textures);
+        let prompt = if app_state.waiting_for_start {Some("Paused. Press Resume or F9 to start.")} else {None};
+        let focused = screen_win.draw(&mut app_state.config, ui, &mut app_state.window_screen.0, prompt, app_state.fast_forwarding, display, textures);
         
         if !focused && app_state.config.pause_emulator_on_focus_loss {
             app_state.emu_set_mode(EmulatorMode::Paused);
@@ -512,7 +1607,19 @@ fn draw_windows(app_state: &mut AppState, ui: &Ui, display: &Display, textures:
     }
 
     if let Some(serial_win) = app_state.window_serial.1.as_mut() {
-        serial_win.draw(ui, &mut app_state.window_serial.0);
+        serial_win.draw(ui, &mut app_state.window_serial.0, &mut app_state.config);
+    }
+
+    if let Some(debug_win) = app_state.window_debug_console.1.as_mut() {
+        debug_win.draw(ui, &mut app_state.window_debug_console.0, &mut app_state.config);
+    }
+
+    if let Some(freeze_win) = app_state.window_freeze.1.as_mut() {
+        freeze_win.draw(ui, &mut app_state.window_freeze.0, &mut app_state.config.overrides, app_state.rom_hash);
+    }
+
+    if let Some(watch_win) = app_state.window_watch.1.as_mut() {
+        watch_win.draw(ui, &mut app_state.window_watch.0);
     }
 
     if let Some(vram_win) = app_state.window_vram_viewer.1.as_mut() {
@@ -520,6 +1627,47 @@ fn draw_windows(app_state: &mut AppState, ui: &Ui, display: &Display, textures:
     }
 }
 
+fn draw_test_pattern(app_state: &mut AppState, ui: &Ui, display: &Display, textures: &mut Textures<Texture>) {
+    let screen_win = app_state.window_test_pattern.1.get_or_insert_with(screen::ScreenWindow::init_test_pattern);
+    screen_win.draw(&mut app_state.config, ui, &mut app_state.window_test_pattern.0, None, false, display, textures);
+}
+
+fn draw_state_diff(app_state: &mut AppState, ui: &Ui) {
+    let start_dir = app_state.config.save_state_dir.clone();
+    let diff_win = app_state.window_state_diff.1.get_or_insert_with(|| state_diff::StateDiffWindow::init(start_dir));
+    diff_win.draw(ui, &mut app_state.window_state_diff.0);
+}
+
+fn draw_crash_dialog(app_state: &mut AppState, ui: &Ui) {
+    ui.open_popup("Emulator error");
+
+    if let Some(_token) = PopupModal::new("Emulator error").always_auto_resize(true).begin_popup(ui) {
+        if let Some(message) = app_state.crash_message.as_ref() {
+            ui.text("The emulator stopped after an internal error:");
+            ui.separator();
+            ui.text_wrapped(message);
+            ui.separator();
+        }
+
+        if ui.button("Reset emulator") {
+            app_state.emu_stop();
+            app_state.crash_message = None;
+            app_state.reload = true;
+
+            ui.close_current_popup();
+        }
+
+        ui.same_line();
+
+        if ui.button("Close ROM") {
+            app_state.emu_stop();
+            app_state.crash_message = None;
+
+            ui.close_current_popup();
+        }
+    }
+}
+
 fn draw_rom_picker(app_state: &mut AppState, ui: &Ui) {
     if let Some(path) = app_state.file_picker_instance.draw(ui) {
         if path.exists() {
@@ -535,34 +1683,40 @@ fn draw_rom_picker(app_state: &mut AppState, ui: &Ui) {
                     }
                 };
 
-                app_state.rom_data = data;
-                app_state.reload = true;
                 app_state.picking_rom = false;
                 app_state.config.last_dir_rom = path.parent().unwrap().into();
         
                 app_state.config.save();
 
-                app_state.notifications.push(
-                    Notification::init(
-                        ImString::new("Loader"),
-                        ImString::new(format!("Loaded ROM file {}.", filename)),
-                        ui.time()
-                    )
-                );
+                match cart::check_rom(&data) {
+                    Ok(warnings) => {
+                        for warning in warnings {
+                            log::warn("Loader", &format!("{} doesn't look like a Game Boy ROM: {}.", filename, warning));
+                        }
+
+                        app_state.emu_save_resume_state();
+
+                        app_state.rom_data = data;
+                        app_state.reload = true;
+                        app_state.resume_pending = true;
+
+                        log::info("Loader", &format!("Loaded ROM file {}.", filename));
+                    }
+                    Err(error) => {
+                        log::error("Loader", &format!("{} can't be loaded as a Game Boy ROM: {}.", filename, error));
+                    }
+                }
             }
             else if let Err(error) = rom_result {
                 app_state.reload = false;
 
-                app_state.notifications.push(
-                    Notification::init(
-                        ImString::new("Loader"),
-                        ImString::new(format!("Failed to load ROM file ({}).", error.to_string())),
-                        ui.time()
-                    )
-                );
+                log::error("Loader", &format!("Failed to load ROM file ({}).", error.to_string()));
             }
         }
     }
+    else if app_state.file_picker_instance.cancelled() {
+        app_state.picking_rom = false;
+    }
 }
 
 fn draw_bootrom_picker(app_state: &mut AppState, ui: &Ui) {
@@ -587,25 +1741,16 @@ fn draw_bootrom_picker(app_state: &mut AppState, ui: &Ui) {
         
                 app_state.config.save();
 
-                app_state.notifications.push(
-                    Notification::init(
-                        ImString::new("Loader"),
-                        ImString::new(format!("Loaded bootrom file {}.", filename)),
-                        ui.time()
-                    )
-                );
+                log::info("Loader", &format!("Loaded bootrom file {}.", filename));
             }
             else if let Err(error) = bootrom_result {
                 app_state.reload = false;
 
-                app_state.notifications.push(
-                    Notification::init(
-                        ImString::new("Loader"),
-                        ImString::new(format!("Failed to load bootrom file ({}).", error.to_string())),
-                        ui.time()
-                    )
-                );
+                log::error("Loader", &format!("Failed to load bootrom file ({}).", error.to_string()));
             }
         }
     }
+    else if app_state.file_picker_instance.cancelled() {
+        app_state.picking_bootrom = false;
+    }
 }
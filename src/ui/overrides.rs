@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use ron::de::from_reader;
+use ron::ser::{PrettyConfig, to_string_pretty};
+
+use crate::log;
+use crate::ui::Keybinds;
+use crate::gameboy::{Breakpoint, Model};
+use crate::gameboy::ppu::compat_palettes::CompatPaletteChoice;
+
+const OVERRIDES_DIR: &str = "overrides";
+
+// Settings for a single game, layered on top of the global AppConfig.
+// Stored as overrides/<ROM CRC32>.ron, anything left as None uses the global value.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GameOverrides {
+    pub skip_bootrom: Option<bool>,
+    pub fast_boot: Option<bool>,
+    pub screen_gamma: Option<f32>,
+    pub screen_contrast: Option<f32>,
+    pub emulation_batch_size: Option<u32>,
+    pub pause_emulator_on_startup: Option<bool>,
+    pub model: Option<Model>,
+    pub compat_palette: Option<CompatPaletteChoice>,
+    // No editor for these yet, they can only be set in the file.
+    pub keybinds: Option<Keybinds>,
+
+    // Managed by the freeze window, not actually a setting.
+    pub freezes: Vec<FrozenAddress>,
+    // Copied from the debugger when they change, if AppConfig::persist_breakpoints is set.
+    pub breakpoints: Vec<Breakpoint>
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FrozenAddress {
+    pub address: u16,
+    pub value: u8,
+    pub enabled: bool
+}
+
+impl GameOverrides {
+    fn path(rom_hash: u32) -> PathBuf {
+        PathBuf::from(OVERRIDES_DIR).join(format!("{:08X}.ron", rom_hash))
+    }
+
+    pub fn load(rom_hash: u32) -> GameOverrides {
+        if let Ok(file) = std::fs::File::open(GameOverrides::path(rom_hash)) {
+            match from_reader(file) {
+                Ok(overrides) => return overrides,
+                Err(error) => log::error("Per-game settings", &format!("Couldn't load the per-game settings ({}).", error))
+            }
+        }
+
+        GameOverrides::default()
+    }
+
+    /// The list the emulator works with, as (address, value).
+    pub fn enabled_freezes(&self) -> Vec<(u16, u8)> {
+        self.freezes.iter().filter(|f| f.enabled).map(|f| (f.address, f.value)).collect()
+    }
+
+    pub fn save(&self, rom_hash: u32) {
+        if let Err(error) = std::fs::create_dir_all(OVERRIDES_DIR) {
+            log::error("Per-game settings", &format!("Couldn't create the overrides directory ({}).", error));
+            return;
+        }
+
+        if let Ok(data) = to_string_pretty(self, PrettyConfig::default()) {
+            if let Err(error) = std::fs::write(GameOverrides::path(rom_hash), data) {
+                log::error("Per-game settings", &format!("Couldn't save the per-game settings ({}).", error));
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::gameboy::DMG_CLOCK_HZ;
+use crate::gameboy::ppu::CYCLES_PER_FRAME;
+
+// The DMG runs at about 59.73 frames per second.
+const FRAMES_PER_SECOND: f64 = DMG_CLOCK_HZ as f64 / CYCLES_PER_FRAME as f64;
+
+// The screen buffer holds one shade per pixel, so a grayscale
+// palette lets the frames be written without any conversion.
+fn grayscale_palette() -> Vec<u8> {
+    (0..=255).flat_map(|shade| vec![shade; 3]).collect()
+}
+
+/// Writes the recorded 160x144 frames as a looping GIF.
+pub fn save_gif(path: &Path, frames: &[Vec<u8>]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = Encoder::new(BufWriter::new(file), 160, 144, &grayscale_palette()).map_err(|e| e.to_string())?;
+
+    encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+
+    for (idx, data) in frames.iter().enumerate() {
+        // Delays are in hundredths of a second, so they alternate between
+        // values to keep the overall speed right.
+        let start = (idx as f64 * 100.0 / FRAMES_PER_SECOND).round() as u16;
+        let end = ((idx + 1) as f64 * 100.0 / FRAMES_PER_SECOND).round() as u16;
+
+        let frame = Frame {
+            width: 160,
+            height: 144,
+            delay: end - start,
+            buffer: Cow::Borrowed(data),
+            ..Frame::default()
+        };
+
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
@@ -1,36 +1,154 @@
 use std::sync::{Arc, RwLock};
 
 use imgui::*;
+use imgui_glium_renderer::Texture;
+
+use glium::Display;
 
 use crate::gameboy::Gameboy;
+use crate::gameboy::memory::GameboyMemory;
 use crate::gameboy::memory::cart::CartHeader;
+use crate::gameboy::memory::cart::hashes::RomHashes;
+use crate::gameboy::ppu::utils::GameboyTexture;
+
+// The logo is 48x8 pixels, stored as 4x4 blocks with two rows per byte (one per nibble).
+// The first 24 bytes are the top half, the other 24 the bottom one.
+fn decode_logo(logo: &[u8; 48]) -> Vec<u8> {
+    let mut data = vec![255; 48 * 8 * 3];
+
+    for (idx, byte) in logo.iter().enumerate() {
+        let block_x = (idx % 24) / 2 * 4;
+        let block_y = (idx / 24) * 4 + (idx % 2) * 2;
+
+        for (row, nibble) in [byte >> 4, byte & 0x0F].iter().enumerate() {
+            for bit in 0..4 {
+                if nibble & (0x08 >> bit) != 0 {
+                    let offset = ((block_y + row) * 48 + block_x + bit) * 3;
+                    data[offset..offset + 3].copy_from_slice(&[0, 0, 0]);
+                }
+            }
+        }
+    }
+
+    data
+}
 
 pub struct CartWindow {
-    header: Arc<CartHeader>
+    header: Arc<CartHeader>,
+    gb_mem: Arc<RwLock<GameboyMemory>>,
+    rom_hashes: Arc<RwLock<Option<RomHashes>>>,
+
+    logo: GameboyTexture
 }
 
 impl CartWindow {
     pub fn init(gb: Arc<RwLock<Gameboy>>) -> CartWindow {
         let header = gb.read().unwrap().ui_get_header();
+        let gb_mem = gb.read().unwrap().ui_get_memory();
+        let rom_hashes = gb.read().unwrap().ui_get_rom_hashes();
         
         CartWindow {
-            header
+            header,
+            gb_mem,
+            rom_hashes,
+
+            logo: GameboyTexture::new(48, 8)
         }
     }
 
-    pub fn draw(&self, ui: &Ui, opened: &mut bool) {
+    fn draw_hashes(&self, ui: &Ui) {
+        let hashes = self.rom_hashes.read().ok().and_then(|lock| lock.clone());
+
+        ui.popup("ROM Hashes", || {
+            if let Some(hashes) = hashes {
+                let lines = [
+                    format!("Title: {}", self.header.title()),
+                    format!("Controller: {}", self.header.cart_type()),
+                    format!("Size: {} bytes", hashes.size()),
+                    format!("CRC32: {}", hashes.crc32()),
+                    format!("MD5: {}", hashes.md5()),
+                    format!("SHA-1: {}", hashes.sha1())
+                ];
+
+                for line in lines.iter() {
+                    ui.text(line);
+                }
+
+                ui.separator();
+
+                if ui.button("Copy") {
+                    ui.set_clipboard_text(lines.join("\n"));
+                }
+            }
+            else {
+                ui.text("Hashing the ROM...");
+            }
+        });
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool, display: &Display, textures: &mut Textures<Texture>) {
         if !*opened {
             return;
         }
 
-        ui.window("Cartridge Info").size([290.0, 105.0], Condition::Always).opened(opened).resizable(false).build(|| {
+        // The header doesn't change, so it only has to be uploaded once.
+        if self.logo.id().is_none() {
+            self.logo.update_texture(decode_logo(self.header.logo()), display, textures);
+        }
+
+        ui.window("Cartridge Info").size([290.0, 175.0], Condition::Always).opened(opened).resizable(false).build(|| {
             ui.text(format!("Cartridge Title: {}", self.header.title()));
             ui.text(format!("Cartridge Controller: {}", self.header.cart_type()));
+            ui.same_line();
+
+            if ui.small_button("Hashes") {
+                ui.open_popup("ROM Hashes");
+            }
+
+            self.draw_hashes(ui);
             
             ui.separator();
 
             ui.text(format!("ROM Size: {} ({} banks)", self.header.rom_size(), self.header.rom_banks_count()));
             ui.text(format!("RAM Size: {} ({} banks)", self.header.ram_size(), self.header.ram_banks_count()));
+
+            ui.separator();
+
+            let sgb_status = {
+                if let Ok(lock) = self.gb_mem.read() {
+                    let sgb = lock.sgb();
+
+                    if sgb.detected() {
+                        format!("{} packets, last {}", sgb.packets_received(), sgb.last_command().unwrap_or("-"))
+                    }
+                    else {
+                        String::from("no packets")
+                    }
+                }
+                else {
+                    String::from("unknown")
+                }
+            };
+
+            ui.text(format!("SGB: {} ({})", if self.header.sgb_support() {"Supported"} else {"Not supported"}, sgb_status));
+
+            ui.separator();
+
+            if let Some(id) = self.logo.id().as_ref() {
+                Image::new(*id, [48.0 * 2.0, 8.0 * 2.0]).build(ui);
+                ui.same_line();
+            }
+
+            if self.header.has_valid_logo() {
+                ui.text_colored([0.4, 1.0, 0.4, 1.0], "Logo OK");
+            }
+            else {
+                ui.text_colored([1.0, 0.0, 0.0, 1.0], "Logo mismatch");
+
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("The bootrom would refuse to start this cartridge.");
+                }
+            }
         });
     }
 }
@@ -0,0 +1,146 @@
+use std::sync::{Arc, RwLock};
+
+use imgui::*;
+
+use crate::gameboy::memory::GameboyMemory;
+
+// Shows every cart RAM bank straight from the mapper, so save data can be
+// checked and edited without having to switch banks from the game.
+pub struct CartRamWindow {
+    gb_mem: Arc<RwLock<GameboyMemory>>,
+
+    selected_bank: usize,
+
+    editing_byte: bool,
+    target_byte_offset: usize,
+    target_byte_new_value: String
+}
+
+impl CartRamWindow {
+    pub fn init(gb_mem: Arc<RwLock<GameboyMemory>>) -> CartRamWindow {
+        CartRamWindow {
+            gb_mem,
+
+            selected_bank: 0,
+
+            editing_byte: false,
+            target_byte_offset: 0,
+            target_byte_new_value: String::new()
+        }
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("Cartridge RAM").size([350.0, 250.0], Condition::FirstUseEver).opened(opened).build(|| {
+            let (banks_count, mapped_bank) = {
+                if let Ok(lock) = self.gb_mem.read() {
+                    (lock.cartridge().ram_banks().len(), lock.cartridge().get_selected_ram_bank())
+                }
+                else {
+                    (0, 0)
+                }
+            };
+
+            if banks_count == 0 {
+                ui.text_disabled("This cartridge doesn't have any RAM.");
+                return;
+            }
+
+            self.selected_bank = self.selected_bank.min(banks_count - 1);
+
+            ui.set_next_item_width(100.0);
+
+            if let Some(_token) = ui.begin_combo("Bank", format!("{:02X}", self.selected_bank)) {
+                for bank in 0..banks_count {
+                    let label = if bank == mapped_bank {format!("{:02X} (mapped)", bank)} else {format!("{:02X}", bank)};
+
+                    if ui.selectable_config(&label).selected(bank == self.selected_bank).build() {
+                        self.selected_bank = bank;
+                        self.editing_byte = false;
+                    }
+                }
+            }
+
+            ui.same_line();
+            ui.text_disabled(format!("Mapped: {:02X}", mapped_bank));
+            ui.separator();
+
+            let data = {
+                if let Ok(lock) = self.gb_mem.read() {
+                    lock.cartridge().ram_banks().get(self.selected_bank).cloned().unwrap_or_default()
+                }
+                else {
+                    Vec::new()
+                }
+            };
+
+            ui.child_window("cart_ram_data").build(|| {
+                let style_padding = ui.push_style_var(StyleVar::FramePadding([0.0, 0.0]));
+                let style_spacing = ui.push_style_var(StyleVar::ItemSpacing([5.0, 1.0]));
+
+                let size = ui.calc_text_size("FF");
+                let mut clipper = ListClipper::new((data.len() / 8) as i32).begin(ui);
+
+                while clipper.step() {
+                    for line in clipper.display_start()..clipper.display_end() {
+                        let start = line as usize * 8;
+
+                        // Shown with the address the bank has when it's mapped in.
+                        ui.text(format!("{:04X}", 0xA000 + start));
+                        ui.same_line();
+                        ui.text("|");
+                        ui.same_line();
+
+                        for (idx, value) in data[start..start + 8].iter().enumerate() {
+                            let token = ui.push_id(&format!("value{}", idx));
+                            let offset = start + idx;
+
+                            if self.editing_byte && self.target_byte_offset == offset {
+                                let mut flags = InputTextFlags::empty();
+
+                                flags.set(InputTextFlags::CHARS_HEXADECIMAL, true);
+                                flags.set(InputTextFlags::ENTER_RETURNS_TRUE, true);
+                                flags.set(InputTextFlags::AUTO_SELECT_ALL, true);
+                                flags.set(InputTextFlags::NO_HORIZONTAL_SCROLL, true);
+                                flags.set(InputTextFlags::ALWAYS_OVERWRITE, true);
+
+                                ui.set_next_item_width(size[0]);
+
+                                if ui.input_text("##data", &mut self.target_byte_new_value).flags(flags).build() {
+                                    if let Ok(value) = u8::from_str_radix(&self.target_byte_new_value, 16) {
+                                        if let Ok(mut lock) = self.gb_mem.write() {
+                                            lock.cartridge_mut().dbg_write_ram(self.selected_bank, offset, value);
+                                        }
+                                    }
+
+                                    self.editing_byte = false;
+                                    self.target_byte_new_value = String::new();
+                                }
+                            }
+                            else if ui.selectable_config(&format!("{:02X}", value)).allow_double_click(true).size(size).build() {
+                                self.editing_byte = true;
+                                self.target_byte_offset = offset;
+                                self.target_byte_new_value = format!("{:02X}", value);
+                            }
+
+                            token.pop();
+                            ui.same_line();
+                        }
+
+                        ui.text(" | ");
+                        ui.same_line();
+
+                        let ascii: String = data[start..start + 8].iter().map(|b| if b.is_ascii_graphic() {*b as char} else {'.'}).collect();
+                        ui.text(ascii);
+                    }
+                }
+
+                style_padding.pop();
+                style_spacing.pop();
+            });
+        });
+    }
+}
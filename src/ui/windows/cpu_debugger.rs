@@ -3,47 +3,135 @@ use std::sync::{Arc, RwLock};
 use imgui::*;
 
 use crate::gameboy::{Breakpoint, EmulatorMode, Gameboy};
+use crate::gameboy::callstack::Callstack;
+use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::memory::dma::DmaLog;
+use crate::gameboy::memory::stats::REGION_NAMES;
+
+use super::Navigation;
 
 pub struct CPUWindow {
     gb: Arc<RwLock<Gameboy>>,
-    callstack: Arc<RwLock<Vec<String>>>,
+    gb_mem: Arc<RwLock<GameboyMemory>>,
+    callstack: Arc<RwLock<Callstack>>,
+    dma_log: Arc<RwLock<DmaLog>>,
 
     registers: [u16; 6],
     dbg_mode: EmulatorMode,
     callstack_items: Vec<ImString>,
+    callstack_mismatch: Option<String>,
     breakpoints_list: Vec<Breakpoint>,
 
+    bank_history_enabled: bool,
+    bank_history_items: Vec<ImString>,
+
+    dma_log_enabled: bool,
+    dma_log_items: Vec<ImString>,
+
+    io_log_enabled: bool,
+    io_log_filter: String,
+    io_log_items: Vec<ImString>,
+
+    access_stats_enabled: bool,
+    access_stats_items: Vec<ImString>,
+
+    step_count: i32,
+    cycle_count: i32,
+
+    ly_bp_enabled: bool,
+    ly_bp_value: i32,
+
     bp_add_addr: String,
     bp_edit_addr: String,
     bp_edit_show_popup: bool,
 
     bp_add: (usize, Breakpoint),
-    bp_edit: (usize, Breakpoint)
+    bp_edit: (usize, Breakpoint),
+
+    navigation: Option<Navigation>
 }
 
 impl CPUWindow {
     pub fn init(gb: Arc<RwLock<Gameboy>>) -> CPUWindow {
         let callstack = gb.read().unwrap().ui_get_callstack();
+        let gb_mem = gb.read().unwrap().ui_get_memory();
+        let dma_log = gb.read().unwrap().ui_get_dma_log();
 
         CPUWindow {
             gb,
+            gb_mem,
             callstack,
+            dma_log,
 
             registers: [0, 0, 0, 0, 0, 0],
             dbg_mode: EmulatorMode::Paused,
             callstack_items: Vec::new(),
+            callstack_mismatch: None,
             breakpoints_list: Vec::new(),
 
+            bank_history_enabled: false,
+            bank_history_items: Vec::new(),
+
+            dma_log_enabled: false,
+            dma_log_items: Vec::new(),
+
+            io_log_enabled: false,
+            io_log_filter: String::new(),
+            io_log_items: Vec::new(),
+
+            access_stats_enabled: false,
+            access_stats_items: Vec::new(),
+
+            step_count: 10,
+            // One frame.
+            cycle_count: 70224,
+
+            ly_bp_enabled: false,
+            ly_bp_value: 0,
+
             bp_add_addr: String::new(),
             bp_edit_addr: String::new(),
             bp_edit_show_popup: false,
 
             bp_add: (0, Breakpoint::new(false, false, false, 0xFFFF)),
-            bp_edit: (0, Breakpoint::new(false, false, false, 0xFFFF))
+            bp_edit: (0, Breakpoint::new(false, false, false, 0xFFFF)),
+
+            navigation: None
+        }
+    }
+
+    /// Returns the address the user asked to see since the last call, if any.
+    pub fn take_navigation(&mut self) -> Option<Navigation> {
+        self.navigation.take()
+    }
+
+    // Draws a register as a link, PC opens the disassembler and the rest the memory viewer.
+    fn register_link(&mut self, ui: &Ui, name: &str, value: u16) {
+        let label = format!("{}: {:04X}", name, value);
+
+        if ui.selectable_config(&label).size(ui.calc_text_size(&label)).build() {
+            self.navigation = Some(if name == "PC" {Navigation::Disassembler(value)} else {Navigation::Memory(value)});
+        }
+
+        if ui.is_item_hovered() {
+            let target = if name == "PC" {"disassembler"} else {"memory viewer"};
+            ui.tooltip_text(format!("Show ${:04X} in the {}", value, target));
+        }
+    }
+
+    // The IO write filter is either a register or an inclusive range, like FF10-FF26.
+    // Anything that doesn't parse shows every write.
+    fn io_log_range(&self) -> Option<(u16, u16)> {
+        let mut parts = self.io_log_filter.split('-').map(|part| u16::from_str_radix(part.trim(), 16));
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(start)), None, None) => Some((start, start)),
+            (Some(Ok(start)), Some(Ok(end)), None) => Some((start, end)),
+            _ => None
         }
     }
 
-    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) -> bool {
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool, clear_breakpoints_on_reset: bool) -> bool {
         if !*opened {
             return false;
         }
@@ -73,13 +161,87 @@ impl CPUWindow {
                 }
 
                 if let Ok(lock) = self.callstack.read() {
-                    let mut callstack_items = Vec::with_capacity(lock.len());
+                    let mut callstack_items = Vec::with_capacity(lock.frames().len());
 
-                    for call in lock.iter().rev() {
-                        callstack_items.push(ImString::from(call.clone()));
+                    for call in lock.frames().iter().rev() {
+                        callstack_items.push(ImString::from(call.description()));
+                    }
+
+                    if lock.dropped() > 0 {
+                        callstack_items.push(ImString::from(format!("... {} more", lock.dropped())));
                     }
 
                     self.callstack_items = callstack_items;
+                    self.callstack_mismatch = lock.mismatch().map(String::from);
+                }
+
+                if let Ok(lock) = self.gb_mem.read() {
+                    let history = lock.cartridge().bank_history();
+                    let mut bank_history_items = Vec::with_capacity(history.entries().len());
+
+                    for switch in history.entries().iter().rev() {
+                        bank_history_items.push(ImString::from(format!("{:04X}: {} {} -> {} (${:04X})",
+                            switch.pc(),
+                            switch.kind(),
+                            switch.old_bank(),
+                            switch.new_bank(),
+                            switch.register()
+                        )));
+                    }
+
+                    self.bank_history_enabled = history.enabled();
+                    self.bank_history_items = bank_history_items;
+
+                    let io_log = lock.io_write_log();
+                    let range = self.io_log_range();
+                    let mut io_log_items = Vec::with_capacity(io_log.entries().len());
+
+                    for write in io_log.entries().iter().rev() {
+                        if let Some((start, end)) = range {
+                            if write.address() < start || write.address() > end {
+                                continue;
+                            }
+                        }
+
+                        io_log_items.push(ImString::from(format!("{:04X}: ${:04X} = ${:02X}", write.pc(), write.address(), write.value())));
+                    }
+
+                    self.io_log_enabled = io_log.enabled();
+                    self.io_log_items = io_log_items;
+
+                    let stats = lock.access_stats();
+                    let mut access_stats_items = Vec::with_capacity(REGION_NAMES.len());
+
+                    if let Some(last_frame) = stats.last_frame() {
+                        for (name, accesses) in REGION_NAMES.iter().zip(last_frame.iter()) {
+                            access_stats_items.push(ImString::from(format!("{:<5} {:>7} reads {:>7} writes", name, accesses.reads, accesses.writes)));
+                        }
+                    }
+
+                    self.access_stats_enabled = stats.enabled();
+                    self.access_stats_items = access_stats_items;
+                }
+
+                if let Ok(lock) = self.dma_log.read() {
+                    let mut dma_log_items = Vec::with_capacity(lock.entries().len());
+
+                    for event in lock.entries().iter().rev() {
+                        let finished = match event.finished_at() {
+                            Some(cycles) => format!("{} cycles", cycles - event.started_at()),
+                            None => String::from("running")
+                        };
+
+                        dma_log_items.push(ImString::from(format!("{:04X}: ${:04X} at {}, {} ({} blocked)",
+                            event.pc(),
+                            event.source(),
+                            event.started_at(),
+                            finished,
+                            event.blocked_accesses()
+                        )));
+                    }
+
+                    self.dma_log_enabled = lock.enabled();
+                    self.dma_log_items = dma_log_items;
                 }
             }
 
@@ -89,15 +251,15 @@ impl CPUWindow {
             
             ui.text(format!("AF: {:04X}", self.registers[0]));
             ui.same_line();
-            ui.text(format!("BC: {:04X}", self.registers[1]));
+            self.register_link(ui, "BC", self.registers[1]);
             
-            ui.text(format!("DE: {:04X}", self.registers[2]));
+            self.register_link(ui, "DE", self.registers[2]);
             ui.same_line();
-            ui.text(format!("HL: {:04X}", self.registers[3]));
+            self.register_link(ui, "HL", self.registers[3]);
 
-            ui.text(format!("SP: {:04X}", self.registers[4]));
+            self.register_link(ui, "SP", self.registers[4]);
             ui.same_line();
-            ui.text(format!("PC: {:04X}", self.registers[5]));
+            self.register_link(ui, "PC", self.registers[5]);
 
             ui.next_column();
 
@@ -113,6 +275,25 @@ impl CPUWindow {
 
             ui.columns(1, "cpu_cols", false);
 
+            if ui.small_button("Copy registers") {
+                let dump = format!(
+                    "AF: {:04X} BC: {:04X} DE: {:04X} HL: {:04X} SP: {:04X} PC: {:04X}\nZ: {} N: {} H: {} C: {}\nStatus: {}",
+                    self.registers[0], self.registers[1], self.registers[2],
+                    self.registers[3], self.registers[4], self.registers[5],
+                    (self.registers[0] & 0x80) != 0, (self.registers[0] & 0x40) != 0,
+                    (self.registers[0] & 0x20) != 0, (self.registers[0] & 0x10) != 0,
+                    self.dbg_mode
+                );
+
+                ui.set_clipboard_text(dump);
+            }
+
+            ui.same_line();
+
+            if ui.small_button("Go to PC") {
+                self.navigation = Some(Navigation::Disassembler(self.registers[5]));
+            }
+
             ui.separator();
             ui.bullet_text("CPU Controls");
 
@@ -154,6 +335,57 @@ impl CPUWindow {
 
                 if let Ok(mut lock) = self.gb.write() {
                     lock.gb_reset();
+
+                    if clear_breakpoints_on_reset {
+                        lock.dbg_breakpoint_list.clear();
+                    }
+                }
+            }
+
+            ui.set_next_item_width(80.0);
+            ui.input_int("##step_count", &mut self.step_count).build();
+
+            if self.step_count < 1 {
+                self.step_count = 1;
+            }
+
+            ui.same_line();
+
+            if ui.button("Step N") {
+                adjust_cursor = true;
+
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.dbg_step_instructions(self.step_count as usize);
+                    self.dbg_mode = lock.dbg_mode.clone();
+                }
+            }
+
+            ui.same_line();
+
+            if ui.button("Step frame") {
+                adjust_cursor = true;
+
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.dbg_step_frame();
+                    self.dbg_mode = lock.dbg_mode.clone();
+                }
+            }
+
+            ui.set_next_item_width(80.0);
+            ui.input_int("##cycle_count", &mut self.cycle_count).build();
+
+            if self.cycle_count < 1 {
+                self.cycle_count = 1;
+            }
+
+            ui.same_line();
+
+            if ui.button("Run N cycles") {
+                adjust_cursor = true;
+
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.dbg_step_cycles(self.cycle_count as usize);
+                    self.dbg_mode = lock.dbg_mode.clone();
                 }
             }
 
@@ -253,14 +485,144 @@ impl CPUWindow {
                 }
             }
 
+            ui.separator();
+            ui.bullet_text("Scanline Breakpoint");
+
+            let mut ly_bp_changed = ui.checkbox("Break on LY", &mut self.ly_bp_enabled);
+
+            ui.same_line();
+            ui.set_next_item_width(80.0);
+            ly_bp_changed |= ui.input_int("##ly_bp", &mut self.ly_bp_value).build();
+
+            if ly_bp_changed {
+                self.ly_bp_value = self.ly_bp_value.clamp(0, 153);
+
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.dbg_set_ly_breakpoint(if self.ly_bp_enabled {Some(self.ly_bp_value as u8)} else {None});
+                }
+            }
+
             ui.separator();
             ui.bullet_text("CPU Callstack");
 
+            if let Some(mismatch) = self.callstack_mismatch.as_ref() {
+                ui.text_colored([1.0, 0.6, 0.0, 1.0], "Stack manipulated");
+
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!("{}.\nThe frames shown might not match the real stack.", mismatch));
+                }
+            }
+
             ListBox::new("##c").size([220.0, 70.0]).build(ui, || {
                 for call in self.callstack_items.iter() {
                     ui.selectable(call);
                 }
             });
+
+            ui.separator();
+            ui.bullet_text("Bank Switches");
+
+            if ui.checkbox("Record", &mut self.bank_history_enabled) {
+                if let Ok(mut lock) = self.gb_mem.write() {
+                    lock.cartridge_mut().bank_history_mut().set_enabled(self.bank_history_enabled);
+                }
+            }
+
+            ui.same_line();
+
+            if ui.button("Clear") {
+                if let Ok(mut lock) = self.gb_mem.write() {
+                    lock.cartridge_mut().bank_history_mut().clear();
+                    self.bank_history_items.clear();
+                }
+            }
+
+            ListBox::new("##banks").size([220.0, 70.0]).build(ui, || {
+                for switch in self.bank_history_items.iter() {
+                    ui.selectable(switch);
+                }
+            });
+
+            ui.separator();
+            ui.bullet_text("OAM DMA");
+
+            if ui.checkbox("Record##dma", &mut self.dma_log_enabled) {
+                if let Ok(mut lock) = self.dma_log.write() {
+                    lock.set_enabled(self.dma_log_enabled);
+                }
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Blocked accesses are reads and writes the CPU did outside of HRAM while the transfer ran.");
+            }
+
+            ui.same_line();
+
+            if ui.button("Clear##dma") {
+                if let Ok(mut lock) = self.dma_log.write() {
+                    lock.clear();
+                    self.dma_log_items.clear();
+                }
+            }
+
+            ListBox::new("##dma").size([220.0, 70.0]).build(ui, || {
+                for event in self.dma_log_items.iter() {
+                    ui.selectable(event);
+                }
+            });
+
+            ui.separator();
+            ui.bullet_text("IO Writes");
+
+            if ui.checkbox("Record##io", &mut self.io_log_enabled) {
+                if let Ok(mut lock) = self.gb_mem.write() {
+                    lock.io_write_log_mut().set_enabled(self.io_log_enabled);
+                }
+            }
+
+            ui.same_line();
+
+            if ui.button("Clear##io") {
+                if let Ok(mut lock) = self.gb_mem.write() {
+                    lock.io_write_log_mut().clear();
+                    self.io_log_items.clear();
+                }
+            }
+
+            ui.same_line();
+            ui.set_next_item_width(90.0);
+            ui.input_text("##io_filter", &mut self.io_log_filter).hint("FF10-FF26").build();
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Only show writes to a register, or a range of them.");
+            }
+
+            ListBox::new("##io").size([220.0, 70.0]).build(ui, || {
+                for write in self.io_log_items.iter() {
+                    ui.selectable(write);
+                }
+            });
+
+            ui.separator();
+            ui.bullet_text("Memory Accesses");
+
+            if ui.checkbox("Record##access", &mut self.access_stats_enabled) {
+                if let Ok(mut lock) = self.gb_mem.write() {
+                    lock.access_stats_mut().set_enabled(self.access_stats_enabled);
+                }
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Counts the CPU's reads and writes to each region during the last frame.");
+            }
+
+            if self.access_stats_enabled && self.access_stats_items.is_empty() {
+                ui.text_disabled("Waiting for a frame to finish.");
+            }
+
+            for item in self.access_stats_items.iter() {
+                ui.text(item);
+            }
         });
 
         adjust_cursor
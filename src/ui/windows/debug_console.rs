@@ -0,0 +1,87 @@
+use std::sync::{Arc, RwLock};
+
+use imgui::*;
+
+use crate::gameboy::Gameboy;
+use crate::gameboy::memory::DEBUG_CHANNEL_ADDRESS;
+use crate::gameboy::memory::serial::SerialLog;
+
+use crate::ui::AppConfig;
+
+// Output of the debug channel, meant for printf-style debugging of homebrew.
+pub struct DebugConsoleWindow {
+    gb: Arc<RwLock<Gameboy>>,
+    gb_debug: Arc<RwLock<SerialLog>>,
+
+    show_timestamps: bool
+}
+
+impl DebugConsoleWindow {
+    pub fn init(gb: Arc<RwLock<Gameboy>>) -> DebugConsoleWindow {
+        let gb_debug = gb.read().unwrap().ui_get_debug_output();
+
+        DebugConsoleWindow {
+            gb,
+            gb_debug,
+
+            show_timestamps: false
+        }
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool, config: &mut AppConfig) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("Debug Console").size([475.0, 220.0], Condition::FirstUseEver).opened(opened).build(|| {
+            if ui.checkbox("Enabled", &mut config.debug_channel) {
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.set_debug_channel(config.debug_channel);
+                }
+
+                config.save();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text(format!(
+                    "Bytes written to ${:04X} show up here, $0A or $00 end the line.\nIt's not a real register, so only enable it for homebrew that expects it.",
+                    DEBUG_CHANNEL_ADDRESS
+                ));
+            }
+
+            ui.same_line();
+            ui.checkbox("Timestamps", &mut self.show_timestamps);
+            ui.same_line();
+
+            if ui.button("Clear") {
+                if let Ok(mut lock) = self.gb_debug.write() {
+                    lock.clear();
+                }
+            }
+
+            ui.separator();
+
+            ui.child_window("debug_lines").build(|| {
+                // Follow new output, unless the user scrolled up to read something.
+                let follow = ui.scroll_y() >= ui.scroll_max_y();
+
+                if let Ok(lock) = self.gb_debug.read() {
+                    for line in lock.lines() {
+                        let text = String::from_utf8_lossy(line.data());
+
+                        if self.show_timestamps {
+                            ui.text_wrapped(format!("[{:>12}] {}", line.cycles(), text));
+                        }
+                        else {
+                            ui.text_wrapped(text);
+                        }
+                    }
+                }
+
+                if follow {
+                    ui.set_scroll_here_y_with_ratio(1.0);
+                }
+            });
+        });
+    }
+}
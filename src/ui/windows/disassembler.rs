@@ -6,12 +6,35 @@ use crate::gameboy::disassembler;
 use crate::gameboy::memory::regions::*;
 use crate::gameboy::memory::GameboyMemory;
 use crate::gameboy::{Breakpoint, EmulatorMode, Gameboy};
+use crate::gameboy::disassembler::DisassemblyStyle;
+
+use crate::ui::AppConfig;
+
+use super::Navigation;
 
 pub struct DisassemblerWindow {
     gb: Arc<RwLock<Gameboy>>,
     gb_mem: Arc<RwLock<GameboyMemory>>,
 
-    adjusted_cursor: bool
+    adjusted_cursor: bool,
+    highlight_coverage: bool,
+    // Copied from the config on every draw.
+    style: DisassemblyStyle,
+    // ROM bank decoded at $4000-$7FFF, None follows the one that's mapped in.
+    rom_bank: Option<usize>,
+
+    selected_line: u16,
+    // Other end of the selection, lines between it and selected_line get copied.
+    selection_anchor: u16,
+    // Set when selected_line was moved from somewhere else, like the references list.
+    scroll_to_selection: bool,
+
+    references_opened: bool,
+    references_target: String,
+    // Address, ROM bank and text of every instruction found by the last search.
+    references: Vec<(u16, usize, String)>,
+
+    navigation: Option<Navigation>
 }
 
 impl DisassemblerWindow {
@@ -22,15 +45,297 @@ impl DisassemblerWindow {
             gb,
             gb_mem,
 
-            adjusted_cursor: true
+            adjusted_cursor: true,
+            highlight_coverage: true,
+            style: DisassemblyStyle::default(),
+            rom_bank: None,
+
+            selected_line: 0,
+            selection_anchor: 0,
+            scroll_to_selection: false,
+
+            references_opened: false,
+            references_target: String::new(),
+            references: Vec::new(),
+
+            navigation: None
+        }
+    }
+
+    /// Returns the address the user asked to see in another window since the last call, if any.
+    pub fn take_navigation(&mut self) -> Option<Navigation> {
+        self.navigation.take()
+    }
+
+    fn region_label(&self, address: u16) -> String {
+        if CARTRIDGE_ROM_BANK0.contains(&address) {
+            String::from("ROM00")
+        }
+        else if CARTRIDGE_ROM_BANKX.contains(&address) {
+            format!("ROM{:02}", self.shown_rom_bank())
+        }
+        else if VRAM.contains(&address) {
+            String::from("VRAM")
+        }
+        else if CARTRIDGE_RAM.contains(&address) {
+            String::from("CRAM")
+        }
+        else if WRAM.contains(&address) {
+            String::from("WRAM")
+        }
+        // Reads here come from WRAM, so show which address is really being decoded.
+        else if ECHO.contains(&address) {
+            format!("ECHO({:04X})", address - 0x2000)
+        }
+        else if OAM.contains(&address) {
+            String::from("OAM")
+        }
+        else if (0xFEA0..=0xFEFF).contains(&address) {
+            String::from("UNK")
+        }
+        else if IO.contains(&address) {
+            String::from("IO")
+        }
+        else if HRAM.contains(&address) {
+            String::from("HRAM")
+        }
+        else {
+            String::from("IE")
+        }
+    }
+
+    // Walks bank 0 and every switchable ROM bank, not only the mapped one,
+    // decoding instructions the same way the listing does.
+    fn find_references(&mut self, target: u16) {
+        let bank_count = {
+            if let Ok(lock) = self.gb_mem.read() {
+                *lock.cartridge().get_header().rom_banks_count()
+            }
+            else {
+                2
+            }
+        };
+
+        self.references.clear();
+
+        for bank in 0..bank_count.max(2) {
+            // Bank 0 is always at $0000-$3FFF, the rest get decoded at $4000-$7FFF.
+            let (mut address, end, rom_bank) = if bank == 0 {(0x0000, 0x4000, None)} else {(0x4000, 0x8000, Some(bank))};
+
+            while address < end {
+                let (len, dis) = disassembler::get_instruction_data_styled_banked(address, &self.gb_mem, &self.style, rom_bank);
+
+                if disassembler::get_instruction_target_banked(address, &self.gb_mem, rom_bank) == Some(target) {
+                    self.references.push((address, bank, format!("ROM{:02}: {:04X} - {}", bank, address, dis)));
+                }
+
+                address += len.max(1);
+            }
+        }
+    }
+
+    fn draw_references(&mut self, ui: &Ui) {
+        if !self.references_opened {
+            return;
+        }
+
+        let mut opened = self.references_opened;
+
+        ui.window("References").size([300.0, 250.0], Condition::FirstUseEver).opened(&mut opened).build(|| {
+            ui.set_next_item_width(80.0);
+
+            let entered = ui.input_text("##references_target", &mut self.references_target)
+                .chars_hexadecimal(true)
+                .enter_returns_true(true)
+                .build()
+            ;
+
+            ui.same_line();
+
+            if ui.button("Find") || entered {
+                if let Ok(target) = u16::from_str_radix(&self.references_target, 16) {
+                    self.find_references(target);
+                }
+            }
+
+            ui.text(format!("{} references found", self.references.len()));
+            ui.separator();
+
+            let mut goto = None;
+
+            ui.child_window("references_list").build(|| {
+                for (address, bank, line) in self.references.iter() {
+                    if ui.selectable_config(line).selected(self.selected_line == *address).build() {
+                        goto = Some((*address, *bank));
+                    }
+                }
+            });
+
+            if let Some((address, bank)) = goto {
+                // References in other banks switch the listing over to them.
+                if CARTRIDGE_ROM_BANKX.contains(&address) && self.shown_rom_bank() != bank {
+                    self.rom_bank = Some(bank);
+                }
+
+                self.navigate_to(address);
+            }
+        });
+
+        self.references_opened = opened;
+    }
+
+    /// Selects the instruction at the given address, and scrolls to it on the next draw.
+    pub fn navigate_to(&mut self, address: u16) {
+        self.selected_line = address;
+        self.selection_anchor = address;
+        self.scroll_to_selection = true;
+    }
+
+    // Finds the start of the instruction right before the given address.
+    fn previous_instruction(&self, address: u16) -> u16 {
+        for offset in (1..=3).rev() {
+            let candidate = address.wrapping_sub(offset);
+            let (len, _) = disassembler::get_instruction_data_banked(candidate, &self.gb_mem, self.rom_bank);
+
+            if candidate < address && candidate.wrapping_add(len) == address {
+                return candidate;
+            }
+        }
+
+        address.saturating_sub(1)
+    }
+
+    // Handles the navigation keys, returns true if the selected line moved.
+    fn handle_keyboard(&mut self, ui: &Ui, page_size: u16) -> bool {
+        let previous = self.selected_line;
+
+        if ui.is_key_pressed(Key::Home) {
+            self.selected_line = 0;
+        }
+        else if ui.is_key_pressed(Key::End) {
+            self.selected_line = 0xFFFF;
+        }
+        else if ui.is_key_pressed(Key::PageUp) {
+            self.selected_line = self.selected_line.saturating_sub(page_size);
+        }
+        else if ui.is_key_pressed(Key::PageDown) {
+            self.selected_line = self.selected_line.saturating_add(page_size);
+        }
+        else if ui.is_key_pressed(Key::UpArrow) {
+            self.selected_line = self.previous_instruction(self.selected_line);
         }
+        else if ui.is_key_pressed(Key::DownArrow) {
+            let (len, _) = disassembler::get_instruction_data_banked(self.selected_line, &self.gb_mem, self.rom_bank);
+            self.selected_line = self.selected_line.saturating_add(len);
+        }
+
+        if !ui.io().key_shift {
+            self.selection_anchor = self.selected_line;
+        }
+
+        previous != self.selected_line
     }
 
-    pub fn draw(&mut self, ui: &Ui, adjust: bool, opened: &mut bool) {
+    fn is_selected(&self, address: u16) -> bool {
+        let start = self.selected_line.min(self.selection_anchor);
+        let end = self.selected_line.max(self.selection_anchor);
+
+        (start..=end).contains(&address)
+    }
+
+    fn copy_selection(&self, ui: &Ui) {
+        let mut lines = Vec::new();
+        let mut address = self.selected_line.min(self.selection_anchor);
+        let end = self.selected_line.max(self.selection_anchor);
+
+        loop {
+            let (len, dis) = disassembler::get_instruction_data_styled_banked(address, &self.gb_mem, &self.style, self.rom_bank);
+            lines.push(format!("{:04X} - {}", address, dis));
+
+            match address.checked_add(len) {
+                Some(next) if next <= end => address = next,
+                _ => break
+            }
+        }
+
+        ui.set_clipboard_text(lines.join("\n"));
+    }
+
+    fn mapped_rom_bank(&self) -> usize {
+        if let Ok(lock) = self.gb_mem.read() {
+            lock.cartridge().get_selected_rom_bank()
+        }
+        else {
+            1
+        }
+    }
+
+    fn shown_rom_bank(&self) -> usize {
+        self.rom_bank.unwrap_or_else(|| self.mapped_rom_bank())
+    }
+
+    fn draw_bank_menu(&mut self, ui: &Ui) {
+        let bank_count = {
+            if let Ok(lock) = self.gb_mem.read() {
+                *lock.cartridge().get_header().rom_banks_count()
+            }
+            else {
+                2
+            }
+        };
+
+        if ui.menu_item_config(format!("Mapped (ROM{:02})", self.mapped_rom_bank())).selected(self.rom_bank.is_none()).build() {
+            self.rom_bank = None;
+        }
+
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Shows whatever bank the game has mapped at $4000-$7FFF.");
+        }
+
+        ui.separator();
+
+        for bank in 1..bank_count.max(2) {
+            if ui.menu_item_config(format!("ROM{:02}", bank)).selected(self.rom_bank == Some(bank)).build() {
+                self.rom_bank = Some(bank);
+            }
+        }
+    }
+
+    fn draw_style_menu(ui: &Ui, config: &mut AppConfig) {
+        let style = &mut config.disassembly_style;
+        let mut changed = false;
+
+        if ui.menu_item_config("Use 0x prefix").selected(style.c_prefix).build() {
+            style.c_prefix = !style.c_prefix;
+            changed = true;
+        }
+
+        if ui.menu_item_config("Lowercase hex").selected(style.lowercase_hex).build() {
+            style.lowercase_hex = !style.lowercase_hex;
+            changed = true;
+        }
+
+        if ui.menu_item_config("Show signed offsets").selected(style.signed_offsets).build() {
+            style.signed_offsets = !style.signed_offsets;
+            changed = true;
+        }
+
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Shows JR and SP offsets as written in the instruction, instead of the resulting address.");
+        }
+
+        if changed {
+            config.save();
+        }
+    }
+
+    pub fn draw(&mut self, ui: &Ui, adjust: bool, opened: &mut bool, config: &mut AppConfig) {
         if !*opened {
             return;
         }
 
+        self.style = config.disassembly_style;
+
         let pc = {
             if let Ok(lock) = self.gb.read() {
                 let (_, _, _, _, _, pc) = lock.ui_get_cpu_registers();
@@ -41,63 +346,79 @@ impl DisassemblerWindow {
             }
         };
 
-        ui.window("Disassembler").size([300.0, 325.0], Condition::FirstUseEver).opened(opened).build(|| {
-            let mut clipper = ListClipper::new(0xFFFF).items_height(ui.text_line_height() / 2.0).begin(ui);
+        ui.window("Disassembler").size([300.0, 325.0], Condition::FirstUseEver).opened(opened).menu_bar(true).build(|| {
+            ui.menu_bar(|| {
+                ui.menu("Edit", || {
+                    if ui.menu_item_config("Copy selection").shortcut("Ctrl+C").build() {
+                        self.copy_selection(ui);
+                    }
+                });
+
+                ui.menu("Search", || {
+                    if ui.menu_item("Find references to selection") {
+                        self.references_target = format!("{:04X}", self.selected_line);
+                        self.references_opened = true;
+                        self.find_references(self.selected_line);
+                    }
+
+                    if ui.menu_item("Show references window") {
+                        self.references_opened = true;
+                    }
+
+                    let target = disassembler::get_instruction_target_banked(self.selected_line, &self.gb_mem, self.rom_bank).filter(|target| VRAM.contains(target));
+
+                    if ui.menu_item_config("Show target tile in VRAM viewer").enabled(target.is_some()).build() {
+                        self.navigation = target.map(Navigation::Tile);
+                    }
+                });
+
+                ui.menu("Display", || {
+                    DisassemblerWindow::draw_style_menu(ui, config);
+                });
+
+                ui.menu("Bank", || {
+                    self.draw_bank_menu(ui);
+                });
+
+                ui.menu("Coverage", || {
+                    if ui.menu_item_config("Highlight executed code").selected(self.highlight_coverage).build() {
+                        self.highlight_coverage = !self.highlight_coverage;
+                    }
+
+                    if ui.menu_item("Reset coverage") {
+                        if let Ok(mut lock) = self.gb.write() {
+                            lock.dbg_reset_coverage();
+                        }
+                    }
+                });
+            });
+
+            let item_height = ui.text_line_height() / 2.0;
+            let page_size = ((ui.content_region_avail()[1] / ui.text_line_height_with_spacing()) as u16).max(1);
+            let selection_moved = ui.is_window_focused() && self.handle_keyboard(ui, page_size);
+
+            if ui.is_window_focused() && ui.io().key_ctrl && ui.is_key_pressed(Key::C) {
+                self.copy_selection(ui);
+            }
+
+            let mut clipper = ListClipper::new(0xFFFF).items_height(item_height).begin(ui);
             clipper.step();
 
             let mut skipped_lines = 0;
             let mut last_instruction_len = 0;
 
+            // PC and coverage only know about the mapped bank, so they don't apply to a different one.
+            let other_bank = self.rom_bank.is_some() && self.rom_bank != Some(self.mapped_rom_bank());
+
             for line in clipper.display_start()..clipper.display_end() {
                 if skipped_lines == last_instruction_len {
                     let current_addr = line as u16;
-                    let (len, dis) = disassembler::get_instruction_data(current_addr, &self.gb_mem);
-
-                    let line_p = if pc == current_addr {"> "} else {""};
-                    let address_p = {
-                        if CARTRIDGE_ROM_BANK0.contains(&current_addr) {
-                            String::from("ROM00")
-                        }
-                        else if CARTRIDGE_ROM_BANKX.contains(&current_addr) {
-                            let bank = {
-                                if let Ok(lock) = self.gb_mem.read() {
-                                    lock.cartridge().get_selected_rom_bank()
-                                }
-                                else {
-                                    1
-                                }
-                            };
+                    let (len, dis) = disassembler::get_instruction_data_styled_banked(current_addr, &self.gb_mem, &self.style, self.rom_bank);
+                    let in_other_bank = other_bank && CARTRIDGE_ROM_BANKX.contains(&current_addr);
+                    let is_pc = pc == current_addr && !in_other_bank;
 
-                            format!("ROM{:02}", bank)
-                        }
-                        else if VRAM.contains(&current_addr) {
-                            String::from("VRAM")
-                        }
-                        else if CARTRIDGE_RAM.contains(&current_addr) {
-                            String::from("CRAM")
-                        }
-                        else if WRAM.contains(&current_addr) {
-                            String::from("WRAM")
-                        }
-                        else if ECHO.contains(&current_addr) {
-                            String::from("ECHO")
-                        }
-                        else if OAM.contains(&current_addr) {
-                            String::from("OAM")
-                        }
-                        else if (0xFEA0..=0xFEFF).contains(&current_addr) {
-                            String::from("UNK")
-                        }
-                        else if IO.contains(&current_addr) {
-                            String::from("IO")
-                        }
-                        else if HRAM.contains(&current_addr) {
-                            String::from("HRAM")
-                        }
-                        else {
-                            String::from("IE")
-                        }
-                    };
+                    let line_p = if is_pc {"> "} else {""};
+                    let address_p = self.region_label(current_addr);
                     let line_str = format!("{}{}: {:04X} - {}", line_p, address_p, current_addr, dis);
 
                     skipped_lines = 1;
@@ -105,6 +426,7 @@ impl DisassemblerWindow {
 
                     let mut bp_idx = 0;
                     let mut address_is_bp = false;
+                    let mut address_executed = false;
 
                     if let Ok(lock) = self.gb.read() {
                         for (idx, bp) in lock.dbg_breakpoint_list.iter().enumerate() {
@@ -115,40 +437,67 @@ impl DisassemblerWindow {
                                 break;
                             }
                         }
+
+                        address_executed = lock.dbg_coverage[current_addr as usize] || in_other_bank;
                     }
 
                     let text = ImString::from(line_str);
-                    let widget = ui.selectable_config(&text).allow_double_click(true);
+                    let widget = ui.selectable_config(&text).selected(self.is_selected(current_addr)).allow_double_click(true);
 
-                    let entry = || if widget.build() && ui.is_mouse_double_clicked(MouseButton::Left) {
-                        if let Ok(mut lock) = self.gb.write() {
-                            if address_is_bp {
-                                lock.dbg_breakpoint_list.remove(bp_idx);
-                            }
-                            else {
-                                lock.dbg_breakpoint_list.push(
-                                    Breakpoint::new(false, false, true, current_addr)
-                                );
+                    let entry = || {
+                        let clicked = widget.build();
+
+                        if clicked && ui.is_mouse_double_clicked(MouseButton::Left) {
+                            if let Ok(mut lock) = self.gb.write() {
+                                if address_is_bp {
+                                    lock.dbg_breakpoint_list.remove(bp_idx);
+                                }
+                                else {
+                                    lock.dbg_breakpoint_list.push(
+                                        Breakpoint::new(false, false, true, current_addr)
+                                    );
+                                }
                             }
                         }
+
+                        clicked
                     };
 
+                    let clicked;
+
                     if address_is_bp {
                         let token = ui.push_style_color(StyleColor::Text, [1.0, 0.0, 0.0, 1.0]);
 
-                        (entry)();
+                        clicked = (entry)();
 
                         token.pop();
                     }
-                    else if pc == current_addr {
+                    else if is_pc {
                         let token = ui.push_style_color(StyleColor::Text, [0.0, 1.0, 0.0, 1.0]);
 
-                        (entry)();
+                        clicked = (entry)();
+
+                        token.pop();
+                    }
+                    // Dim the code that hasn't run yet, so executed paths stand out.
+                    else if self.highlight_coverage && !address_executed {
+                        let token = ui.push_style_color(StyleColor::Text, [0.5, 0.5, 0.5, 1.0]);
+
+                        clicked = (entry)();
 
                         token.pop();
                     }
                     else {
-                        (entry)();
+                        clicked = (entry)();
+                    }
+
+                    if clicked {
+                        self.selected_line = current_addr;
+
+                        // Shift+click extends the selection instead of starting a new one.
+                        if !ui.io().key_shift {
+                            self.selection_anchor = current_addr;
+                        }
                     }
                 }
                 else {
@@ -158,10 +507,25 @@ impl DisassemblerWindow {
 
             clipper.end();
 
+            // Keep the selected line on screen when moving it with the keyboard.
+            if selection_moved || self.scroll_to_selection {
+                self.scroll_to_selection = false;
+
+                let top = ui.scroll_y() / item_height;
+                let selected = self.selected_line as f32;
+
+                if selected < top {
+                    ui.set_scroll_y(selected * item_height);
+                }
+                else if selected >= top + page_size as f32 {
+                    ui.set_scroll_y((selected - page_size as f32 + 1.0) * item_height);
+                }
+            }
+
             if adjust {
                 if let Ok(lock) = self.gb.read() {
                     match lock.dbg_mode {
-                        EmulatorMode::Paused | EmulatorMode::BreakpointHit | EmulatorMode::UnknownInstruction(..) => {
+                        EmulatorMode::Paused | EmulatorMode::BreakpointHit | EmulatorMode::Locked(_) => {
                             if !self.adjusted_cursor {
                                 let target = ui.cursor_start_pos()[1] + pc as f32 * (ui.text_line_height() / 2.0);
     
@@ -177,5 +541,7 @@ impl DisassemblerWindow {
                 self.adjusted_cursor = false;
             }
         });
+
+        self.draw_references(ui);
     }
 }
@@ -1,11 +1,19 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use imgui::*;
 
 pub struct FilePickerWindow {
     current_path: PathBuf,
-    show_dot_entries: bool
+    show_dot_entries: bool,
+
+    // Files without an extension always show up, unless this is empty.
+    extensions: &'static [&'static str],
+    show_all_files: bool,
+
+    // Only set when picking where to save a file.
+    save_name: Option<String>,
+    cancelled: bool
 }
 
 impl FilePickerWindow {
@@ -14,10 +22,48 @@ impl FilePickerWindow {
         
         FilePickerWindow {
             current_path,
-            show_dot_entries: false
+            show_dot_entries: false,
+
+            extensions: &[],
+            show_all_files: false,
+
+            save_name: None,
+            cancelled: false
         }
     }
 
+    /// Creates a picker that lets the user type a file name, for saving files.
+    pub fn init_save(current_path: PathBuf, file_name: &str) -> FilePickerWindow {
+        let mut picker = FilePickerWindow::init(current_path);
+        picker.save_name = Some(file_name.to_string());
+
+        picker
+    }
+
+    /// Only lists files with one of the given extensions (or none at all), unless the user asks for all of them.
+    pub fn with_extensions(mut self, extensions: &'static [&'static str]) -> FilePickerWindow {
+        self.extensions = extensions;
+        self
+    }
+
+    fn extension_allowed(&self, path: &Path) -> bool {
+        if self.extensions.is_empty() || self.show_all_files {
+            return true;
+        }
+
+        match path.extension() {
+            Some(extension) => {
+                let extension = extension.to_string_lossy().to_ascii_lowercase();
+                self.extensions.iter().any(|e| *e == extension)
+            }
+            None => true
+        }
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
     pub fn draw(&mut self, ui: &Ui) -> Option<PathBuf> {
         let mut chosen_file = None;
 
@@ -45,7 +91,7 @@ impl FilePickerWindow {
                                         files.push(entry);
                                     }
                                 }
-                                else {
+                                else if self.extension_allowed(&entry.path()) {
                                     files.push(entry);
                                 }
                             }
@@ -74,9 +120,14 @@ impl FilePickerWindow {
                             if let Some(path) = file.file_name().to_str() {
                                 let path = path.to_string();
 
-                                if ui.selectable(&ImString::from(path)) {
-                                    chosen_file = Some(file.path());
-                                    ui.close_current_popup();
+                                if ui.selectable(&ImString::from(path.clone())) {
+                                    if let Some(name) = self.save_name.as_mut() {
+                                        *name = path;
+                                    }
+                                    else {
+                                        chosen_file = Some(file.path());
+                                        ui.close_current_popup();
+                                    }
                                 }
                             }
                         }
@@ -84,6 +135,26 @@ impl FilePickerWindow {
                 });
 
                 ui.checkbox("Show entries starting with .", &mut self.show_dot_entries);
+
+                if !self.extensions.is_empty() {
+                    ui.checkbox("Show all files", &mut self.show_all_files);
+                }
+
+                if let Some(name) = self.save_name.as_mut() {
+                    ui.input_text("File name", name).build();
+
+                    if ui.button("Save") && !name.is_empty() {
+                        chosen_file = Some(self.current_path.join(name.as_str()));
+                        ui.close_current_popup();
+                    }
+
+                    ui.same_line();
+                }
+
+                if ui.button("Cancel") {
+                    self.cancelled = true;
+                    ui.close_current_popup();
+                }
             }
             else {
                 ui.text_colored([1.0, 0.0, 0.0, 1.0], "Couldn't open current path.");
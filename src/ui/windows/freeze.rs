@@ -0,0 +1,95 @@
+use std::sync::{Arc, RwLock};
+
+use imgui::*;
+
+use crate::gameboy::Gameboy;
+use crate::ui::overrides::{FrozenAddress, GameOverrides};
+
+pub struct FreezeWindow {
+    gb: Arc<RwLock<Gameboy>>,
+
+    new_address: String,
+    new_value: String
+}
+
+impl FreezeWindow {
+    pub fn init(gb: Arc<RwLock<Gameboy>>) -> FreezeWindow {
+        FreezeWindow {
+            gb,
+
+            new_address: String::new(),
+            new_value: String::new()
+        }
+    }
+
+    // The list is kept with the game's overrides, so it's saved right away on every change.
+    fn apply(&self, overrides: &GameOverrides, rom_hash: u32) {
+        if let Ok(mut lock) = self.gb.write() {
+            lock.freeze_list = overrides.enabled_freezes();
+        }
+
+        overrides.save(rom_hash);
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool, overrides: &mut GameOverrides, rom_hash: u32) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("Freeze").size([250.0, 200.0], Condition::FirstUseEver).opened(opened).build(|| {
+            let mut changed = false;
+            let mut remove = None;
+
+            for (idx, freeze) in overrides.freezes.iter_mut().enumerate() {
+                let token = ui.push_id(&format!("freeze{}", idx));
+
+                changed |= ui.checkbox(format!("${:04X} = ${:02X}", freeze.address, freeze.value), &mut freeze.enabled);
+
+                ui.same_line();
+
+                if ui.small_button("X") {
+                    remove = Some(idx);
+                }
+
+                token.pop();
+            }
+
+            if let Some(idx) = remove {
+                overrides.freezes.remove(idx);
+                changed = true;
+            }
+
+            ui.separator();
+
+            ui.set_next_item_width(50.0);
+            ui.input_text("##freeze_address", &mut self.new_address).chars_hexadecimal(true).build();
+            ui.same_line();
+            ui.text("=");
+            ui.same_line();
+            ui.set_next_item_width(30.0);
+            ui.input_text("##freeze_value", &mut self.new_value).chars_hexadecimal(true).build();
+            ui.same_line();
+
+            if ui.button("Add") {
+                let address = u16::from_str_radix(&self.new_address, 16);
+                let value = u8::from_str_radix(&self.new_value, 16);
+
+                if let (Ok(address), Ok(value)) = (address, value) {
+                    overrides.freezes.push(FrozenAddress { address, value, enabled: true });
+                    changed = true;
+
+                    self.new_address.clear();
+                    self.new_value.clear();
+                }
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("The value is written back to the address after every frame.");
+            }
+
+            if changed {
+                self.apply(overrides, rom_hash);
+            }
+        });
+    }
+}
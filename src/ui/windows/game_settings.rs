@@ -0,0 +1,176 @@
+use imgui::*;
+
+use crate::gameboy::Model;
+use crate::gameboy::ppu::compat_palettes::{CompatPaletteChoice, MANUAL_PALETTES};
+use crate::ui::AppState;
+use crate::ui::overrides::GameOverrides;
+
+pub struct GameSettingsWindow;
+
+impl GameSettingsWindow {
+    pub fn init() -> GameSettingsWindow {
+        GameSettingsWindow {}
+    }
+
+    pub fn draw(&mut self, ui: &Ui, app_state: &mut AppState) {
+        if let Some(_token) = PopupModal::new("Per-game Settings").always_auto_resize(true).begin_popup(ui) {
+            ui.text(format!("Settings for ROM {:08X}", app_state.rom_hash));
+            ui.text_disabled("Checked settings replace the global ones for this game.");
+            ui.separator();
+
+            let global_fast_boot = app_state.config.fast_boot;
+            let global_pause = app_state.config.pause_emulator_on_startup;
+            let global_gamma = app_state.config.screen_gamma;
+            let global_contrast = app_state.config.screen_contrast;
+            let global_batch_size = app_state.config.emulation_batch_size;
+            let global_model = app_state.config.model;
+            let global_palette = app_state.config.compat_palette;
+
+            let overrides = &mut app_state.config.overrides;
+
+            override_bool(ui, "Skip bootrom", &mut overrides.skip_bootrom, false);
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Takes effect on the next reload.");
+            }
+
+            override_bool(ui, "Fast boot", &mut overrides.fast_boot, global_fast_boot);
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Runs the bootrom without waiting between frames, keeping the state it leaves behind.");
+            }
+
+            override_bool(ui, "Pause emulator on startup", &mut overrides.pause_emulator_on_startup, global_pause);
+            override_f32(ui, "Screen gamma", &mut overrides.screen_gamma, global_gamma);
+            override_f32(ui, "Screen contrast", &mut overrides.screen_contrast, global_contrast);
+
+            let mut enabled = overrides.emulation_batch_size.is_some();
+
+            if ui.checkbox("##override Instructions per batch", &mut enabled) {
+                overrides.emulation_batch_size = if enabled {Some(global_batch_size)} else {None};
+            }
+
+            ui.same_line();
+
+            if let Some(value) = overrides.emulation_batch_size.as_mut() {
+                Slider::new("Instructions per batch", 1, 512).build(ui, value);
+            }
+            else {
+                ui.text_disabled("Instructions per batch");
+            }
+
+            let mut enabled = overrides.model.is_some();
+
+            if ui.checkbox("##override Model", &mut enabled) {
+                overrides.model = if enabled {Some(global_model)} else {None};
+            }
+
+            ui.same_line();
+
+            if let Some(value) = overrides.model.as_mut() {
+                for model in [Model::Dmg, Model::Mgb, Model::Cgb].iter() {
+                    if ui.radio_button_bool(model.to_string(), *value == *model) {
+                        *value = *model;
+                    }
+
+                    ui.same_line();
+                }
+
+                ui.text("Model");
+            }
+            else {
+                ui.text_disabled("Model");
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Takes effect on the next reload.");
+            }
+
+            let mut enabled = overrides.compat_palette.is_some();
+
+            if ui.checkbox("##override CGB colors for DMG games", &mut enabled) {
+                overrides.compat_palette = if enabled {Some(global_palette)} else {None};
+            }
+
+            ui.same_line();
+
+            if let Some(value) = overrides.compat_palette.as_mut() {
+                ui.set_next_item_width(200.0);
+
+                if let Some(_token) = ui.begin_combo("CGB colors for DMG games", value.name()) {
+                    if ui.selectable_config("Auto").selected(*value == CompatPaletteChoice::Auto).build() {
+                        *value = CompatPaletteChoice::Auto;
+                    }
+
+                    for (idx, palette) in MANUAL_PALETTES.iter().enumerate() {
+                        let choice = CompatPaletteChoice::Manual(idx);
+
+                        if ui.selectable_config(palette.name).selected(*value == choice).build() {
+                            *value = choice;
+                        }
+                    }
+                }
+            }
+            else {
+                ui.text_disabled("CGB colors for DMG games");
+            }
+
+            if overrides.keybinds.is_some() {
+                ui.text_disabled("Custom keybinds are set in this game's overrides file.");
+            }
+
+            ui.separator();
+
+            if ui.button("Save") {
+                app_state.config.overrides.save(app_state.rom_hash);
+                app_state.emu_set_batch_size(app_state.config.emulation_batch_size());
+                app_state.emu_set_fast_boot(app_state.config.fast_boot());
+                app_state.game_settings_opened = false;
+            }
+
+            ui.same_line();
+
+            if ui.button("Cancel") {
+                app_state.config.overrides = GameOverrides::load(app_state.rom_hash);
+                app_state.game_settings_opened = false;
+            }
+        };
+
+        ui.open_popup("Per-game Settings");
+    }
+}
+
+// Checkbox to toggle the override, followed by the actual value.
+fn override_bool(ui: &Ui, label: &str, value: &mut Option<bool>, global: bool) {
+    let mut enabled = value.is_some();
+
+    if ui.checkbox(format!("##override {}", label), &mut enabled) {
+        *value = if enabled {Some(global)} else {None};
+    }
+
+    ui.same_line();
+
+    if let Some(value) = value.as_mut() {
+        ui.checkbox(label, value);
+    }
+    else {
+        ui.text_disabled(label);
+    }
+}
+
+fn override_f32(ui: &Ui, label: &str, value: &mut Option<f32>, global: f32) {
+    let mut enabled = value.is_some();
+
+    if ui.checkbox(format!("##override {}", label), &mut enabled) {
+        *value = if enabled {Some(global)} else {None};
+    }
+
+    ui.same_line();
+
+    if let Some(value) = value.as_mut() {
+        Slider::new(label, 0.2, 3.0).build(ui, value);
+    }
+    else {
+        ui.text_disabled(label);
+    }
+}
@@ -0,0 +1,59 @@
+use imgui::*;
+
+use crate::log::{self, Level};
+
+pub struct LogWindow {
+    show_info: bool,
+    show_warnings: bool,
+    show_errors: bool
+}
+
+impl LogWindow {
+    pub fn init() -> LogWindow {
+        LogWindow {
+            show_info: true,
+            show_warnings: true,
+            show_errors: true
+        }
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("Log").size([475.0, 220.0], Condition::FirstUseEver).opened(opened).build(|| {
+            ui.checkbox("Info", &mut self.show_info);
+            ui.same_line();
+            ui.checkbox("Warnings", &mut self.show_warnings);
+            ui.same_line();
+            ui.checkbox("Errors", &mut self.show_errors);
+            ui.same_line();
+
+            if ui.button("Clear") {
+                log::clear();
+            }
+
+            ui.separator();
+
+            ui.child_window("log_lines").build(|| {
+                let follow = ui.scroll_y() >= ui.scroll_max_y();
+
+                for entry in log::entries() {
+                    let line = format!("[{}] {}", entry.title, entry.message);
+
+                    match entry.level {
+                        Level::Info if self.show_info => ui.text(line),
+                        Level::Warning if self.show_warnings => ui.text_colored([1.0, 0.8, 0.0, 1.0], line),
+                        Level::Error if self.show_errors => ui.text_colored([1.0, 0.3, 0.3, 1.0], line),
+                        _ => {}
+                    }
+                }
+
+                if follow {
+                    ui.set_scroll_here_y_with_ratio(1.0);
+                }
+            });
+        });
+    }
+}
@@ -1,15 +1,61 @@
+use std::ops::RangeInclusive;
 use std::sync::{Arc, RwLock};
 
 use imgui::*;
 
 use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::memory::regions::*;
+use crate::log;
+
+use super::Navigation;
+
+struct Region {
+    name: &'static str,
+    range: RangeInclusive<u16>,
+    color: [f32; 4]
+}
+
+// Used to color the address column, every address falls into one of these.
+static REGIONS: [Region; 11] = [
+    Region { name: "ROM0", range: CARTRIDGE_ROM_BANK0, color: [0.4, 0.6, 1.0, 1.0] },
+    Region { name: "ROMX", range: CARTRIDGE_ROM_BANKX, color: [0.4, 0.8, 1.0, 1.0] },
+    Region { name: "VRAM", range: VRAM, color: [0.4, 1.0, 0.4, 1.0] },
+    Region { name: "CRAM", range: CARTRIDGE_RAM, color: [1.0, 0.8, 0.3, 1.0] },
+    Region { name: "WRAM", range: WRAM, color: [1.0, 1.0, 1.0, 1.0] },
+    Region { name: "ECHO", range: ECHO, color: [0.6, 0.6, 0.6, 1.0] },
+    Region { name: "OAM", range: OAM, color: [1.0, 0.5, 1.0, 1.0] },
+    Region { name: "UNUSED", range: 0xFEA0..=0xFEFF, color: [0.4, 0.4, 0.4, 1.0] },
+    Region { name: "IO", range: IO, color: [1.0, 0.4, 0.4, 1.0] },
+    Region { name: "HRAM", range: HRAM, color: [0.8, 0.6, 1.0, 1.0] },
+    Region { name: "IE", range: 0xFFFF..=0xFFFF, color: [1.0, 0.6, 0.6, 1.0] }
+];
+
+fn get_region(address: u16) -> &'static Region {
+    REGIONS.iter().find(|region| region.range.contains(&address)).unwrap_or(&REGIONS[0])
+}
 
 pub struct MemoryWindow {
     gb_mem: Arc<RwLock<GameboyMemory>>,
 
     editing_byte: bool,
     target_byte_address: u16,
-    target_byte_new_value: String
+    target_byte_new_value: String,
+
+    selected_address: u16,
+    // Set when selected_address was moved from another window.
+    scroll_to_selection: bool,
+
+    // Byte that was right-clicked, for the context menu.
+    context_address: u16,
+    navigation: Option<Navigation>,
+
+    // Inputs of the fill and copy popups, as hex strings.
+    fill_start: String,
+    fill_end: String,
+    fill_value: String,
+    copy_source: String,
+    copy_dest: String,
+    copy_length: String
 }
 
 impl MemoryWindow {
@@ -19,7 +65,181 @@ impl MemoryWindow {
 
             editing_byte: false,
             target_byte_address: 0,
-            target_byte_new_value: String::new()
+            target_byte_new_value: String::new(),
+
+            selected_address: 0,
+            scroll_to_selection: false,
+
+            context_address: 0,
+            navigation: None,
+
+            fill_start: String::new(),
+            fill_end: String::new(),
+            fill_value: String::new(),
+            copy_source: String::new(),
+            copy_dest: String::new(),
+            copy_length: String::new()
+        }
+    }
+
+    /// Returns the address the user asked to see in another window since the last call, if any.
+    pub fn take_navigation(&mut self) -> Option<Navigation> {
+        self.navigation.take()
+    }
+
+    fn draw_context_menu(&mut self, ui: &Ui) {
+        ui.popup("memory_context", || {
+            ui.text_disabled(format!("${:04X}", self.context_address));
+            ui.separator();
+
+            if ui.menu_item("Show in disassembler") {
+                self.navigation = Some(Navigation::Disassembler(self.context_address));
+            }
+
+            // Tile data, tile map entries and sprites all point to a tile.
+            let has_tile = VRAM.contains(&self.context_address) || OAM.contains(&self.context_address);
+
+            if ui.menu_item_config("Show tile in VRAM viewer").enabled(has_tile).build() {
+                self.navigation = Some(Navigation::Tile(self.context_address));
+            }
+        });
+    }
+
+    fn draw_fill_popup(&mut self, ui: &Ui) {
+        ui.popup("memory_fill", || {
+            ui.set_next_item_width(50.0);
+            ui.input_text("Start", &mut self.fill_start).chars_hexadecimal(true).build();
+            ui.set_next_item_width(50.0);
+            ui.input_text("End", &mut self.fill_end).chars_hexadecimal(true).build();
+            ui.set_next_item_width(30.0);
+            ui.input_text("Value", &mut self.fill_value).chars_hexadecimal(true).build();
+
+            if ui.button("Fill") {
+                let start = u16::from_str_radix(&self.fill_start, 16);
+                let end = u16::from_str_radix(&self.fill_end, 16);
+                let value = u8::from_str_radix(&self.fill_value, 16);
+
+                if let (Ok(start), Ok(end), Ok(value)) = (start, end, value) {
+                    let result = {
+                        if let Ok(mut lock) = self.gb_mem.write() {
+                            lock.dbg_fill(start, end, value)
+                        }
+                        else {
+                            Ok(())
+                        }
+                    };
+
+                    match result {
+                        Ok(_) => log::info("Memory Viewer", &format!("Filled ${:04X}-${:04X} with ${:02X}.", start, end, value)),
+                        Err(error) => log::error("Memory Viewer", &error)
+                    }
+
+                    ui.close_current_popup();
+                }
+                else {
+                    log::error("Memory Viewer", "The fill range or value isn't valid hex.");
+                }
+            }
+        });
+    }
+
+    fn draw_copy_popup(&mut self, ui: &Ui) {
+        ui.popup("memory_copy", || {
+            ui.set_next_item_width(50.0);
+            ui.input_text("Source", &mut self.copy_source).chars_hexadecimal(true).build();
+            ui.set_next_item_width(50.0);
+            ui.input_text("Destination", &mut self.copy_dest).chars_hexadecimal(true).build();
+            ui.set_next_item_width(50.0);
+            ui.input_text("Length", &mut self.copy_length).chars_hexadecimal(true).build();
+
+            if ui.button("Copy") {
+                let source = u16::from_str_radix(&self.copy_source, 16);
+                let dest = u16::from_str_radix(&self.copy_dest, 16);
+                let length = u16::from_str_radix(&self.copy_length, 16);
+
+                if let (Ok(source), Ok(dest), Ok(length)) = (source, dest, length) {
+                    let result = {
+                        if let Ok(mut lock) = self.gb_mem.write() {
+                            lock.dbg_copy(source, dest, length)
+                        }
+                        else {
+                            Ok(false)
+                        }
+                    };
+
+                    match result {
+                        Ok(true) => log::warn("Memory Viewer", &format!("Copied ${:04X} bytes to ${:04X}, but the ranges overlap.", length, dest)),
+                        Ok(false) => log::info("Memory Viewer", &format!("Copied ${:04X} bytes from ${:04X} to ${:04X}.", length, source, dest)),
+                        Err(error) => log::error("Memory Viewer", &error)
+                    }
+
+                    ui.close_current_popup();
+                }
+                else {
+                    log::error("Memory Viewer", "The copy addresses or length aren't valid hex.");
+                }
+            }
+        });
+    }
+
+    /// Selects the given address, and scrolls to it on the next draw.
+    pub fn navigate_to(&mut self, address: u16) {
+        self.selected_address = address;
+        self.scroll_to_selection = true;
+    }
+
+    // Handles the navigation keys, returns true if the selected address moved.
+    fn handle_keyboard(&mut self, ui: &Ui, page_size: u16) -> bool {
+        let previous = self.selected_address;
+
+        if ui.is_key_pressed(Key::Home) {
+            self.selected_address = 0;
+        }
+        else if ui.is_key_pressed(Key::End) {
+            self.selected_address = 0xFFFF;
+        }
+        else if ui.is_key_pressed(Key::PageUp) {
+            self.selected_address = self.selected_address.saturating_sub(page_size.saturating_mul(8));
+        }
+        else if ui.is_key_pressed(Key::PageDown) {
+            self.selected_address = self.selected_address.saturating_add(page_size.saturating_mul(8));
+        }
+        else if ui.is_key_pressed(Key::UpArrow) {
+            self.selected_address = self.selected_address.saturating_sub(8);
+        }
+        else if ui.is_key_pressed(Key::DownArrow) {
+            self.selected_address = self.selected_address.saturating_add(8);
+        }
+        else if ui.is_key_pressed(Key::LeftArrow) {
+            self.selected_address = self.selected_address.saturating_sub(1);
+        }
+        else if ui.is_key_pressed(Key::RightArrow) {
+            self.selected_address = self.selected_address.saturating_add(1);
+        }
+
+        previous != self.selected_address
+    }
+
+    // The banked regions also show which bank is mapped in right now.
+    fn region_label(&self, region: &Region) -> String {
+        let bank = {
+            if let Ok(lock) = self.gb_mem.read() {
+                match region.name {
+                    "ROMX" => Some(lock.cartridge().get_selected_rom_bank()),
+                    "CRAM" => Some(lock.cartridge().get_selected_ram_bank()),
+                    _ => None
+                }
+            }
+            else {
+                None
+            }
+        };
+
+        let label = format!("{:<6} ${:04X}-${:04X}", region.name, region.range.start(), region.range.end());
+
+        match bank {
+            Some(bank) => format!("{} (bank {:02X})", label, bank),
+            None => label
         }
     }
 
@@ -28,12 +248,46 @@ impl MemoryWindow {
             return;
         }
 
-        ui.window("Memory Viewer").size([350.0, 170.0], Condition::FirstUseEver).opened(opened).build(|| {
+        ui.window("Memory Viewer").size([350.0, 170.0], Condition::FirstUseEver).opened(opened).menu_bar(true).build(|| {
+            let mut open_fill = false;
+            let mut open_copy = false;
+
+            ui.menu_bar(|| {
+                ui.menu("Edit", || {
+                    open_fill = ui.menu_item("Fill range...");
+                    open_copy = ui.menu_item("Copy range...");
+                });
+
+                ui.menu("Legend", || {
+                    for region in REGIONS.iter() {
+                        ui.text_colored(region.color, self.region_label(region));
+                    }
+                });
+            });
+
+            // Opened out here, popups opened from a menu would belong to it.
+            if open_fill {
+                ui.open_popup("memory_fill");
+            }
+
+            if open_copy {
+                ui.open_popup("memory_copy");
+            }
+
+            self.draw_fill_popup(ui);
+            self.draw_copy_popup(ui);
+
             let style_padding = ui.push_style_var(StyleVar::FramePadding([0.0, 0.0]));
             let style_spacing = ui.push_style_var(StyleVar::ItemSpacing([5.0, 1.0]));
 
             let size = ui.calc_text_size("FF");
-            let mut clipper = ListClipper::new(0xFFFF / 8).items_height(ui.text_line_height() / 2.0).begin(ui);
+            let item_height = ui.text_line_height() / 2.0;
+            let page_size = ((ui.content_region_avail()[1] / ui.text_line_height_with_spacing()) as u16).max(1);
+            let selection_moved = ui.is_window_focused() && !self.editing_byte && self.handle_keyboard(ui, page_size);
+
+            let mut open_context = false;
+
+            let mut clipper = ListClipper::new(0xFFFF / 8).items_height(item_height).begin(ui);
             clipper.step();
 
             for line in clipper.display_start()..clipper.display_end() {
@@ -53,7 +307,16 @@ impl MemoryWindow {
                     current_addr += 1;
                 }
 
-                ui.text(format!("{:04X} |", current_addr - 8));
+                let region = get_region(current_addr - 8);
+
+                ui.text_colored(region.color, format!("{:04X}", current_addr - 8));
+
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(self.region_label(region));
+                }
+
+                ui.same_line();
+                ui.text("|");
 
                 ui.same_line();
 
@@ -85,12 +348,18 @@ impl MemoryWindow {
                             self.target_byte_new_value = String::new();
                         }
                     }
-                    else if ui.selectable_config(&ImString::from(format!("{:02X}", value))).allow_double_click(true).size(size).build() {
+                    else if ui.selectable_config(&ImString::from(format!("{:02X}", value))).selected(self.selected_address == value_address).allow_double_click(true).size(size).build() {
+                        self.selected_address = value_address;
                         self.editing_byte = true;
                         self.target_byte_address = (current_addr - 8) + idx as u16;
                         self.target_byte_new_value = format!("{:02X}", value);
                     }
 
+                    if ui.is_item_clicked_with_button(MouseButton::Right) {
+                        self.context_address = value_address;
+                        open_context = true;
+                    }
+
                     token.pop();
                     ui.same_line();
                 }
@@ -114,6 +383,27 @@ impl MemoryWindow {
 
             clipper.end();
 
+            if open_context {
+                ui.open_popup("memory_context");
+            }
+
+            self.draw_context_menu(ui);
+
+            // Keep the selected row on screen when moving it with the keyboard.
+            if selection_moved || self.scroll_to_selection {
+                self.scroll_to_selection = false;
+
+                let top = ui.scroll_y() / item_height;
+                let selected = (self.selected_address / 8) as f32;
+
+                if selected < top {
+                    ui.set_scroll_y(selected * item_height);
+                }
+                else if selected >= top + page_size as f32 {
+                    ui.set_scroll_y((selected - page_size as f32 + 1.0) * item_height);
+                }
+            }
+
             style_padding.pop();
             style_spacing.pop();
         });
@@ -1,10 +1,25 @@
 pub mod cart_info;
+pub mod cart_ram;
 pub mod cpu_debugger;
+pub mod debug_console;
 pub mod disassembler;
 pub mod file_picker;
+pub mod freeze;
+pub mod game_settings;
+pub mod log_viewer;
 pub mod memory_viewer;
 pub mod notification;
 pub mod screen;
 pub mod serial_output;
 pub mod settings;
+pub mod state_diff;
 pub mod vram_viewer;
+pub mod watch;
+
+// Where an address picked in one window should be shown.
+pub enum Navigation {
+    Disassembler(u16),
+    Memory(u16),
+    // A tile's data, a tile map entry or an OAM entry, see VramViewerWindow::select_tile().
+    Tile(u16)
+}
@@ -1,13 +1,15 @@
 use imgui::*;
 
+use crate::log::{Level, LogEntry};
+
 const FADE_DURATION: f64 = 0.5;
 const NOTIFICATION_OPACITY: f64 = 0.7;
-const NOTIFICATION_SHOW_DURATION: f64 = 3.5;
-const NOTIFICATION_TOTAL_DURATION: f64 = FADE_DURATION + NOTIFICATION_SHOW_DURATION + FADE_DURATION;
 
 pub struct Notification {
     title: ImString,
     content: ImString,
+    thumbnail: Option<TextureId>,
+    level: Level,
     
     created_at: f64
 }
@@ -17,13 +19,46 @@ impl Notification {
         Notification {
             title,
             content,
+            thumbnail: None,
+            level: Level::Info,
 
             created_at
         }
     }
 
-    pub fn draw(&mut self, ui: &Ui, idx: usize) -> bool {
+    pub fn from_entry(entry: &LogEntry, created_at: f64) -> Notification {
+        let mut notification = Notification::init(ImString::new(&entry.title), ImString::new(&entry.message), created_at);
+
+        notification.level = entry.level;
+        notification
+    }
+
+    pub fn with_thumbnail(mut self, thumbnail: TextureId) -> Notification {
+        self.thumbnail = Some(thumbnail);
+        self
+    }
+
+    pub fn thumbnail(&self) -> Option<TextureId> {
+        self.thumbnail
+    }
+
+    // Problems stay on screen for longer, so they don't go unnoticed.
+    fn show_duration(&self) -> f64 {
+        match self.level {
+            Level::Info => 3.5,
+            Level::Warning => 5.0,
+            Level::Error => 8.0
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        if self.thumbnail.is_some() {137.0} else {55.0}
+    }
+
+    pub fn draw(&mut self, ui: &Ui, idx: usize, offset: f32) -> bool {
         let elapsed = ui.time() - self.created_at;
+        let show_duration = self.show_duration();
+        let total_duration = FADE_DURATION + show_duration + FADE_DURATION;
         let mut flags = WindowFlags::empty();
 
         flags.set(WindowFlags::NO_MOVE, true);
@@ -33,8 +68,8 @@ impl Notification {
             if elapsed <= FADE_DURATION {
                 (elapsed / FADE_DURATION) * NOTIFICATION_OPACITY
             }
-            else if elapsed > NOTIFICATION_SHOW_DURATION + FADE_DURATION {
-                (1.0 + ((NOTIFICATION_TOTAL_DURATION - FADE_DURATION - elapsed) / FADE_DURATION)) * NOTIFICATION_OPACITY
+            else if elapsed > show_duration + FADE_DURATION {
+                (1.0 + ((total_duration - FADE_DURATION - elapsed) / FADE_DURATION)) * NOTIFICATION_OPACITY
             }
             else {
                 1.0 * NOTIFICATION_OPACITY
@@ -46,17 +81,26 @@ impl Notification {
         let window = ui.window(&title)
             .flags(flags)    
             .bg_alpha(opacity as f32)
-            .size([400.0, 55.0], Condition::Always)
-            .position([1280.0, 768.0 - (60.0 * idx as f32)], Condition::Always)
+            .size([400.0, self.height()], Condition::Always)
+            .position([1280.0, 768.0 - offset], Condition::Always)
             .position_pivot([1.0, 1.0])
         ;
 
         window.build(|| {
-            ui.text(&self.title);
+            match self.level {
+                Level::Info => ui.text(&self.title),
+                Level::Warning => ui.text_colored([1.0, 0.8, 0.0, 1.0], &self.title),
+                Level::Error => ui.text_colored([1.0, 0.3, 0.3, 1.0], &self.title)
+            }
+
             ui.separator();
             ui.text_wrapped(&self.content);
+
+            if let Some(thumbnail) = self.thumbnail {
+                Image::new(thumbnail, [80.0, 72.0]).build(ui);
+            }
         });
 
-        elapsed > NOTIFICATION_TOTAL_DURATION
+        elapsed > total_duration
     }
 }
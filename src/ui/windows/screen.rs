@@ -5,8 +5,7 @@ use imgui_glium_renderer::Texture;
 
 use glium::Display;
 
-use crate::gameboy::Gameboy;
-use crate::gameboy::JoypadHandler;
+use crate::gameboy::{Gameboy, JoypadHandler, Model};
 use crate::gameboy::ppu::utils::GameboyTexture;
 
 use crate::ui::AppConfig;
@@ -14,27 +13,69 @@ use crate::ui::AppConfig;
 const SCREEN_WIDTH: usize = 160;
 const SCREEN_HEIGHT: usize = 144;
 
+const MIN_SCALE: f32 = 1.0;
+const MAX_SCALE: f32 = 8.0;
+
+// Size in pixels of one cell of the input overlay.
+const OVERLAY_CELL: f32 = 8.0;
+// Where each button goes in the overlay, in cells, same order as JoypadHandler::get_pressed.
+// The D-pad on the left, A and B on the right, Select and Start below them.
+const OVERLAY_LAYOUT: [([f32; 2], [f32; 2]); 8] = [
+    ([1.0, 0.0], [1.0, 1.0]),
+    ([1.0, 2.0], [1.0, 1.0]),
+    ([0.0, 1.0], [1.0, 1.0]),
+    ([2.0, 1.0], [1.0, 1.0]),
+
+    ([6.0, 0.5], [1.0, 1.0]),
+    ([4.5, 1.0], [1.0, 1.0]),
+    ([3.5, 2.5], [1.5, 0.5]),
+    ([5.5, 2.5], [1.5, 0.5])
+];
+
 pub struct ScreenWindow {
     screen: GameboyTexture,
 
     gb_joy: Arc<RwLock<JoypadHandler>>,
     screen_data: Arc<RwLock<Vec<u8>>>,
+    // Used to color the screen when emulating a CGB.
+    screen_layers: Arc<RwLock<Vec<u8>>>,
+    compat_title_key: Option<(u8, u8)>,
+    // Last picture sent to the texture, blended into the next one for LCD ghosting.
+    previous_frame: Vec<u8>
 }
 
 impl ScreenWindow {
     pub fn init(gb: Arc<RwLock<Gameboy>>) -> ScreenWindow {
         let gb_joy = gb.read().unwrap().ui_get_joypad_handler();
         let screen_data = gb.read().unwrap().ui_get_screen_data();
+        let screen_layers = gb.read().unwrap().ui_get_screen_layers();
+        let compat_title_key = gb.read().unwrap().ui_get_header().compat_title_key();
 
         ScreenWindow {
             screen: GameboyTexture::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32),
 
             gb_joy,
-            screen_data
+            screen_data,
+            screen_layers,
+            compat_title_key,
+            previous_frame: Vec::new()
+        }
+    }
+
+    // Shows a fixed test pattern instead of a game, to check the display path on its own.
+    pub fn init_test_pattern() -> ScreenWindow {
+        ScreenWindow {
+            screen: GameboyTexture::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32),
+
+            gb_joy: Arc::new(RwLock::new(JoypadHandler::default())),
+            screen_data: Arc::new(RwLock::new(create_test_pattern())),
+            screen_layers: Arc::new(RwLock::new(vec![0; SCREEN_WIDTH * SCREEN_HEIGHT])),
+            compat_title_key: None,
+            previous_frame: Vec::new()
         }
     }
 
-    pub fn draw(&mut self, config: &mut AppConfig, ui: &Ui, opened: &mut bool, display: &Display, textures: &mut Textures<Texture>) -> bool {
+    pub fn draw(&mut self, config: &mut AppConfig, ui: &Ui, opened: &mut bool, prompt: Option<&str>, fast_forwarding: bool, display: &Display, textures: &mut Textures<Texture>) -> bool {
         if !*opened {
             return true;
         }
@@ -43,46 +84,125 @@ impl ScreenWindow {
 
         ui.window("Screen").size(config.screen_size, Condition::Always).opened(opened).build(|| {
             let window_size = ui.content_region_avail();
+            // Space taken by the title bar and padding, needed to size the window for a given scale.
+            let decoration = [ui.window_size()[0] - window_size[0], ui.window_size()[1] - window_size[1]];
+            let mut resized = false;
 
             let x_scale = window_size[0] / SCREEN_WIDTH as f32;
             let y_scale = window_size[1] / SCREEN_HEIGHT as f32;
 
             focused = ui.is_window_focused();
 
+            // A CGB running a DMG game colors it, depending on the layer each pixel came from.
+            let palette = if config.model() == Model::Cgb {config.compat_palette().resolve(self.compat_title_key)} else {None};
+
             if let Ok(lock) = self.screen_data.try_read() {
+                let shades = create_shade_table(config.screen_gamma(), config.screen_contrast());
                 let mut data: Vec<u8> = Vec::with_capacity((SCREEN_WIDTH * SCREEN_HEIGHT) * 3);
 
-                for b in lock.iter() {                        
-                    data.push(*b);
-                    data.push(*b);
-                    data.push(*b);
+                match (palette, self.screen_layers.try_read()) {
+                    (Some(palette), Ok(layers)) => {
+                        for (b, layer) in lock.iter().zip(layers.iter()) {
+                            for channel in palette.color(*layer, *b).iter() {
+                                data.push(shades[*channel as usize]);
+                            }
+                        }
+                    }
+                    _ => {
+                        for b in lock.iter() {
+                            let b = shades[*b as usize];
+
+                            data.push(b);
+                            data.push(b);
+                            data.push(b);
+                        }
+                    }
+                }
+
+                let ghosting = config.screen_ghosting.clamp(0.0, 0.9);
+
+                // The previous frame already holds a bit of the ones before it, so moving
+                // objects leave a fading trail and flickering sprites look see-through.
+                if ghosting > 0.0 && self.previous_frame.len() == data.len() {
+                    for (new, old) in data.iter_mut().zip(self.previous_frame.iter()) {
+                        *new = (*new as f32 * (1.0 - ghosting) + *old as f32 * ghosting).round() as u8;
+                    }
                 }
 
+                self.previous_frame.clear();
+                self.previous_frame.extend_from_slice(&data);
                 self.screen.update_texture(data, display, textures);
             }
 
             if let Some(id) = self.screen.id().as_ref() {
-                let w = SCREEN_WIDTH as f32 * x_scale;
-                let h = SCREEN_HEIGHT as f32 * y_scale;
+                let (w, h) = {
+                    if config.screen_integer_scale {
+                        // Largest whole scale that fits, centered in the window.
+                        let scale = x_scale.min(y_scale).floor().clamp(MIN_SCALE, MAX_SCALE);
+                        let w = SCREEN_WIDTH as f32 * scale;
+                        let h = SCREEN_HEIGHT as f32 * scale;
+                        let cursor = ui.cursor_pos();
+
+                        ui.set_cursor_pos([cursor[0] + ((window_size[0] - w) / 2.0).max(0.0), cursor[1] + ((window_size[1] - h) / 2.0).max(0.0)]);
+                        (w, h)
+                    }
+                    else {
+                        (SCREEN_WIDTH as f32 * x_scale, SCREEN_HEIGHT as f32 * y_scale)
+                    }
+                };
+
+                Image::new(*id, [w, h]).build(ui);
 
-                Image::new(*id, [w as f32, h as f32]).build(ui);
+                if config.screen_input_overlay {
+                    self.draw_input_overlay(ui);
+                }
+
+                if fast_forwarding {
+                    draw_fast_forward_status(ui);
+                }
+
+                if let Some(prompt) = prompt {
+                    draw_prompt(ui, prompt);
+                }
+
+                if ui.is_item_clicked_with_button(MouseButton::Right) {
+                    ui.open_popup("Screen scale");
+                }
             }
 
+            ui.popup("Screen scale", || {
+                ui.checkbox("Integer scaling", &mut config.screen_integer_scale);
+                ui.checkbox("Show pressed buttons", &mut config.screen_input_overlay);
+                ui.separator();
+
+                for scale in MIN_SCALE as u32..=MAX_SCALE as u32 {
+                    if ui.menu_item(format!("{}x", scale)) {
+                        config.screen_size = [
+                            SCREEN_WIDTH as f32 * scale as f32 + decoration[0],
+                            SCREEN_HEIGHT as f32 * scale as f32 + decoration[1]
+                        ];
+
+                        resized = true;
+                        config.save();
+                    }
+                }
+            });
+
             if ui.is_window_focused() {
                 if let Ok(mut lock) = self.gb_joy.write() {
-                    lock.set_a_state(ui.io().keys_down[config.keybinds.gb_a as usize]);
-                    lock.set_b_state(ui.io().keys_down[config.keybinds.gb_b as usize]);
-                    lock.set_start_state(ui.io().keys_down[config.keybinds.gb_start as usize]);
-                    lock.set_select_state(ui.io().keys_down[config.keybinds.gb_select as usize]);
-
-                    lock.set_up_state(ui.io().keys_down[config.keybinds.gb_up as usize]);
-                    lock.set_down_state(ui.io().keys_down[config.keybinds.gb_down as usize]);
-                    lock.set_left_state(ui.io().keys_down[config.keybinds.gb_left as usize]);
-                    lock.set_right_state(ui.io().keys_down[config.keybinds.gb_right as usize]);
+                    lock.set_a_state(ui.io().keys_down[config.keybinds().gb_a as usize]);
+                    lock.set_b_state(ui.io().keys_down[config.keybinds().gb_b as usize]);
+                    lock.set_start_state(ui.io().keys_down[config.keybinds().gb_start as usize]);
+                    lock.set_select_state(ui.io().keys_down[config.keybinds().gb_select as usize]);
+
+                    lock.set_up_state(ui.io().keys_down[config.keybinds().gb_up as usize]);
+                    lock.set_down_state(ui.io().keys_down[config.keybinds().gb_down as usize]);
+                    lock.set_left_state(ui.io().keys_down[config.keybinds().gb_left as usize]);
+                    lock.set_right_state(ui.io().keys_down[config.keybinds().gb_right as usize]);
                 }
             }
 
-            if !config.screen_size.eq(&ui.window_size()) {
+            if !resized && !config.screen_size.eq(&ui.window_size()) {
                 config.screen_size = ui.window_size();
                 config.save()
             }
@@ -90,4 +210,98 @@ impl ScreenWindow {
 
         focused
     }
+
+    // Draws the buttons on the bottom left corner of the last item, which should be the screen image.
+    fn draw_input_overlay(&self, ui: &Ui) {
+        let pressed = {
+            if let Ok(lock) = self.gb_joy.read() {
+                lock.get_pressed()
+            }
+            else {
+                return;
+            }
+        };
+
+        let draw_list = ui.get_window_draw_list();
+        let origin = [ui.item_rect_min()[0] + 4.0, ui.item_rect_max()[1] - OVERLAY_CELL * 3.0 - 4.0];
+
+        for ((position, size), pressed) in OVERLAY_LAYOUT.iter().zip(pressed.iter()) {
+            let start = [origin[0] + position[0] * OVERLAY_CELL, origin[1] + position[1] * OVERLAY_CELL];
+            let end = [start[0] + size[0] * OVERLAY_CELL - 1.0, start[1] + size[1] * OVERLAY_CELL - 1.0];
+            let color = if *pressed {[1.0, 0.2, 0.2, 0.9]} else {[0.3, 0.3, 0.3, 0.6]};
+
+            draw_list.add_rect(start, end, color).filled(true).build();
+        }
+    }
+}
+
+// Draws the text centered on the last item, which should be the screen image.
+fn draw_prompt(ui: &Ui, text: &str) {
+    let draw_list = ui.get_window_draw_list();
+    let (min, max) = (ui.item_rect_min(), ui.item_rect_max());
+    let size = ui.calc_text_size(text);
+
+    let start = [(min[0] + max[0] - size[0]) / 2.0, (min[1] + max[1] - size[1]) / 2.0];
+    let end = [start[0] + size[0], start[1] + size[1]];
+
+    draw_list.add_rect([start[0] - 6.0, start[1] - 4.0], [end[0] + 6.0, end[1] + 4.0], [0.0, 0.0, 0.0, 0.75]).filled(true).build();
+    draw_list.add_text(start, [1.0, 1.0, 1.0, 1.0], text);
+}
+
+// Draws a fast-forward marker on the top right corner of the last item, which should be the screen image.
+fn draw_fast_forward_status(ui: &Ui) {
+    let draw_list = ui.get_window_draw_list();
+    let text = ">>";
+    let size = ui.calc_text_size(text);
+
+    let start = [ui.item_rect_max()[0] - size[0] - 6.0, ui.item_rect_min()[1] + 4.0];
+    let end = [start[0] + size[0], start[1] + size[1]];
+
+    draw_list.add_rect([start[0] - 2.0, start[1] - 1.0], [end[0] + 2.0, end[1] + 1.0], [0.0, 0.0, 0.0, 0.6]).filled(true).build();
+    draw_list.add_text(start, [1.0, 1.0, 1.0, 1.0], text);
+}
+
+// Remaps the grayscale values coming from the PPU using the contrast and gamma settings.
+// Contrast stretches the shades around the middle gray, then gamma curves the result.
+fn create_shade_table(gamma: f32, contrast: f32) -> [u8; 256] {
+    let mut table = [0; 256];
+    let gamma = if gamma > 0.0 {gamma} else {1.0};
+
+    for (value, shade) in table.iter_mut().enumerate() {
+        let normalized = value as f32 / 255.0;
+        let contrasted = ((normalized - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+
+        *shade = (contrasted.powf(1.0 / gamma) * 255.0).round() as u8;
+    }
+
+    table
+}
+
+// The four DMG shades as bars on top, a full gradient in the middle, and a checkerboard at the bottom.
+fn create_test_pattern() -> Vec<u8> {
+    let shades = [255, 192, 96, 0];
+    let mut data = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT);
+
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let value = {
+                if y < 48 {
+                    shades[x / 40]
+                }
+                else if y < 96 {
+                    255 - (x * 255 / (SCREEN_WIDTH - 1)) as u8
+                }
+                else if ((x / 8) + (y / 8)) % 2 == 0 {
+                    255
+                }
+                else {
+                    0
+                }
+            };
+
+            data.push(value);
+        }
+    }
+
+    data
 }
@@ -2,54 +2,178 @@ use std::sync::{Arc, RwLock};
 
 use imgui::*;
 
+use crate::log;
 use crate::gameboy::Gameboy;
+use crate::gameboy::memory::serial::{SerialLine, SerialLog, TcpLink};
+
+use crate::ui::AppConfig;
 
 pub struct SerialWindow {
-    gb_serial: Arc<RwLock<Vec<u8>>>,
-    serial_show_lines_as_hex: bool
+    gb: Arc<RwLock<Gameboy>>,
+    gb_serial: Arc<RwLock<SerialLog>>,
+    serial_show_lines_as_hex: bool,
+    serial_show_timestamps: bool,
+    serial_wrap_lines: bool,
+
+    scrollback: i32
 }
 
 impl SerialWindow {
     pub fn init(gb: Arc<RwLock<Gameboy>>) -> SerialWindow {
         let gb_serial = gb.read().unwrap().ui_get_serial_output();
-        
+        let scrollback = gb_serial.read().map(|lock| lock.max_lines() as i32).unwrap_or(0);
+
         SerialWindow {
+            gb,
             gb_serial,
-            serial_show_lines_as_hex: false
+            serial_show_lines_as_hex: false,
+            serial_show_timestamps: false,
+            serial_wrap_lines: true,
+
+            scrollback
+        }
+    }
+
+    fn format_line(&self, line: &SerialLine) -> String {
+        let mut output = String::new();
+
+        if self.serial_show_timestamps {
+            output.push_str(&format!("[{:>12}] ", line.cycles()));
+        }
+
+        if self.serial_show_lines_as_hex {
+            let bytes: Vec<String> = line.data().iter().map(|b| format!("{:02X}", b)).collect();
+            output.push_str(&bytes.join(" "));
+        }
+        else {
+            // Control characters would only mess up the console, so they are replaced.
+            for b in line.data() {
+                let c = *b as char;
+                output.push(if c.is_ascii_graphic() || c == ' ' {c} else {'.'});
+            }
+        }
+
+        output
+    }
+
+    fn set_link(&self, link: Result<TcpLink, String>) {
+        match link {
+            Ok(link) => {
+                if let Ok(mut lock) = self.gb.write() {
+                    lock.set_serial_link(Some(Box::new(link)));
+                }
+            }
+            Err(error) => log::error("Link cable", &format!("Couldn't set up the link ({}).", error))
+        }
+    }
+
+    fn draw_link_settings(&self, ui: &Ui, config: &mut AppConfig) {
+        let status = {
+            if let Ok(lock) = self.gb.read() {
+                lock.serial_link_status()
+            }
+            else {
+                String::new()
+            }
+        };
+
+        let mut port = config.link_port as i32;
+
+        ui.set_next_item_width(120.0);
+        ui.input_text("Host", &mut config.link_host).build();
+        ui.same_line();
+        ui.set_next_item_width(100.0);
+
+        if ui.input_int("Port", &mut port).build() {
+            config.link_port = port.clamp(1, u16::MAX as i32) as u16;
+        }
+
+        if ui.button("Listen") {
+            self.set_link(TcpLink::listen(config.link_port));
+            config.save();
+        }
+
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Waits for the other emulator to connect on the port above.");
+        }
+
+        ui.same_line();
+
+        if ui.button("Connect") {
+            self.set_link(TcpLink::connect(&config.link_host, config.link_port));
+            config.save();
+        }
+
+        ui.same_line();
+
+        if ui.button("Disconnect") {
+            if let Ok(mut lock) = self.gb.write() {
+                lock.set_serial_link(None);
+            }
         }
+
+        ui.same_line();
+        ui.text_disabled(status);
     }
 
-    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) {
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool, config: &mut AppConfig) {
         if !*opened {
             return;
         }
-        
-        ui.window("Serial Output").size([475.0, 170.0], Condition::FirstUseEver).opened(opened).build(|| {
-            if let Ok(lock) = self.gb_serial.read() {
-                let mut output = String::new();
-
-                ListBox::new("").size([420.0, 110.0]).build(ui, || {
-                    if self.serial_show_lines_as_hex {
-                        for b in lock.iter() {
-                            if *b == 0x0A {
-                                output.push('\n');
-                            }
-                            else {
-                                output.push(*b as char);
-                            }
-                        }
-                    }
-                    else {
-                        output = String::from_utf8_lossy(&lock).to_string();
-                    }
 
-                    for line in output.lines() {
-                        ui.selectable(&ImString::from(line.to_string()));
-                    }
-                });
+        ui.window("Serial Output").size([475.0, 220.0], Condition::FirstUseEver).opened(opened).build(|| {
+            if CollapsingHeader::new("Link cable").build(ui) {
+                self.draw_link_settings(ui, config);
+                ui.separator();
+            }
 
-                ui.checkbox("Show lines as hex", &mut self.serial_show_lines_as_hex);
+            ui.checkbox("Show lines as hex", &mut self.serial_show_lines_as_hex);
+            ui.same_line();
+            ui.checkbox("Timestamps", &mut self.serial_show_timestamps);
+            ui.same_line();
+            ui.checkbox("Wrap lines", &mut self.serial_wrap_lines);
+
+            ui.set_next_item_width(100.0);
+
+            if ui.input_int("Scrollback (lines)", &mut self.scrollback).build() {
+                self.scrollback = self.scrollback.max(1);
+
+                if let Ok(mut lock) = self.gb_serial.write() {
+                    lock.set_max_lines(self.scrollback as usize);
+                }
             }
+
+            ui.same_line();
+
+            if ui.button("Clear") {
+                if let Ok(mut lock) = self.gb_serial.write() {
+                    lock.clear();
+                }
+            }
+
+            ui.separator();
+
+            ui.child_window("serial_lines").horizontal_scrollbar(!self.serial_wrap_lines).build(|| {
+                // Follow new output, unless the user scrolled up to read something.
+                let follow = ui.scroll_y() >= ui.scroll_max_y();
+
+                if let Ok(lock) = self.gb_serial.read() {
+                    for line in lock.lines() {
+                        let line = self.format_line(line);
+
+                        if self.serial_wrap_lines {
+                            ui.text_wrapped(line);
+                        }
+                        else {
+                            ui.text(line);
+                        }
+                    }
+                }
+
+                if follow {
+                    ui.set_scroll_here_y_with_ratio(1.0);
+                }
+            });
         });
     }
 }
@@ -1,6 +1,10 @@
 use imgui::*;
 
-use crate::ui::{AppConfig, AppState};
+use crate::gameboy::{Model, DMG_CLOCK_HZ, SGB_CLOCK_HZ};
+use crate::gameboy::memory::PowerOnState;
+use crate::gameboy::ppu::Renderer;
+use crate::gameboy::ppu::compat_palettes::{CompatPaletteChoice, MANUAL_PALETTES};
+use crate::ui::{AccuracyProfile, AppConfig, AppState, FastForwardMode, WindowSet};
 
 pub struct SettingsWindow;
 
@@ -14,9 +18,256 @@ impl SettingsWindow {
             TabBar::new("Settings Tabs").build(ui, || {
                 TabItem::new("General").build(ui, || {
                     ui.checkbox("Pause emulator on startup", &mut app_state.config.pause_emulator_on_startup);
+
+                    if app_state.config.pause_emulator_on_startup {
+                        ui.indent();
+                        ui.checkbox("Show how to start on the screen", &mut app_state.config.show_startup_prompt);
+                        ui.unindent();
+                    }
+
                     ui.checkbox("Pause emulator on screen focus loss", &mut app_state.config.pause_emulator_on_focus_loss);
 
-                    ui.input_float2("Screen size (Default: 160x144)", &mut app_state.config.screen_size).build();
+                    if ui.input_float2("Screen size (Default: 160x144)", &mut app_state.config.screen_size).build() {
+                        // Anything smaller leaves no room for the picture at all.
+                        app_state.config.screen_size[0] = app_state.config.screen_size[0].max(80.0);
+                        app_state.config.screen_size[1] = app_state.config.screen_size[1].max(72.0);
+                    }
+
+                    ui.checkbox("Integer screen scaling", &mut app_state.config.screen_integer_scale);
+                    ui.checkbox("Show pressed buttons on the screen", &mut app_state.config.screen_input_overlay);
+
+                    Slider::new("Screen gamma (Default: 1.0)", 0.2, 3.0).build(ui, &mut app_state.config.screen_gamma);
+                    Slider::new("Screen contrast (Default: 1.0)", 0.2, 3.0).build(ui, &mut app_state.config.screen_contrast);
+                    Slider::new("LCD ghosting (Default: 0.0)", 0.0, 0.9).build(ui, &mut app_state.config.screen_ghosting);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Blends each frame with the previous one, some games flicker sprites to make them look transparent.");
+                    }
+
+                    ui.text("Accuracy profile (Default: Balanced)");
+
+                    let profiles = [
+                        (AccuracyProfile::Accurate, "Accurate", "Pixel FIFO renderer, 16 instructions per batch so pausing stops sooner."),
+                        (AccuracyProfile::Balanced, "Balanced", "Pixel FIFO renderer, 64 instructions per batch."),
+                        (AccuracyProfile::Fast, "Fast", "Scanline renderer, 256 instructions per batch. Mid-line effects won't show up."),
+                        (AccuracyProfile::Custom, "Custom", "Set when the renderer or the batch size are changed by hand.")
+                    ];
+
+                    for (profile, name, description) in profiles.iter() {
+                        ui.same_line();
+
+                        if ui.radio_button_bool(name, app_state.config.accuracy_profile == *profile) {
+                            app_state.config.set_accuracy_profile(*profile);
+                        }
+
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(description);
+                        }
+                    }
+
+                    ui.text("Renderer (Default: Pixel FIFO)");
+                    ui.same_line();
+
+                    if ui.radio_button_bool("Pixel FIFO", app_state.config.renderer == Renderer::PixelFifo) {
+                        app_state.config.renderer = Renderer::PixelFifo;
+                        app_state.config.accuracy_profile = AccuracyProfile::Custom;
+                    }
+
+                    ui.same_line();
+
+                    if ui.radio_button_bool("Scanline", app_state.config.renderer == Renderer::Scanline) {
+                        app_state.config.renderer = Renderer::Scanline;
+                        app_state.config.accuracy_profile = AccuracyProfile::Custom;
+                    }
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Draws each line at once, games changing scroll or palettes in the middle of a line won't look right.");
+                    }
+
+                    if Slider::new("Instructions per batch (Default: 64)", 1, 512).build(ui, &mut app_state.config.emulation_batch_size) {
+                        app_state.config.accuracy_profile = AccuracyProfile::Custom;
+                    }
+                    Slider::new("Fast-forward frame skip (Default: 4)", 1, 16).build(ui, &mut app_state.config.fast_forward_frame_skip);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Only one in every this many frames is drawn while fast-forwarding.");
+                    }
+
+                    ui.text("Fast-forward key (Default: Tab)");
+                    ui.same_line();
+
+                    if ui.radio_button_bool("Hold", app_state.config.fast_forward_mode == FastForwardMode::Hold) {
+                        app_state.config.fast_forward_mode = FastForwardMode::Hold;
+                    }
+
+                    ui.same_line();
+
+                    if ui.radio_button_bool("Toggle", app_state.config.fast_forward_mode == FastForwardMode::Toggle) {
+                        app_state.config.fast_forward_mode = FastForwardMode::Toggle;
+                    }
+
+                    Slider::new("Recording length in seconds (Default: 30)", 1, 120).build(ui, &mut app_state.config.recording_max_seconds);
+                    Slider::new("Cart RAM auto-save interval in seconds (Default: 30)", 0, 300).build(ui, &mut app_state.config.ram_autosave_interval);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Saves the cart RAM while playing if it changed, 0 disables it.");
+                    }
+
+                    Slider::new("Callstack depth (Default: 256)", 16, 4096).build(ui, &mut app_state.config.callstack_max_depth);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Older calls are dropped from the CPU debugger past this, and a warning gets logged.");
+                    }
+
+                    ui.checkbox("Fast boot", &mut app_state.config.fast_boot);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Runs the bootrom without waiting between frames. Games still start with the same state and timing, unlike skipping it.");
+                    }
+
+                    ui.checkbox("Resume games where they were left", &mut app_state.config.resume_on_startup);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Saves a state when closing the emulator or loading another ROM, and loads it the next time the game starts.");
+                    }
+
+                    ui.checkbox("Copy CPU state dumps to the clipboard", &mut app_state.config.copy_state_dump);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("The dump key (Default: F8) logs the disassembly around PC and the registers, this also copies them.");
+                    }
+
+                    ui.checkbox("Skip unknown opcodes", &mut app_state.config.skip_unknown_opcodes);
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Runs them as NOPs and logs where they were found, instead of stopping the emulator.");
+                    }
+
+                    ui.separator();
+                    ui.text("Model (applied on reload)");
+
+                    for (idx, model) in [Model::Dmg, Model::Mgb, Model::Cgb].iter().enumerate() {
+                        if idx > 0 {
+                            ui.same_line();
+                        }
+
+                        if ui.radio_button_bool(model.to_string(), app_state.config.model == *model) {
+                            app_state.config.model = *model;
+                        }
+                    }
+
+                    let mut clock = app_state.config.cpu_clock_hz as i32;
+
+                    ui.set_next_item_width(120.0);
+
+                    if ui.input_int("CPU clock in Hz", &mut clock).step(1000).build() {
+                        // Anything too slow makes the frame pacing wait for ages.
+                        app_state.config.cpu_clock_hz = clock.clamp(100_000, 100_000_000) as u32;
+                    }
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Only changes how many cycles run per second, timers and the PPU still count cycles.");
+                    }
+
+                    ui.same_line();
+
+                    if ui.small_button("DMG") {
+                        app_state.config.cpu_clock_hz = DMG_CLOCK_HZ;
+                    }
+
+                    ui.same_line();
+
+                    if ui.small_button("SGB") {
+                        app_state.config.cpu_clock_hz = SGB_CLOCK_HZ;
+                    }
+
+                    ui.set_next_item_width(200.0);
+
+                    if let Some(_token) = ui.begin_combo("CGB colors for DMG games", app_state.config.compat_palette.name()) {
+                        if ui.selectable_config("Auto").selected(app_state.config.compat_palette == CompatPaletteChoice::Auto).build() {
+                            app_state.config.compat_palette = CompatPaletteChoice::Auto;
+                        }
+
+                        for (idx, palette) in MANUAL_PALETTES.iter().enumerate() {
+                            let choice = CompatPaletteChoice::Manual(idx);
+
+                            if ui.selectable_config(palette.name).selected(app_state.config.compat_palette == choice).build() {
+                                app_state.config.compat_palette = choice;
+                            }
+                        }
+                    }
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Auto picks the palette by title like the CGB bootrom, games it doesn't know stay in grayscale.");
+                    }
+
+                    ui.separator();
+                    ui.text("Power-on memory state (applied on reload)");
+
+                    let state = &mut app_state.config.power_on_state;
+
+                    if ui.radio_button_bool("Zeroed", *state == PowerOnState::Zeroed) {
+                        *state = PowerOnState::Zeroed;
+                    }
+
+                    ui.same_line();
+
+                    if ui.radio_button_bool("Post-boot IO values", *state == PowerOnState::PostBoot) {
+                        *state = PowerOnState::PostBoot;
+                    }
+
+                    ui.same_line();
+
+                    let mut pattern = if let PowerOnState::Pattern(value) = *state {Some(value as i32)} else {None};
+
+                    if ui.radio_button_bool("RAM pattern", pattern.is_some()) {
+                        *state = PowerOnState::Pattern(0xFF);
+                    }
+
+                    if let Some(value) = pattern.as_mut() {
+                        ui.same_line();
+                        ui.set_next_item_width(80.0);
+
+                        if Slider::new("##power_on_pattern", 0, 0xFF).display_format("$%02X").build(ui, value) {
+                            *state = PowerOnState::Pattern(*value as u8);
+                        }
+                    }
+                });
+
+                TabItem::new("Windows").build(ui, || {
+                    ui.text("Opened after loading a ROM or resetting the layout");
+
+                    if ui.button("Play") {
+                        app_state.config.default_windows = WindowSet::play();
+                    }
+
+                    ui.same_line();
+
+                    if ui.button("Debug") {
+                        app_state.config.default_windows = WindowSet::debug();
+                    }
+
+                    ui.same_line();
+
+                    if ui.button("Default") {
+                        app_state.config.default_windows = WindowSet::default();
+                    }
+
+                    ui.separator();
+
+                    let set = &mut app_state.config.default_windows;
+
+                    ui.checkbox("Screen", &mut set.screen);
+                    ui.checkbox("Cartridge info", &mut set.cart_info);
+                    ui.checkbox("CPU debugger", &mut set.cpu_debugger);
+                    ui.checkbox("Disassembler", &mut set.disassembler);
+                    ui.checkbox("Memory viewer", &mut set.memory_viewer);
+                    ui.checkbox("Cartridge RAM", &mut set.cart_ram);
+                    ui.checkbox("VRAM viewer", &mut set.vram_viewer);
+                    ui.checkbox("Watch", &mut set.watch);
+                    ui.checkbox("Freeze list", &mut set.freeze);
+                    ui.checkbox("Serial output", &mut set.serial);
+                    ui.checkbox("Debug console", &mut set.debug_console);
                 });
 
                 TabItem::new("Keybinds").build(ui, || {
@@ -69,13 +320,23 @@ impl SettingsWindow {
 
             if ui.button("Save") {
                 app_state.config.save();
+                app_state.emu_set_batch_size(app_state.config.emulation_batch_size());
+                app_state.emu_set_ram_autosave_interval(app_state.config.ram_autosave_interval);
+                app_state.emu_set_skip_unknown_opcodes(app_state.config.skip_unknown_opcodes);
+                app_state.emu_set_fast_boot(app_state.config.fast_boot());
+                app_state.emu_set_clock_speed(app_state.config.cpu_clock_hz);
+                app_state.emu_set_renderer(app_state.config.renderer);
+                app_state.emu_set_callstack_max_depth(app_state.config.callstack_max_depth);
                 app_state.settings_opened = false;
             }
 
             ui.same_line();
 
             if ui.button("Cancel") {
+                let overrides = std::mem::take(&mut app_state.config.overrides);
+
                 app_state.config = AppConfig::load();
+                app_state.config.overrides = overrides;
                 app_state.settings_opened = false;
             }
         };
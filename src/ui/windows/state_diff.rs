@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use imgui::*;
+
+use crate::gameboy::state::{StateDiff, StateSnapshot};
+use crate::ui::windows::file_picker::FilePickerWindow;
+
+pub struct StateDiffWindow {
+    start_dir: PathBuf,
+    paths: [Option<PathBuf>; 2],
+
+    // Which of the two states is being picked, if any.
+    picking: Option<usize>,
+    picker: FilePickerWindow,
+
+    result: Option<Result<StateDiff, String>>
+}
+
+impl StateDiffWindow {
+    pub fn init(start_dir: PathBuf) -> StateDiffWindow {
+        StateDiffWindow {
+            picker: FilePickerWindow::init(start_dir.clone()),
+            start_dir,
+            paths: [None, None],
+
+            picking: None,
+
+            result: None
+        }
+    }
+
+    fn compare(first: &Path, second: &Path) -> Result<StateDiff, String> {
+        let first = std::fs::read(first).map_err(|e| format!("Couldn't read {} ({}).", first.display(), e))?;
+        let second = std::fs::read(second).map_err(|e| format!("Couldn't read {} ({}).", second.display(), e))?;
+
+        let first = StateSnapshot::parse(&first)?;
+        let second = StateSnapshot::parse(&second)?;
+
+        Ok(first.diff(&second))
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("State Diff").size([360.0, 400.0], Condition::FirstUseEver).opened(opened).build(|| {
+            for (idx, label) in ["First state", "Second state"].iter().enumerate() {
+                if ui.button(label) {
+                    self.picking = Some(idx);
+                    self.picker = FilePickerWindow::init(self.start_dir.clone());
+                }
+
+                ui.same_line();
+
+                match self.paths[idx].as_ref() {
+                    Some(path) => ui.text(path.display().to_string()),
+                    None => ui.text_disabled("None")
+                }
+            }
+
+            if let Some(idx) = self.picking {
+                if let Some(path) = self.picker.draw(ui) {
+                    self.paths[idx] = Some(path);
+                    self.picking = None;
+                }
+                else if self.picker.cancelled() {
+                    self.picking = None;
+                }
+            }
+
+            let ready = self.paths.iter().all(|p| p.is_some());
+
+            if ui.button("Compare") && ready {
+                if let [Some(first), Some(second)] = &self.paths {
+                    self.result = Some(StateDiffWindow::compare(first, second));
+                }
+            }
+
+            ui.separator();
+
+            match self.result.as_ref() {
+                Some(Ok(diff)) => {
+                    if diff.fields.is_empty() && diff.bytes.is_empty() {
+                        ui.text("The states are identical.");
+                    }
+
+                    for (name, first, second) in diff.fields.iter() {
+                        ui.text(format!("{:<10} {} -> {}", name, first, second));
+                    }
+
+                    if !diff.bytes.is_empty() {
+                        ui.separator();
+                        ui.text(format!("{} bytes differ", diff.bytes.len()));
+
+                        ui.child_window("state_diff_bytes").build(|| {
+                            let mut clipper = ListClipper::new(diff.bytes.len() as i32).begin(ui);
+
+                            while clipper.step() {
+                                for (address, first, second) in &diff.bytes[clipper.display_start() as usize..clipper.display_end() as usize] {
+                                    ui.text(format!("${:04X}: {:02X} -> {:02X}", address, first, second));
+                                }
+                            }
+                        });
+                    }
+                }
+                Some(Err(error)) => ui.text_colored([1.0, 0.0, 0.0, 1.0], error),
+                None => ui.text_disabled("Pick two save states to compare.")
+            }
+        });
+    }
+}
@@ -1,3 +1,5 @@
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
 use imgui::*;
@@ -7,41 +9,309 @@ use glium::Display;
 
 use crate::gameboy::Gameboy;
 use crate::gameboy::memory::GameboyMemory;
+use crate::gameboy::memory::regions::OAM;
 
 use crate::gameboy::ppu::utils;
 use crate::gameboy::ppu::utils::GameboyTexture;
+use crate::log;
+
+use super::file_picker::FilePickerWindow;
+
+// Tiles from $8000 to $97FF.
+const TILE_COUNT: usize = 384;
+
+// Tile sheets are 16 tiles wide, so the $8000, $8800 and $9000 blocks end up
+// one below the other like on the tiles tab.
+const SHEET_TILES_PER_ROW: usize = 16;
+const SHEET_WIDTH: usize = SHEET_TILES_PER_ROW * 8;
+const SHEET_HEIGHT: usize = TILE_COUNT / SHEET_TILES_PER_ROW * 8;
+
+// Where a tile shows up, for highlighting it. Map entries are (x, y) in tiles.
+#[derive(Default)]
+struct TileUsage {
+    maps: [Vec<(usize, usize)>; 2],
+    sprites: Vec<usize>
+}
+
+// Map entries are an unsigned index from $8000, or a signed one from $9000, depending on LCDC.
+fn map_entry_tile(index: u8, lcdc: u8) -> usize {
+    if lcdc & 0x10 != 0 {
+        index as usize
+    }
+    else {
+        (256 + index as i8 as i32) as usize
+    }
+}
+
+fn find_tile_usage(gb_mem: &Arc<RwLock<GameboyMemory>>, tile: usize) -> TileUsage {
+    let mut usage = TileUsage::default();
+
+    if let Ok(lock) = gb_mem.read() {
+        let lcdc = lock.read(0xFF40);
+
+        for (map, positions) in usage.maps.iter_mut().enumerate() {
+            let base = 0x9800 + map as u16 * 0x400;
+
+            for offset in 0..0x400 {
+                if map_entry_tile(lock.read(base + offset), lcdc) == tile {
+                    positions.push((offset as usize % 32, offset as usize / 32));
+                }
+            }
+        }
+
+        for sprite in 0..40 {
+            let index = lock.read(0xFE00 + sprite as u16 * 4 + 2) as usize;
+            // 8x16 sprites take two tiles, ignoring the lowest bit of the index.
+            let used = if lcdc & 0x04 != 0 {index & !1 == tile & !1} else {index == tile};
+
+            if used {
+                usage.sprites.push(sprite);
+            }
+        }
+    }
+
+    usage
+}
+
+#[derive(Clone, Copy)]
+enum ExportTarget {
+    Background(usize),
+    Screen,
+    Tiles
+}
 
 pub struct VramViewerWindow {
     gb_mem: Arc<RwLock<GameboyMemory>>,
     
     tiles: Vec<GameboyTexture>,
     backgrounds: Vec<GameboyTexture>,
-    backgrounds_data: Arc<RwLock<Vec<Vec<u8>>>>
+    backgrounds_data: Arc<RwLock<Vec<Vec<u8>>>>,
+    screen_data: Arc<RwLock<Vec<u8>>>,
+
+    selected_tile: Option<usize>,
+    // Set by select_tile(), brings the tiles tab up and scrolls to the tile on the next draw.
+    focus_selected_tile: bool,
+
+    export_target: Option<ExportTarget>,
+    export_picker: FilePickerWindow,
+    // Set while picking a tile sheet to load into VRAM.
+    import_picker: Option<FilePickerWindow>
 }
 
 impl VramViewerWindow {
     pub fn init(gb: Arc<RwLock<Gameboy>>) -> VramViewerWindow {
         let gb_mem = gb.read().unwrap().ui_get_memory();
 
-        let tiles = vec![GameboyTexture::new(8, 8); 256];
+        let tiles = vec![GameboyTexture::new(8, 8); TILE_COUNT];
         let backgrounds = vec![GameboyTexture::new(256, 256); 2];
         let backgrounds_data = gb.read().unwrap().ui_get_backgrounds_data();
+        let screen_data = gb.read().unwrap().ui_get_screen_data();
 
         VramViewerWindow {
             gb_mem,
 
             tiles,
             backgrounds,
-            backgrounds_data
+            backgrounds_data,
+            screen_data,
+
+            selected_tile: None,
+            focus_selected_tile: false,
+
+            export_target: None,
+            export_picker: FilePickerWindow::init(PathBuf::new()),
+            import_picker: None
+        }
+    }
+
+    /// Highlights the tile the address refers to. It can be part of the tile's data, an entry of a
+    /// tile map (using the addressing mode LCDC selects), or an entry in OAM.
+    /// Returns false if the address doesn't point to any tile.
+    pub fn select_tile(&mut self, address: u16) -> bool {
+        let tile = {
+            if let Ok(lock) = self.gb_mem.read() {
+                match address {
+                    0x8000..=0x97FF => Some((address as usize - 0x8000) / 16),
+                    0x9800..=0x9FFF => Some(map_entry_tile(lock.read(address), lock.read(0xFF40))),
+                    _ if OAM.contains(&address) => {
+                        // Sprites always use $8000 addressing, the index is the third byte of each entry.
+                        let entry = address & !0x03;
+                        Some(lock.read(entry + 2) as usize)
+                    }
+                    _ => None
+                }
+            }
+            else {
+                None
+            }
+        };
+
+        if tile.is_some() {
+            self.selected_tile = tile;
+            self.focus_selected_tile = true;
+        }
+
+        tile.is_some()
+    }
+
+    // Outlines every entry of the map using the selected tile, on top of the background image that was just drawn.
+    fn draw_tile_markers(ui: &Ui, usage: &TileUsage, map: usize, scale: [f32; 2]) {
+        let draw_list = ui.get_window_draw_list();
+        let origin = ui.item_rect_min();
+
+        for (x, y) in usage.maps[map].iter() {
+            let start = [origin[0] + (*x * 8) as f32 * scale[0], origin[1] + (*y * 8) as f32 * scale[1]];
+            let end = [start[0] + 8.0 * scale[0], start[1] + 8.0 * scale[1]];
+
+            draw_list.add_rect(start, end, [1.0, 0.0, 0.0, 1.0]).build();
+        }
+    }
+
+    // The backgrounds only have color indices, so they're shown with the current BG palette.
+    fn apply_bg_palette(&self, background: &[u8]) -> Vec<u8> {
+        let mut palette = utils::Palette::new();
+
+        if let Ok(lock) = self.gb_mem.read() {
+            palette.update(lock.read(0xFF47));
+        }
+
+        background.iter().map(|color_idx| palette.get_color(*color_idx)).collect()
+    }
+
+    fn start_export(&mut self, target: ExportTarget, file_name: &str) {
+        self.export_target = Some(target);
+        self.export_picker = FilePickerWindow::init_save(PathBuf::new(), file_name);
+    }
+
+    fn export(&self, target: ExportTarget, path: &Path) -> Result<(), String> {
+        match target {
+            ExportTarget::Background(idx) => {
+                let backgrounds = self.backgrounds_data.read().map_err(|e| e.to_string())?;
+                save_png(path, 256, 256, &self.apply_bg_palette(&backgrounds[idx]))
+            }
+            ExportTarget::Screen => {
+                let screen = self.screen_data.read().map_err(|e| e.to_string())?;
+                save_png(path, 160, 144, &screen)
+            }
+            ExportTarget::Tiles => {
+                let sheet = self.tile_sheet().ok_or("couldn't read VRAM")?;
+                save_png(path, SHEET_WIDTH as u32, SHEET_HEIGHT as u32, &sheet)
+            }
+        }
+    }
+
+    // Tile sheets use the plain shades from BASE_PALETTE instead of BGP,
+    // so every color index keeps its own shade and importing the sheet gets the same tiles back.
+    fn tile_sheet(&self) -> Option<Vec<u8>> {
+        let lock = self.gb_mem.read().ok()?;
+        let mut sheet = vec![0; SHEET_WIDTH * SHEET_HEIGHT];
+
+        for tile in 0..TILE_COUNT {
+            let address = 0x8000 + tile as u16 * 16;
+            let data: Vec<u8> = (address..address + 16).map(|address| lock.read(address)).collect();
+            let origin = (tile / SHEET_TILES_PER_ROW * 8 * SHEET_WIDTH) + (tile % SHEET_TILES_PER_ROW * 8);
+
+            for (idx, color_idx) in utils::tile_indices(&data).into_iter().enumerate() {
+                sheet[origin + (idx / 8 * SHEET_WIDTH) + idx % 8] = utils::BASE_PALETTE[color_idx as usize];
+            }
         }
+
+        Some(sheet)
+    }
+
+    // Sheets shorter than the full 384 tiles only replace the tiles they have.
+    fn import_tiles(&self, path: &Path) -> Result<usize, String> {
+        let (width, height, shades) = load_png_shades(path)?;
+
+        if width != SHEET_WIDTH || height % 8 != 0 || height > SHEET_HEIGHT {
+            return Err(format!("expected a {}x{} tile sheet, got {}x{}", SHEET_WIDTH, SHEET_HEIGHT, width, height));
+        }
+
+        let tile_count = (height / 8) * SHEET_TILES_PER_ROW;
+        let mut lock = self.gb_mem.write().map_err(|e| e.to_string())?;
+
+        for tile in 0..tile_count {
+            let origin = (tile / SHEET_TILES_PER_ROW * 8 * SHEET_WIDTH) + (tile % SHEET_TILES_PER_ROW * 8);
+            let indices: Vec<u8> = (0..64).map(|idx| utils::closest_color_index(shades[origin + (idx / 8 * SHEET_WIDTH) + idx % 8])).collect();
+            let address = 0x8000 + tile as u16 * 16;
+
+            for (offset, value) in utils::tile_data(&indices).into_iter().enumerate() {
+                lock.dbg_write(address + offset as u16, value);
+            }
+        }
+
+        Ok(tile_count)
     }
 
     pub fn draw(&mut self, ui: &Ui, opened: &mut bool, display: &Display, textures: &mut Textures<Texture>) {
         if !*opened {
             return;
         }
-        
-        ui.window("VRAM Viewer").size([256.0, 256.0], Condition::FirstUseEver).opened(opened).build(|| {
+
+        let focus_selected_tile = self.focus_selected_tile;
+        self.focus_selected_tile = false;
+
+        let selected_usage = self.selected_tile.map(|tile| find_tile_usage(&self.gb_mem, tile));
+
+        ui.window("VRAM Viewer").size([256.0, 256.0], Condition::FirstUseEver).opened(opened).menu_bar(true).focused(focus_selected_tile).build(|| {
+            ui.menu_bar(|| {
+                ui.menu("Export", || {
+                    if ui.menu_item("Background 0 as PNG") {
+                        self.start_export(ExportTarget::Background(0), "background0.png");
+                    }
+
+                    if ui.menu_item("Background 1 as PNG") {
+                        self.start_export(ExportTarget::Background(1), "background1.png");
+                    }
+
+                    if ui.menu_item("Screen as PNG") {
+                        self.start_export(ExportTarget::Screen, "screen.png");
+                    }
+
+                    if ui.menu_item("Tiles as PNG sheet") {
+                        self.start_export(ExportTarget::Tiles, "tiles.png");
+                    }
+                });
+
+                ui.menu("Import", || {
+                    if ui.menu_item("Tiles from PNG sheet") {
+                        self.import_picker = Some(FilePickerWindow::init(PathBuf::new()).with_extensions(&["png"]));
+                    }
+
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Takes a 128x192 sheet like the exported one, shades get matched to the closest of the 4 colors.");
+                    }
+                });
+            });
+
+            if let Some(picker) = self.import_picker.as_mut() {
+                if let Some(path) = picker.draw(ui) {
+                    match self.import_tiles(&path) {
+                        Ok(count) => log::info("VRAM Viewer", &format!("Loaded {} tiles from {}.", count, path.display())),
+                        Err(error) => log::error("VRAM Viewer", &format!("Failed to load {} ({}).", path.display(), error))
+                    }
+
+                    self.import_picker = None;
+                }
+                else if picker.cancelled() {
+                    self.import_picker = None;
+                }
+            }
+
+            if let Some(target) = self.export_target {
+                if let Some(path) = self.export_picker.draw(ui) {
+                    match self.export(target, &path) {
+                        Ok(_) => log::info("VRAM Viewer", &format!("Saved {}.", path.display())),
+                        Err(error) => log::error("VRAM Viewer", &format!("Failed to save {} ({}).", path.display(), error))
+                    }
+
+                    self.export_target = None;
+                }
+                else if self.export_picker.cancelled() {
+                    self.export_target = None;
+                }
+            }
+
             TabBar::new("Viewer Tabs").build(ui, || {
                 TabItem::new("Background 0").build(ui, || {
                     let window_size = ui.content_region_avail();
@@ -50,13 +320,13 @@ impl VramViewerWindow {
                     let y_scale = window_size[1] / 256.0;
 
                     if let Ok(backgrounds) = self.backgrounds_data.try_read() {
-                        let background = &backgrounds[0];
+                        let background = self.apply_bg_palette(&backgrounds[0]);
                         let mut data: Vec<u8> = Vec::with_capacity((256 * 256) * 3);
         
                         for b in background {                        
-                            data.push(*b);
-                            data.push(*b);
-                            data.push(*b);
+                            data.push(b);
+                            data.push(b);
+                            data.push(b);
                         }
         
                         self.backgrounds[0].update_texture(data, display, textures);
@@ -64,6 +334,10 @@ impl VramViewerWindow {
 
                     if let Some(id) = self.backgrounds[0].id().as_ref() {
                         Image::new(*id, [256.0 * x_scale, 256.0 * y_scale]).build(ui);
+
+                        if let Some(usage) = selected_usage.as_ref() {
+                            VramViewerWindow::draw_tile_markers(ui, usage, 0, [x_scale, y_scale]);
+                        }
                     }
                 });
 
@@ -74,13 +348,13 @@ impl VramViewerWindow {
                     let y_scale = window_size[1] / 256.0;
                     
                     if let Ok(backgrounds) = self.backgrounds_data.try_read() {
-                        let background = &backgrounds[1];
+                        let background = self.apply_bg_palette(&backgrounds[1]);
                         let mut data: Vec<u8> = Vec::with_capacity((256 * 256) * 3);
         
                         for b in background {                        
-                            data.push(*b);
-                            data.push(*b);
-                            data.push(*b);
+                            data.push(b);
+                            data.push(b);
+                            data.push(b);
                         }
         
                         self.backgrounds[1].update_texture(data, display, textures);
@@ -88,21 +362,23 @@ impl VramViewerWindow {
 
                     if let Some(id) = self.backgrounds[1].id().as_ref() {
                         Image::new(*id, [256.0 * x_scale, 256.0 * y_scale]).build(ui);
+
+                        if let Some(usage) = selected_usage.as_ref() {
+                            VramViewerWindow::draw_tile_markers(ui, usage, 1, [x_scale, y_scale]);
+                        }
                     }
                 });
 
-                TabItem::new("Tiles").build(ui, || {
+                let tiles_flags = if focus_selected_tile {TabItemFlags::SET_SELECTED} else {TabItemFlags::empty()};
+
+                TabItem::new("Tiles").flags(tiles_flags).build(ui, || {
                     let mut palette = utils::Palette::new();
                     let mut data = Vec::new();
 
                     if let Ok(lock) = self.gb_mem.read() {
                         palette.update(lock.read(0xFF47));
 
-                        for address in 0x8000..0x87FF {
-                            data.push(lock.read(address));
-                        }
-    
-                        for address in 0x8800..0x8FFF {
+                        for address in 0x8000..0x9800 {
                             data.push(lock.read(address));
                         }
                     }
@@ -127,17 +403,45 @@ impl VramViewerWindow {
                         if let Some(id) = tex.id().as_ref() {
                             Image::new(*id, [8.0 * 3.0, 8.0 * 3.0]).build(ui);
 
+                            if self.selected_tile == Some(idx) {
+                                let draw_list = ui.get_window_draw_list();
+                                draw_list.add_rect(ui.item_rect_min(), ui.item_rect_max(), [1.0, 0.0, 0.0, 1.0]).thickness(2.0).build();
+
+                                if focus_selected_tile {
+                                    ui.set_scroll_here_y();
+                                }
+                            }
+
                             if ui.is_item_hovered() {
+                                let usage = find_tile_usage(&self.gb_mem, idx);
+
                                 ui.tooltip(|| {
-                                    ui.text(format!("Tile ID: ${:02X}", idx));
+                                    // Tiles past $9000 are only reachable with signed indexes.
+                                    ui.text(format!("Tile ID: ${:02X}", idx as u8));
                                     ui.text(format!("Tile Address: ${:04X}", tile_addr));
+                                    ui.text(format!("Used in map $9800: {} times", usage.maps[0].len()));
+                                    ui.text(format!("Used in map $9C00: {} times", usage.maps[1].len()));
+
+                                    if usage.sprites.is_empty() {
+                                        ui.text("Used by sprites: none");
+                                    }
+                                    else {
+                                        let sprites: Vec<String> = usage.sprites.iter().map(|s| s.to_string()).collect();
+                                        ui.text(format!("Used by sprites: {}", sprites.join(", ")));
+                                    }
+
+                                    ui.text_disabled("Click to outline it on the background tabs.");
                                 });
                             }
 
+                            if ui.is_item_clicked() {
+                                self.selected_tile = Some(idx);
+                            }
+
                             tile_addr += 16;
                         }
 
-                        if tile_addr == 0x8800 {
+                        if tile_addr == 0x8800 || tile_addr == 0x9000 {
                             ui.spacing();
                             same_line_offset = 0.0;
                         }
@@ -152,5 +456,51 @@ impl VramViewerWindow {
                 });
             });
         });
+
     }
 }
+
+// Loads any PNG as one shade per pixel, alpha is ignored.
+fn load_png_shades(path: &Path) -> Result<(usize, usize, Vec<u8>), String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+
+    // Palettes and bit depths under 8 get expanded, so only 8 and 16 bit samples are left.
+    decoder.set_transformations(png::Transformations::EXPAND);
+
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+
+    let samples = info.color_type.samples();
+    let sample_size = if info.bit_depth == png::BitDepth::Sixteen {2} else {1};
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut shades = Vec::with_capacity(width * height);
+
+    for line in data.chunks_exact(info.line_size).take(height) {
+        for pixel in line.chunks_exact(samples * sample_size).take(width) {
+            // The high byte comes first on 16 bit samples.
+            let sample = |idx: usize| pixel[idx * sample_size] as u32;
+
+            let shade = match info.color_type {
+                png::ColorType::Rgb | png::ColorType::Rgba => (sample(0) * 299 + sample(1) * 587 + sample(2) * 114) / 1000,
+                _ => sample(0)
+            };
+
+            shades.push(shade as u8);
+        }
+    }
+
+    Ok((width, height, shades))
+}
+
+fn save_png(path: &Path, width: u32, height: u32, data: &[u8]) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(data).map_err(|e| e.to_string())
+}
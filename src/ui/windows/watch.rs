@@ -0,0 +1,215 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::{Arc, RwLock};
+
+use imgui::*;
+
+use crate::gameboy::Gameboy;
+use crate::gameboy::memory::GameboyMemory;
+
+pub struct WatchWindow {
+    gb: Arc<RwLock<Gameboy>>,
+    gb_mem: Arc<RwLock<GameboyMemory>>,
+
+    new_expression: String,
+    expressions: Vec<String>
+}
+
+impl WatchWindow {
+    pub fn init(gb: Arc<RwLock<Gameboy>>) -> WatchWindow {
+        let gb_mem = gb.read().unwrap().ui_get_memory();
+
+        WatchWindow {
+            gb,
+            gb_mem,
+
+            new_expression: String::new(),
+            expressions: Vec::new()
+        }
+    }
+
+    pub fn draw(&mut self, ui: &Ui, opened: &mut bool) {
+        if !*opened {
+            return;
+        }
+
+        ui.window("Watch").size([300.0, 200.0], Condition::FirstUseEver).opened(opened).build(|| {
+            let registers = {
+                if let Ok(lock) = self.gb.read() {
+                    lock.ui_get_cpu_registers()
+                }
+                else {
+                    (0, 0, 0, 0, 0, 0)
+                }
+            };
+
+            let mut remove = None;
+
+            for (idx, expression) in self.expressions.iter().enumerate() {
+                let token = ui.push_id(&format!("watch{}", idx));
+
+                if ui.small_button("X") {
+                    remove = Some(idx);
+                }
+
+                ui.same_line();
+
+                let result = {
+                    if let Ok(lock) = self.gb_mem.read() {
+                        evaluate(expression, registers, &lock)
+                    }
+                    else {
+                        Err(String::from("memory unavailable"))
+                    }
+                };
+
+                match result {
+                    Ok(Value::Byte(value)) => ui.text(format!("{} = ${:02X} ({})", expression, value, value)),
+                    Ok(Value::Word(value)) => ui.text(format!("{} = ${:04X} ({})", expression, value, value)),
+                    Err(error) => ui.text_colored([1.0, 0.0, 0.0, 1.0], format!("{}: {}", expression, error))
+                }
+
+                token.pop();
+            }
+
+            if let Some(idx) = remove {
+                self.expressions.remove(idx);
+            }
+
+            ui.separator();
+
+            let entered = ui.input_text("##new_watch", &mut self.new_expression).enter_returns_true(true).build();
+
+            ui.same_line();
+
+            if (ui.button("Add") || entered) && !self.new_expression.trim().is_empty() {
+                self.expressions.push(self.new_expression.trim().to_uppercase());
+                self.new_expression.clear();
+            }
+
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Registers (A, HL, SP...), hex numbers ($C000), [address] reads a byte, + and - offset.");
+            }
+        });
+    }
+}
+
+enum Value {
+    Byte(u8),
+    Word(u16)
+}
+
+impl Value {
+    fn get(&self) -> u16 {
+        match self {
+            Value::Byte(value) => *value as u16,
+            Value::Word(value) => *value
+        }
+    }
+}
+
+// Grammar: expr = term (('+' | '-') term)*
+//          term = register | number | '[' expr ']'
+fn evaluate(expression: &str, registers: (u16, u16, u16, u16, u16, u16), gb_mem: &GameboyMemory) -> Result<Value, String> {
+    let mut chars = expression.chars().peekable();
+    let value = parse_expr(&mut chars, registers, gb_mem)?;
+
+    skip_spaces(&mut chars);
+
+    if let Some(c) = chars.next() {
+        return Err(format!("unexpected '{}'", c));
+    }
+
+    Ok(value)
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+        chars.next();
+    }
+}
+
+fn parse_expr(chars: &mut Peekable<Chars>, registers: (u16, u16, u16, u16, u16, u16), gb_mem: &GameboyMemory) -> Result<Value, String> {
+    let mut value = parse_term(chars, registers, gb_mem)?;
+
+    loop {
+        skip_spaces(chars);
+
+        match chars.peek() {
+            Some('+') => {
+                chars.next();
+                let rhs = parse_term(chars, registers, gb_mem)?;
+                value = Value::Word(value.get().wrapping_add(rhs.get()));
+            }
+            Some('-') => {
+                chars.next();
+                let rhs = parse_term(chars, registers, gb_mem)?;
+                value = Value::Word(value.get().wrapping_sub(rhs.get()));
+            }
+            _ => return Ok(value)
+        }
+    }
+}
+
+fn parse_term(chars: &mut Peekable<Chars>, registers: (u16, u16, u16, u16, u16, u16), gb_mem: &GameboyMemory) -> Result<Value, String> {
+    skip_spaces(chars);
+
+    if chars.peek() == Some(&'[') {
+        chars.next();
+
+        let address = parse_expr(chars, registers, gb_mem)?;
+        skip_spaces(chars);
+
+        if chars.next() != Some(']') {
+            return Err(String::from("missing ']'"));
+        }
+
+        return Ok(Value::Byte(gb_mem.read(address.get())));
+    }
+
+    let mut token = String::new();
+
+    while let Some(c) = chars.peek() {
+        if c.is_ascii_alphanumeric() || *c == '$' {
+            token.push(*c);
+            chars.next();
+        }
+        else {
+            break;
+        }
+    }
+
+    if token.is_empty() {
+        return Err(String::from("expected a value"));
+    }
+
+    let (af, bc, de, hl, sp, pc) = registers;
+
+    // Register names win over hex numbers, use $ to force a number (like $C).
+    let value = match token.as_str() {
+        "A" => Value::Byte((af >> 8) as u8),
+        "F" => Value::Byte(af as u8),
+        "B" => Value::Byte((bc >> 8) as u8),
+        "C" => Value::Byte(bc as u8),
+        "D" => Value::Byte((de >> 8) as u8),
+        "E" => Value::Byte(de as u8),
+        "H" => Value::Byte((hl >> 8) as u8),
+        "L" => Value::Byte(hl as u8),
+        "AF" => Value::Word(af),
+        "BC" => Value::Word(bc),
+        "DE" => Value::Word(de),
+        "HL" => Value::Word(hl),
+        "SP" => Value::Word(sp),
+        "PC" => Value::Word(pc),
+        _ => {
+            let digits = token.trim_start_matches('$').trim_start_matches("0X");
+
+            match u16::from_str_radix(digits, 16) {
+                Ok(value) => Value::Word(value),
+                Err(_) => return Err(format!("unknown value '{}'", token))
+            }
+        }
+    };
+
+    Ok(value)
+}